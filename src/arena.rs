@@ -0,0 +1,77 @@
+//! Which arena is being played, and what's in it beyond the floor and the platform.
+//!
+//! `--arena <name>` picks `assets/arenas/<name>.ron`, and `default` is played without it. Anything
+//! the file leaves out is left empty or at its default, and a missing or broken file is an empty
+//! arena.
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::hazard::HazardConfig;
+use crate::lighting::LightingConfig;
+
+const DEFAULT_ARENA: &str = "default";
+const DEFAULT_LAUNCHER_LEAD_SECS: f32 = 1.0;
+
+pub struct ArenaPlugin;
+
+impl Plugin for ArenaPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Arena::load(&Arena::name_from_args()));
+    }
+}
+
+/// The arena being played.
+#[derive(Resource, Debug)]
+pub struct Arena {
+    pub name: String,
+    pub config: ArenaConfig,
+}
+
+/// One arena's file.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct ArenaConfig {
+    pub hazards: Vec<HazardConfig>,
+    /// How long launchers wind up before firing, unless a launcher says otherwise
+    pub launcher_lead_secs: f32,
+    /// Played in the dark, by flashlight
+    pub night: bool,
+    pub lighting: LightingConfig,
+}
+
+impl Default for ArenaConfig {
+    fn default() -> Self {
+        Self {
+            hazards: Vec::new(),
+            launcher_lead_secs: DEFAULT_LAUNCHER_LEAD_SECS,
+            night: false,
+            lighting: LightingConfig::default(),
+        }
+    }
+}
+
+impl Arena {
+    fn name_from_args() -> String {
+        let mut args = std::env::args().skip_while(|arg| arg != "--arena").skip(1);
+        args.next().unwrap_or_else(|| DEFAULT_ARENA.to_string())
+    }
+
+    pub fn load(name: &str) -> Self {
+        let path = format!("assets/arenas/{}.ron", name);
+        let config = match std::fs::read_to_string(&path) {
+            Ok(text) => ron::from_str(&text).unwrap_or_else(|error| {
+                println!("Ignoring {}: {}", path, error);
+                ArenaConfig::default()
+            }),
+            Err(error) => {
+                println!("Couldn't read {}: {}", path, error);
+                ArenaConfig::default()
+            }
+        };
+        Self {
+            name: name.to_string(),
+            config,
+        }
+    }
+}
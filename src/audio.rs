@@ -0,0 +1,435 @@
+//! Sound effects and music.
+//!
+//! There are no sound files, every sound is synthesized into a WAV when the game starts.
+//! Ball impacts come from rapier contact force events, louder and higher the faster the ball was
+//! going. Launchers whine as they wind up, stretched to last their lead time. Effects are spatial,
+//! heard from the first local player's camera.
+//!
+//! The music is a loop in layers. Each round of a match brings another layer in; outside a match
+//! it builds with the number of balls in play.
+
+use bevy::audio::{DefaultSpatialScale, SpatialScale, Volume};
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use bevy_rapier3d::prelude::*;
+use rand::prelude::*;
+use std::f32::consts::{PI, TAU};
+
+use crate::ball::{Ball, BallKind, ThrownBy};
+use crate::bot::Headless;
+use crate::camera::PlayerCamera;
+use crate::dodgeball::Match;
+use crate::player::{Grounded, Player};
+use crate::settings::Settings;
+use crate::telegraph::Telegraph;
+
+const SAMPLE_RATE: u32 = 22_050;
+// Rodio's falloff is steep, so distances are shrunk before it sees them
+const SPATIAL_SCALE: f32 = 0.25;
+const EAR_GAP: f32 = 0.3;
+
+// Impacts slower than this don't make a sound
+const MIN_IMPACT_SPEED: f32 = 2.0;
+// Impacts at this speed are as loud as they get
+const LOUD_IMPACT_SPEED: f32 = 25.0;
+// A rolling ball keeps reporting contact forces, so each ball sounds at most this often
+const IMPACT_COOLDOWN_SECS: f32 = 0.15;
+
+const STRIDE_LENGTH: f32 = 1.1;
+const MIN_FOOTSTEP_SPEED: f32 = 0.5;
+
+// A fast ball passing this close to someone, without hitting them, whooshes
+const NEAR_MISS_RADIUS: f32 = 1.2;
+const NEAR_MISS_MIN_DISTANCE: f32 = 0.6;
+const NEAR_MISS_SPEED: f32 = 12.0;
+
+// The launcher whine is rendered this long and sped up or slowed down to fit a launcher's lead time
+const CHARGE_SECS: f32 = 1.0;
+const MIN_CHARGE_SPEED: f32 = 0.5;
+const MAX_CHARGE_SPEED: f32 = 4.0;
+
+const MUSIC_VOLUME: f32 = 0.4;
+const MUSIC_BEAT_SECS: f32 = 0.5;
+const MUSIC_LOOP_BEATS: usize = 8;
+// How fast a music layer fades, in volume per second
+const MUSIC_FADE_SPEED: f32 = 0.5;
+// Outside a match, this many balls in play counts as another wave
+const BALLS_PER_WAVE: f32 = 4.0;
+
+pub struct SoundPlugin;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(DefaultSpatialScale(SpatialScale::new(SPATIAL_SCALE)))
+            .add_systems(
+                Startup,
+                (setup_sounds, start_music)
+                    .chain()
+                    .run_if(not(resource_exists::<Headless>)),
+            )
+            .add_systems(
+                Update,
+                (
+                    add_listener,
+                    play_impact_sounds,
+                    play_footsteps,
+                    play_near_misses,
+                    play_launch_warnings,
+                    intensify_music,
+                )
+                    .run_if(resource_exists::<SoundAssets>),
+            );
+    }
+}
+
+/// Every synthesized sound effect.
+#[derive(Resource)]
+pub struct SoundAssets {
+    pub bounce: Handle<AudioSource>,
+    pub hit: Handle<AudioSource>,
+    pub footstep: Handle<AudioSource>,
+    pub whoosh: Handle<AudioSource>,
+    pub charge: Handle<AudioSource>,
+}
+
+/// One layer of the music. Layer n comes in at wave n + 1.
+#[derive(Component)]
+struct MusicLayer {
+    index: usize,
+    volume: f32,
+}
+
+// 16 bit mono PCM, wrapped up as a WAV file for rodio to decode
+fn wav(samples: &[f32]) -> AudioSource {
+    let data_len = (samples.len() * 2) as u32;
+    let mut bytes = Vec::with_capacity(44 + data_len as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, one channel
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    bytes.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    bytes.extend_from_slice(&2u16.to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+    AudioSource {
+        bytes: bytes.into(),
+    }
+}
+
+// Runs `sample` on the time of every sample in `secs` of sound
+fn render(secs: f32, sample: impl FnMut(f32) -> f32) -> AudioSource {
+    let count = (secs * SAMPLE_RATE as f32) as usize;
+    let samples: Vec<f32> = (0..count)
+        .map(|i| i as f32 / SAMPLE_RATE as f32)
+        .map(sample)
+        .collect();
+    wav(&samples)
+}
+
+// Phase of a tone that slides exponentially from `start` Hz down to `end` Hz
+fn sweep_phase(t: f32, start: f32, end: f32, rate: f32) -> f32 {
+    TAU * (end * t + (start - end) * (1.0 - (-rate * t).exp()) / rate)
+}
+
+fn setup_sounds(mut commands: Commands, mut audio_sources: ResMut<Assets<AudioSource>>) {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut noise = move || rng.gen_range(-1.0..1.0);
+
+    // A dull thump with a click on the front
+    let bounce = render(0.25, |t| {
+        let thump = (TAU * 160.0 * t).sin() * 0.8;
+        let click = noise() * 0.2 * (-t * 80.0).exp();
+        (thump + click) * (-t * 25.0).exp()
+    });
+    // Punchier, a falling tone and a burst of noise
+    let hit = render(0.35, |t| {
+        let tone = sweep_phase(t, 220.0, 90.0, 20.0).sin() * (-t * 12.0).exp();
+        let burst = noise() * 0.5 * (-t * 40.0).exp();
+        (tone + burst) * 0.9
+    });
+    // Muffled noise, a scuff on the floor
+    let mut low = 0.0;
+    let footstep = render(0.12, |t| {
+        low += (noise() - low) * 0.15;
+        low * 2.0 * (-t * 40.0).exp() * (1.0 - (-t * 600.0).exp())
+    });
+    // Noise that swells and opens up as it passes
+    let whoosh_secs = 0.45;
+    let mut low = 0.0;
+    let whoosh = render(whoosh_secs, |t| {
+        let passing = (PI * t / whoosh_secs).sin();
+        low += (noise() - low) * (0.02 + 0.18 * passing);
+        low * 2.5 * passing * passing
+    });
+
+    // A whine rising to a peak, with a tremolo that speeds up along with it
+    let charge = render(CHARGE_SECS, |t| {
+        let rise = t / CHARGE_SECS;
+        // Sliding straight from 300 Hz up to 900 Hz
+        let tone = (TAU * (300.0 * t + 300.0 * t * rise)).sin();
+        let tremolo = 0.7 + 0.3 * (TAU * (6.0 + 18.0 * rise) * t).sin();
+        tone * tremolo * 0.5 * rise.sqrt()
+    });
+
+    commands.insert_resource(SoundAssets {
+        bounce: audio_sources.add(bounce),
+        hit: audio_sources.add(hit),
+        footstep: audio_sources.add(footstep),
+        whoosh: audio_sources.add(whoosh),
+        charge: audio_sources.add(charge),
+    });
+}
+
+// Two bars in A minor. Frequencies are whole numbers of cycles per loop so it loops without a click.
+fn music_layers() -> [AudioSource; 4] {
+    let loop_secs = MUSIC_BEAT_SECS * MUSIC_LOOP_BEATS as f32;
+    let mut rng = StdRng::seed_from_u64(1);
+
+    let pad = render(loop_secs, |t| {
+        let swell = 0.8 + 0.2 * (TAU * t / loop_secs).sin();
+        [110.0, 132.0, 165.0]
+            .iter()
+            .map(|frequency| (TAU * frequency * t).sin())
+            .sum::<f32>()
+            * 0.15
+            * swell
+    });
+    let kick = render(loop_secs, |t| {
+        let beat_t = t % MUSIC_BEAT_SECS;
+        sweep_phase(beat_t, 100.0, 40.0, 25.0).sin() * (-beat_t * 12.0).exp() * 0.7
+    });
+    let mut low = 0.0;
+    let hats = render(loop_secs, |t| {
+        let offbeat_t = (t + MUSIC_BEAT_SECS / 2.0) % MUSIC_BEAT_SECS;
+        let white = rng.gen_range(-1.0..1.0);
+        low += (white - low) * 0.3;
+        // What's left once the lows are taken out
+        (white - low) * (-offbeat_t * 60.0).exp() * 0.3
+    });
+    let arp = render(loop_secs, |t| {
+        let step_secs = MUSIC_BEAT_SECS / 4.0;
+        let notes = [220.0, 264.0, 330.0, 396.0];
+        let frequency = notes[(t / step_secs) as usize % notes.len()];
+        let step_t = t % step_secs;
+        let tone = (TAU * frequency * t).sin() + 0.3 * (TAU * 2.0 * frequency * t).sin();
+        tone * (-step_t * 18.0).exp() * 0.2
+    });
+    [pad, kick, hats, arp]
+}
+
+fn start_music(mut commands: Commands, mut audio_sources: ResMut<Assets<AudioSource>>) {
+    for (index, layer) in music_layers().into_iter().enumerate() {
+        commands.spawn((
+            AudioBundle {
+                source: audio_sources.add(layer),
+                settings: PlaybackSettings::LOOP.with_volume(Volume::new(0.0)),
+            },
+            MusicLayer { index, volume: 0.0 },
+            Name::new(format!("Music layer {}", index + 1)),
+        ));
+    }
+}
+
+// Bevy only mixes for one listener, so split screen hears from the first camera
+fn add_listener(
+    mut commands: Commands,
+    camera_q: Query<Entity, (Added<PlayerCamera>, With<Camera>)>,
+    listener_q: Query<(), With<SpatialListener>>,
+) {
+    if !listener_q.is_empty() {
+        return;
+    }
+    if let Some(camera) = camera_q.iter().next() {
+        commands
+            .entity(camera)
+            .insert(SpatialListener::new(EAR_GAP));
+    }
+}
+
+fn play_sound(
+    commands: &mut Commands,
+    sound: &Handle<AudioSource>,
+    position: Vec3,
+    volume: f32,
+    pitch: f32,
+) {
+    commands.spawn((
+        AudioBundle {
+            source: sound.clone(),
+            settings: PlaybackSettings::DESPAWN
+                .with_volume(Volume::new(volume))
+                .with_speed(pitch)
+                .with_spatial(true),
+        },
+        TransformBundle::from_transform(Transform::from_translation(position)),
+    ));
+}
+
+fn play_impact_sounds(
+    mut commands: Commands,
+    time: Res<Time>,
+    sounds: Res<SoundAssets>,
+    mut contact_force_events: EventReader<ContactForceEvent>,
+    mut last_played: Local<HashMap<Entity, f32>>,
+    ball_q: Query<(&Transform, &Velocity, &BallKind), With<Ball>>,
+    velocity_q: Query<&Velocity>,
+    player_q: Query<(), With<Player>>,
+) {
+    let now = time.elapsed_seconds();
+    for event in contact_force_events.read() {
+        let (ball, other) = match (
+            ball_q.contains(event.collider1),
+            ball_q.contains(event.collider2),
+        ) {
+            (true, _) => (event.collider1, event.collider2),
+            (_, true) => (event.collider2, event.collider1),
+            _ => continue,
+        };
+        if last_played
+            .get(&ball)
+            .is_some_and(|&played| now - played < IMPACT_COOLDOWN_SECS)
+        {
+            continue;
+        }
+        let (transform, velocity, kind) = ball_q.get(ball).unwrap();
+        let other_linvel = velocity_q
+            .get(other)
+            .map_or(Vec3::ZERO, |velocity| velocity.linvel);
+        let speed = (velocity.linvel - other_linvel).length();
+        if speed < MIN_IMPACT_SPEED {
+            continue;
+        }
+        last_played.insert(ball, now);
+
+        let loudness = (speed / LOUD_IMPACT_SPEED).clamp(0.05, 1.0);
+        let mut pitch = 0.8 + 0.6 * loudness;
+        if *kind == BallKind::Heavy {
+            pitch *= 0.7;
+        }
+        let sound = if player_q.contains(other) {
+            &sounds.hit
+        } else {
+            &sounds.bounce
+        };
+        play_sound(&mut commands, sound, transform.translation, loudness, pitch);
+    }
+    // Forget balls that have been quiet a while
+    last_played.retain(|_, played| now - *played < IMPACT_COOLDOWN_SECS);
+}
+
+fn play_footsteps(
+    mut commands: Commands,
+    time: Res<Time>,
+    sounds: Res<SoundAssets>,
+    mut walked: Local<HashMap<Entity, f32>>,
+    player_q: Query<(Entity, &Transform, &Velocity, &Grounded), With<Player>>,
+) {
+    let mut rng = thread_rng();
+    for (player, transform, velocity, grounded) in player_q.iter() {
+        let speed = velocity.linvel.with_y(0.0).length();
+        let distance = walked.entry(player).or_default();
+        if grounded.count == 0 || speed < MIN_FOOTSTEP_SPEED {
+            // The first step after stopping comes straight away
+            *distance = STRIDE_LENGTH;
+            continue;
+        }
+        *distance += speed * time.delta_seconds();
+        if *distance >= STRIDE_LENGTH {
+            *distance = 0.0;
+            let feet = transform.translation - Vec3::Y * 0.8;
+            let pitch = rng.gen_range(0.9..1.1);
+            play_sound(&mut commands, &sounds.footstep, feet, 0.35, pitch);
+        }
+    }
+}
+
+fn play_near_misses(
+    mut commands: Commands,
+    sounds: Res<SoundAssets>,
+    mut passing: Local<HashSet<(Entity, Entity)>>,
+    ball_q: Query<
+        (Entity, &Transform, &Velocity, Option<&ThrownBy>),
+        (With<Ball>, Without<RigidBodyDisabled>),
+    >,
+    player_q: Query<(Entity, &Transform), With<Player>>,
+) {
+    let mut still_passing = HashSet::new();
+    for (ball, ball_transform, velocity, thrown_by) in ball_q.iter() {
+        let speed = velocity.linvel.length();
+        if speed < NEAR_MISS_SPEED {
+            continue;
+        }
+        for (player, player_transform) in player_q.iter() {
+            if thrown_by.is_some_and(|thrown_by| thrown_by.player == player) {
+                continue;
+            }
+            let distance = ball_transform
+                .translation
+                .distance(player_transform.translation);
+            if distance > NEAR_MISS_RADIUS {
+                continue;
+            }
+            still_passing.insert((ball, player));
+            if distance > NEAR_MISS_MIN_DISTANCE && !passing.contains(&(ball, player)) {
+                let pitch = (speed / NEAR_MISS_SPEED).clamp(1.0, 1.5);
+                play_sound(
+                    &mut commands,
+                    &sounds.whoosh,
+                    ball_transform.translation,
+                    0.6,
+                    pitch,
+                );
+            }
+        }
+    }
+    *passing = still_passing;
+}
+
+fn play_launch_warnings(
+    mut commands: Commands,
+    sounds: Res<SoundAssets>,
+    mut winding: Local<HashSet<Entity>>,
+    launcher_q: Query<(Entity, &Telegraph)>,
+) {
+    let mut still_winding = HashSet::new();
+    for (launcher, telegraph) in launcher_q.iter() {
+        if telegraph.remaining_secs.is_none() {
+            continue;
+        }
+        still_winding.insert(launcher);
+        if winding.contains(&launcher) || telegraph.lead_secs <= 0.0 {
+            continue;
+        }
+        let speed = (CHARGE_SECS / telegraph.lead_secs).clamp(MIN_CHARGE_SPEED, MAX_CHARGE_SPEED);
+        play_sound(&mut commands, &sounds.charge, telegraph.muzzle, 0.5, speed);
+    }
+    *winding = still_winding;
+}
+
+fn intensify_music(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    game: Option<Res<Match>>,
+    ball_q: Query<(), (With<Ball>, Without<RigidBodyDisabled>)>,
+    mut layer_q: Query<(&mut MusicLayer, &AudioSink)>,
+) {
+    let wave = match game {
+        Some(game) => game.round as f32,
+        None => 1.0 + ball_q.iter().count() as f32 / BALLS_PER_WAVE,
+    };
+    for (mut layer, sink) in layer_q.iter_mut() {
+        let target = (wave - layer.index as f32).clamp(0.0, 1.0);
+        let step = MUSIC_FADE_SPEED * time.delta_seconds();
+        layer.volume += (target - layer.volume).clamp(-step, step);
+        sink.set_volume(layer.volume * MUSIC_VOLUME * settings.volume);
+    }
+}
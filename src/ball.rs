@@ -0,0 +1,668 @@
+use crate::arena::Arena;
+use crate::particles::{Effect, SpawnEffect};
+use crate::world::SimulationSet;
+use bevy::ecs::schedule::SystemConfigs;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::geometry::CollisionEventFlags;
+use rand::prelude::*;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// How many balls are pre-allocated when the game starts.
+/// The pool grows on demand if a wave asks for more than this.
+const BALL_POOL_SIZE: usize = 64;
+const BALL_LIFETIME_SECS: u64 = 5;
+pub(crate) const LAUNCH_POSITION: Vec3 = Vec3::new(-4.0, 1.0, 0.0);
+pub(crate) const LAUNCH_VELOCITY: Vec3 = Vec3::new(30.0, 1.0, 0.0);
+
+// Parked balls sit far below the arena so nothing ever sees them
+const BALL_PARK_TRANSLATION: Vec3 = Vec3::new(0.0, -1000.0, 0.0);
+
+// Lift per unit mass is MAGNUS_COEFFICIENT * (angvel x linvel)
+const MAGNUS_COEFFICIENT: f32 = 0.02;
+const SPLITTER_SHARDS: usize = 3;
+const BALL_TRAIL_LENGTH: usize = 24;
+// Contacts pushing harder than this report a force event, which is what impact sounds listen for.
+// Well over the weight of a ball sat on the ground.
+const IMPACT_FORCE_THRESHOLD: f32 = 2_000.0;
+
+pub struct BallPlugin;
+
+impl Plugin for BallPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Startup,
+            (
+                setup_ball_spawning,
+                (setup_ball_assets, setup_ball_pool).chain(),
+            ),
+        )
+        .init_resource::<BallTrailSettings>()
+        .init_resource::<BallRng>()
+        .add_systems(Update, simulation_systems().in_set(SimulationSet))
+        .add_systems(
+            Update,
+            (
+                toggle_ball_trails,
+                (record_ball_trails, draw_ball_trails)
+                    .chain()
+                    .run_if(|settings: Res<BallTrailSettings>| settings.enabled),
+            ),
+        );
+    }
+}
+
+/// Ball systems that move the game on. A rollback session steps these itself.
+pub(crate) fn simulation_systems() -> SystemConfigs {
+    (spawn_ball, despawn_ball, apply_magnus_effect, split_balls).chain()
+}
+
+#[derive(Component)]
+pub struct Ball;
+
+/// What sort of ball this is. Every kind looks and behaves differently so it can be read at a glance.
+#[derive(Component, Reflect, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum BallKind {
+    #[default]
+    Standard,
+    /// Slow and massive, shoves whatever it hits a long way
+    Heavy,
+    /// Keeps almost all of its energy on every bounce
+    Bouncy,
+    /// Launched with lots of spin so it bends through the air
+    Curve,
+    /// Breaks into shards the first time it touches anything
+    Splitter,
+    /// What a splitter breaks into
+    Shard,
+}
+
+impl BallKind {
+    /// The kinds a launcher can fire, with their relative weights.
+    pub const LAUNCHABLE: [(BallKind, f32); 5] = [
+        (BallKind::Standard, 4.0),
+        (BallKind::Heavy, 1.0),
+        (BallKind::Bouncy, 1.5),
+        (BallKind::Curve, 1.5),
+        (BallKind::Splitter, 1.0),
+    ];
+
+    pub fn radius(self) -> f32 {
+        match self {
+            BallKind::Heavy => 0.7,
+            BallKind::Shard => 0.25,
+            _ => 0.5,
+        }
+    }
+
+    /// Mass added on top of what the collider's density gives
+    pub fn additional_mass(self) -> f32 {
+        match self {
+            BallKind::Heavy => 4000.0,
+            BallKind::Shard => 100.0,
+            _ => 1000.2,
+        }
+    }
+
+    pub fn restitution(self) -> Restitution {
+        match self {
+            BallKind::Heavy => Restitution::coefficient(0.3),
+            // Max so the floor's zero restitution doesn't average the bounce away
+            BallKind::Bouncy => Restitution {
+                coefficient: 1.0,
+                combine_rule: CoefficientCombineRule::Max,
+            },
+            _ => Restitution::coefficient(0.9),
+        }
+    }
+
+    /// Multiplier on the launcher's velocity
+    pub fn speed_scale(self) -> f32 {
+        match self {
+            BallKind::Heavy => 0.5,
+            BallKind::Curve => 0.75,
+            _ => 1.0,
+        }
+    }
+
+    pub fn launch_angvel(self) -> Vec3 {
+        match self {
+            BallKind::Curve => Vec3::new(0.0, 12.0, 0.0),
+            _ => Vec3::new(0.2, 0.4, 0.8),
+        }
+    }
+
+    pub(crate) fn color(self) -> &'static str {
+        match self {
+            BallKind::Standard => "#d62828",
+            BallKind::Heavy => "#3d3d3d",
+            BallKind::Bouncy => "#f77f00",
+            BallKind::Curve => "#9b5de5",
+            BallKind::Splitter => "#00bbf9",
+            BallKind::Shard => "#9be7ff",
+        }
+    }
+}
+
+#[derive(Component, Reflect, Clone, Debug)]
+pub struct BallLifetime {
+    /// track when the ball should return to the pool (non-repeating timer)
+    pub timer: Timer,
+}
+
+impl Default for BallLifetime {
+    fn default() -> Self {
+        Self {
+            timer: Timer::new(Duration::from_secs(BALL_LIFETIME_SECS), TimerMode::Once),
+        }
+    }
+}
+
+/// The player who last threw this ball. Gone once the ball is parked.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct ThrownBy {
+    pub player: Entity,
+}
+
+#[derive(Resource, Clone)]
+pub struct BallSpawnConfig {
+    /// How often to spawn a new ball? (repeating timer)
+    pub timer: Timer,
+    /// How long before firing the launcher warns that it's about to
+    pub lead_secs: f32,
+    /// What it fires next, picked once the warning starts
+    pub next_kind: Option<BallKind>,
+}
+
+/// Recent positions of a ball, newest last.
+#[derive(Component, Default)]
+pub struct BallTrail {
+    pub points: VecDeque<Vec3>,
+}
+
+/// Whether balls draw a trail of where they've just been. Toggled with T.
+#[derive(Resource)]
+pub struct BallTrailSettings {
+    pub enabled: bool,
+}
+
+impl Default for BallTrailSettings {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+/// Balls that are parked and ready to be launched again.
+/// Parked balls keep their rapier bodies but carry `RigidBodyDisabled` and `ColliderDisabled`.
+#[derive(Resource, Default, Clone)]
+pub struct BallPool {
+    pub free: Vec<Entity>,
+}
+
+/// Picks which kind of ball launchers fire. Seeded the same on every peer in a rollback session.
+#[derive(Resource, Clone)]
+pub struct BallRng(pub StdRng);
+
+impl Default for BallRng {
+    fn default() -> Self {
+        Self(StdRng::from_entropy())
+    }
+}
+
+/// Mesh and material for each `BallKind`, shared by every ball of that kind.
+#[derive(Resource)]
+pub struct BallAssets {
+    looks: HashMap<BallKind, (Handle<Mesh>, Handle<StandardMaterial>)>,
+}
+
+impl BallAssets {
+    pub fn look(&self, kind: BallKind) -> (Handle<Mesh>, Handle<StandardMaterial>) {
+        self.looks[&kind].clone()
+    }
+}
+
+fn setup_ball_spawning(mut commands: Commands, arena: Res<Arena>) {
+    commands.insert_resource(BallSpawnConfig {
+        // create the repeating timer
+        timer: Timer::new(Duration::from_secs(3), TimerMode::Repeating),
+        lead_secs: arena.config.launcher_lead_secs,
+        next_kind: None,
+    })
+}
+
+fn setup_ball_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let looks = [
+        BallKind::Standard,
+        BallKind::Heavy,
+        BallKind::Bouncy,
+        BallKind::Curve,
+        BallKind::Splitter,
+        BallKind::Shard,
+    ]
+    .into_iter()
+    .map(|kind| {
+        let mesh = meshes.add(Sphere::new(kind.radius()).mesh().uv(32, 18));
+        let material = materials.add(StandardMaterial {
+            base_color: Srgba::hex(kind.color()).unwrap().into(),
+            metallic: 0.1,
+            perceptual_roughness: 0.6,
+            ..default()
+        });
+        (kind, (mesh, material))
+    })
+    .collect();
+    commands.insert_resource(BallAssets { looks });
+}
+
+fn setup_ball_pool(mut commands: Commands, ball_assets: Res<BallAssets>) {
+    let mut pool = BallPool::default();
+    for _ in 0..BALL_POOL_SIZE {
+        let ball = spawn_parked_ball(&mut commands, &ball_assets);
+        pool.free.push(ball);
+    }
+    commands.insert_resource(pool);
+}
+
+/// Spawns a ball with every component it will ever need, disabled, hidden and out of the way.
+fn spawn_parked_ball(commands: &mut Commands, ball_assets: &BallAssets) -> Entity {
+    let kind = BallKind::default();
+    let (mesh, material) = ball_assets.look(kind);
+    commands
+        .spawn(RigidBody::Dynamic)
+        .insert(Ball)
+        .insert(kind)
+        .insert(BallLifetime::default())
+        .insert(ActiveCollisionTypes::default() | ActiveCollisionTypes::KINEMATIC_STATIC)
+        .insert(ActiveEvents::COLLISION_EVENTS | ActiveEvents::CONTACT_FORCE_EVENTS)
+        .insert(ContactForceEventThreshold(IMPACT_FORCE_THRESHOLD))
+        .insert(Collider::ball(kind.radius()))
+        .insert(AdditionalMassProperties::Mass(kind.additional_mass()))
+        .insert(ColliderMassProperties::Density(1000.0))
+        .insert(ReadMassProperties::default())
+        .insert(kind.restitution())
+        .insert(PbrBundle {
+            mesh,
+            material,
+            transform: Transform::from_translation(BALL_PARK_TRANSLATION),
+            visibility: Visibility::Hidden,
+            ..default()
+        })
+        .insert(Friction {
+            coefficient: 3.00,
+            combine_rule: CoefficientCombineRule::Min,
+        })
+        .insert(Velocity::zero())
+        .insert(ExternalForce::default())
+        .insert(BallTrail::default())
+        .insert((RigidBodyDisabled, ColliderDisabled))
+        .insert(Name::new("Ball"))
+        .id()
+}
+
+/// Takes a ball out of the pool (or grows the pool) and launches it as `kind` from `transform` with `velocity`.
+pub fn launch_ball(
+    commands: &mut Commands,
+    pool: &mut BallPool,
+    ball_assets: &BallAssets,
+    kind: BallKind,
+    transform: Transform,
+    velocity: Velocity,
+) -> Entity {
+    let ball = take_ball(commands, pool, ball_assets);
+    activate_ball(
+        commands,
+        ball_assets,
+        ball,
+        kind,
+        transform,
+        velocity,
+        BallLifetime::default(),
+    );
+    ball
+}
+
+/// A parked ball from the pool, growing the pool if it's empty. It stays parked.
+pub fn take_ball(commands: &mut Commands, pool: &mut BallPool, ball_assets: &BallAssets) -> Entity {
+    match pool.free.pop() {
+        Some(ball) => ball,
+        None => spawn_parked_ball(commands, ball_assets),
+    }
+}
+
+/// Brings a parked ball into play as `kind`. Doesn't touch the pool.
+pub(crate) fn activate_ball(
+    commands: &mut Commands,
+    ball_assets: &BallAssets,
+    ball: Entity,
+    kind: BallKind,
+    transform: Transform,
+    velocity: Velocity,
+    lifetime: BallLifetime,
+) {
+    let (mesh, material) = ball_assets.look(kind);
+    commands
+        .entity(ball)
+        .remove::<(RigidBodyDisabled, ColliderDisabled)>()
+        .insert(kind)
+        .insert(Collider::ball(kind.radius()))
+        .insert(AdditionalMassProperties::Mass(kind.additional_mass()))
+        .insert(kind.restitution())
+        .insert((mesh, material, Visibility::Inherited))
+        .insert(transform)
+        .insert(velocity)
+        .insert(lifetime);
+}
+
+/// Parks a ball and hands it back to the pool.
+pub fn release_ball(commands: &mut Commands, pool: &mut BallPool, ball: Entity) {
+    park_ball(commands, ball);
+    pool.free.push(ball);
+}
+
+/// Takes a ball out of play. Doesn't touch the pool.
+pub(crate) fn park_ball(commands: &mut Commands, ball: Entity) {
+    commands
+        .entity(ball)
+        .insert((RigidBodyDisabled, ColliderDisabled))
+        .insert(Visibility::Hidden)
+        .insert(Transform::from_translation(BALL_PARK_TRANSLATION))
+        .insert(Velocity::zero())
+        .insert(ExternalForce::default())
+        .insert(BallTrail::default())
+        .remove::<ThrownBy>();
+}
+
+fn random_launchable_kind(rng: &mut impl Rng) -> BallKind {
+    BallKind::LAUNCHABLE
+        .choose_weighted(rng, |(_, weight)| *weight)
+        .map(|(kind, _)| *kind)
+        .unwrap_or_default()
+}
+
+fn spawn_ball(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut config: ResMut<BallSpawnConfig>,
+    mut pool: ResMut<BallPool>,
+    mut rng: ResMut<BallRng>,
+    ball_assets: Res<BallAssets>,
+    mut effects: EventWriter<SpawnEffect>,
+) {
+    config.timer.tick(time.delta());
+    if config.next_kind.is_none() && config.timer.remaining_secs() <= config.lead_secs {
+        config.next_kind = Some(random_launchable_kind(&mut rng.0));
+    }
+    if config.timer.finished() {
+        let kind = config
+            .next_kind
+            .take()
+            .unwrap_or_else(|| random_launchable_kind(&mut rng.0));
+        let linvel = LAUNCH_VELOCITY * kind.speed_scale();
+        launch_ball(
+            &mut commands,
+            &mut pool,
+            &ball_assets,
+            kind,
+            Transform::from_translation(LAUNCH_POSITION),
+            Velocity {
+                linvel,
+                angvel: kind.launch_angvel(),
+            },
+        );
+        effects.send(SpawnEffect {
+            effect: Effect::MuzzleBurst,
+            position: LAUNCH_POSITION,
+            direction: linvel,
+            strength: 1.0,
+        });
+    }
+}
+
+fn despawn_ball(
+    mut commands: Commands,
+    mut q: Query<(Entity, &mut BallLifetime), (With<Ball>, Without<RigidBodyDisabled>)>,
+    mut pool: ResMut<BallPool>,
+    time: Res<Time>,
+) {
+    for (entity, mut fuse_timer) in q.iter_mut() {
+        // timers gotta be ticked, to work
+        fuse_timer.timer.tick(time.delta());
+
+        // if it finished, send the ball back to the pool
+        if fuse_timer.timer.just_finished() {
+            release_ball(&mut commands, &mut pool, entity);
+        }
+    }
+}
+
+// Spinning balls get pushed sideways, the lift is perpendicular to both the spin axis and the direction of travel
+fn apply_magnus_effect(
+    mut ball_q: Query<
+        (
+            &BallKind,
+            &Velocity,
+            &ReadMassProperties,
+            &mut ExternalForce,
+        ),
+        Without<RigidBodyDisabled>,
+    >,
+) {
+    for (kind, velocity, mass_props, mut force) in ball_q.iter_mut() {
+        if *kind != BallKind::Curve {
+            continue;
+        }
+        force.force =
+            MAGNUS_COEFFICIENT * mass_props.get().mass * velocity.angvel.cross(velocity.linvel);
+    }
+}
+
+fn split_balls(
+    mut commands: Commands,
+    mut collision_events: EventReader<CollisionEvent>,
+    ball_q: Query<(&BallKind, &Transform, &Velocity), Without<RigidBodyDisabled>>,
+    mut pool: ResMut<BallPool>,
+    ball_assets: Res<BallAssets>,
+) {
+    // A splitter can touch several things in the same frame, only break it once
+    let mut split = HashSet::new();
+    for collision_event in collision_events.read() {
+        let CollisionEvent::Started(entity1, entity2, flags) = collision_event else {
+            continue;
+        };
+        // Passing through a sensor isn't a bounce
+        if flags.contains(CollisionEventFlags::SENSOR) {
+            continue;
+        }
+        for &entity in [entity1, entity2] {
+            let Ok((kind, transform, velocity)) = ball_q.get(entity) else {
+                continue;
+            };
+            if *kind != BallKind::Splitter || !split.insert(entity) {
+                continue;
+            }
+            release_ball(&mut commands, &mut pool, entity);
+
+            // Fan the shards out around the splitter's direction of travel
+            let speed = velocity.linvel.length();
+            let heading = velocity.linvel.with_y(0.0).normalize_or(Vec3::X);
+            for i in 0..SPLITTER_SHARDS {
+                let angle = (i as f32 - (SPLITTER_SHARDS - 1) as f32 / 2.0) * 0.5;
+                let direction = Quat::from_rotation_y(angle) * heading;
+                launch_ball(
+                    &mut commands,
+                    &mut pool,
+                    &ball_assets,
+                    BallKind::Shard,
+                    Transform::from_translation(transform.translation + direction * 0.5),
+                    Velocity {
+                        linvel: direction * speed * 0.8 + Vec3::Y * 4.0,
+                        angvel: BallKind::Shard.launch_angvel(),
+                    },
+                );
+            }
+        }
+    }
+}
+
+fn toggle_ball_trails(keys: Res<ButtonInput<KeyCode>>, mut settings: ResMut<BallTrailSettings>) {
+    if keys.just_pressed(KeyCode::KeyT) {
+        settings.enabled = !settings.enabled;
+    }
+}
+
+fn record_ball_trails(
+    mut ball_q: Query<(&Transform, &mut BallTrail), (With<Ball>, Without<RigidBodyDisabled>)>,
+) {
+    for (transform, mut trail) in ball_q.iter_mut() {
+        trail.points.push_back(transform.translation);
+        if trail.points.len() > BALL_TRAIL_LENGTH {
+            trail.points.pop_front();
+        }
+    }
+}
+
+// The trail fades out towards its tail
+fn draw_ball_trails(
+    mut gizmos: Gizmos,
+    ball_q: Query<(&BallKind, &BallTrail), Without<RigidBodyDisabled>>,
+) {
+    for (kind, trail) in ball_q.iter() {
+        let color = Srgba::hex(kind.color()).unwrap();
+        let len = trail.points.len() as f32;
+        gizmos.linestrip_gradient(
+            trail
+                .points
+                .iter()
+                .enumerate()
+                .map(|(i, &point)| (point, color.with_alpha((i + 1) as f32 / len))),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    const FRAMES: usize = 600;
+    // Each ball is in play this many frames before it's put away
+    const BALL_LIFETIME_FRAMES: usize = 60;
+
+    #[derive(Resource)]
+    struct Churn {
+        pooled: bool,
+        launches_per_frame: usize,
+        frame: usize,
+        in_play: VecDeque<(usize, Entity)>,
+    }
+
+    // Launches balls and puts away the ones that have had their time, either back into the pool
+    // or by spawning and despawning them the way balls were before the pool
+    fn launch_and_put_away(
+        mut commands: Commands,
+        mut churn: ResMut<Churn>,
+        mut pool: ResMut<BallPool>,
+        ball_assets: Res<BallAssets>,
+    ) {
+        let churn = &mut *churn;
+        churn.frame += 1;
+        while let Some(&(launched, ball)) = churn.in_play.front() {
+            if churn.frame - launched < BALL_LIFETIME_FRAMES {
+                break;
+            }
+            churn.in_play.pop_front();
+            if churn.pooled {
+                release_ball(&mut commands, &mut pool, ball);
+            } else {
+                commands.entity(ball).despawn();
+            }
+        }
+        for i in 0..churn.launches_per_frame {
+            let kind = BallKind::default();
+            let transform = Transform::from_translation(LAUNCH_POSITION + Vec3::Y * i as f32);
+            let velocity = Velocity {
+                linvel: LAUNCH_VELOCITY,
+                angvel: kind.launch_angvel(),
+            };
+            let ball = if churn.pooled {
+                launch_ball(
+                    &mut commands,
+                    &mut pool,
+                    &ball_assets,
+                    kind,
+                    transform,
+                    velocity,
+                )
+            } else {
+                let ball = spawn_parked_ball(&mut commands, &ball_assets);
+                activate_ball(
+                    &mut commands,
+                    &ball_assets,
+                    ball,
+                    kind,
+                    transform,
+                    velocity,
+                    BallLifetime::default(),
+                );
+                ball
+            };
+            churn.in_play.push_back((churn.frame, ball));
+        }
+    }
+
+    fn time_frames(balls_in_play: usize, pooled: bool) -> Duration {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            AssetPlugin::default(),
+            TransformPlugin,
+            HierarchyPlugin,
+            RapierPhysicsPlugin::<NoUserData>::default(),
+        ))
+        .init_asset::<Mesh>()
+        .init_asset::<StandardMaterial>()
+        .insert_resource(Churn {
+            pooled,
+            launches_per_frame: (balls_in_play / BALL_LIFETIME_FRAMES).max(1),
+            frame: 0,
+            in_play: VecDeque::new(),
+        })
+        .add_systems(Startup, (setup_ball_assets, setup_ball_pool).chain())
+        .add_systems(Update, launch_and_put_away);
+
+        // Let the pool fill up first, so both ways are timed once they've settled
+        for _ in 0..BALL_LIFETIME_FRAMES * 2 {
+            app.update();
+        }
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            app.update();
+        }
+        start.elapsed()
+    }
+
+    // Run with `cargo test --release ball_pool -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn ball_pool_against_spawning() {
+        println!("{FRAMES} frames, balls in play for {BALL_LIFETIME_FRAMES} frames");
+        for balls_in_play in [100, 300, 600] {
+            let churn = time_frames(balls_in_play, false);
+            let pooled = time_frames(balls_in_play, true);
+            println!(
+                "{balls_in_play:>4} balls | spawn/despawn: {:>8.2?} ({:>6.2?}/frame) | pool: {:>8.2?} ({:>6.2?}/frame)",
+                churn,
+                churn / FRAMES as u32,
+                pooled,
+                pooled / FRAMES as u32,
+            );
+        }
+    }
+}
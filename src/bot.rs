@@ -0,0 +1,458 @@
+//! Bots that play through `PlayerActions`, the same as someone at a keyboard.
+//!
+//! Put a `Bot` on anything with `PlayerActions` and it plays. Every so often a bot scores
+//! everything it could do right now - dodge or catch a ball coming at it, fetch a loose ball,
+//! throw at an open opponent, or get back into position - and commits to whichever scores best
+//! until it next thinks. Difficulty profiles change how quickly it reacts, how well it aims and
+//! times catches, and how often it tries to catch rather than dodge.
+//!
+//! `--bot-difficulty <easy|normal|hard>` picks the profile. `--headless` plays a bots-vs-bots
+//! match with no window and quits when it's over.
+
+use crate::ball::{Ball, ThrownBy};
+use crate::camera::PlayerCamera;
+use crate::dodgeball::Eliminated;
+use crate::input::{PlayerActions, PlayerInputSet};
+use crate::nav::NavAgent;
+use crate::player::{CatchState, DashState, HeldBall, HitStun, Player, Team, THROW_SPEED};
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// Balls that will pass closer than this are worth getting out of the way of
+const DANGER_RADIUS: f32 = 1.5;
+const DODGE_STEP: f32 = 2.0;
+// Anything further than this is walked to along the navmesh, so dodges stay direct
+const NAV_MIN_DISTANCE: f32 = 2.5;
+const PICKUP_RANGE: f32 = 1.0;
+// Beyond this, a loose ball or a target isn't worth much
+const FETCH_RANGE: f32 = 12.0;
+const THROW_RANGE: f32 = 20.0;
+// How much better another option has to be before a bot changes its mind
+const COMMITMENT: f32 = 0.1;
+
+// Relative weights of each option, before considerations scale them
+const DODGE_WEIGHT: f32 = 1.0;
+const CATCH_WEIGHT: f32 = 1.0;
+const FETCH_WEIGHT: f32 = 0.6;
+const THROW_WEIGHT: f32 = 0.8;
+const REPOSITION_WEIGHT: f32 = 0.2;
+
+pub struct BotPlugin;
+
+impl Plugin for BotPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(BotDifficulty::from_args().unwrap_or_default())
+            .add_systems(Update, spawn_bot_views)
+            .add_systems(
+                PreUpdate,
+                (choose_bot_intents, drive_bots)
+                    .chain()
+                    .in_set(PlayerInputSet),
+            );
+    }
+}
+
+/// Playing bots-vs-bots with no window, from `--headless`.
+#[derive(Resource)]
+pub struct Headless;
+
+impl Headless {
+    pub fn from_args() -> Option<Self> {
+        std::env::args()
+            .any(|arg| arg == "--headless")
+            .then_some(Self)
+    }
+}
+
+/// How well bots play, unless a bot has its own.
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BotDifficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl BotDifficulty {
+    pub fn from_args() -> Option<Self> {
+        let mut args = std::env::args()
+            .skip_while(|arg| arg != "--bot-difficulty")
+            .skip(1);
+        match args.next().as_deref() {
+            Some("easy") => Some(BotDifficulty::Easy),
+            Some("normal") => Some(BotDifficulty::Normal),
+            Some("hard") => Some(BotDifficulty::Hard),
+            _ => None,
+        }
+    }
+
+    pub fn profile(self) -> BotProfile {
+        match self {
+            BotDifficulty::Easy => BotProfile {
+                reaction_secs: 0.6,
+                awareness_secs: 0.6,
+                catch_bias: 0.15,
+                catch_timing_error: 0.15,
+                aim_secs: 1.2,
+                aim_error: 1.5,
+                leads_targets: false,
+                evasive_moves: false,
+            },
+            BotDifficulty::Normal => BotProfile {
+                reaction_secs: 0.35,
+                awareness_secs: 0.9,
+                catch_bias: 0.35,
+                catch_timing_error: 0.08,
+                aim_secs: 0.7,
+                aim_error: 0.7,
+                leads_targets: true,
+                evasive_moves: true,
+            },
+            BotDifficulty::Hard => BotProfile {
+                reaction_secs: 0.15,
+                awareness_secs: 1.2,
+                catch_bias: 0.6,
+                catch_timing_error: 0.03,
+                aim_secs: 0.4,
+                aim_error: 0.25,
+                leads_targets: true,
+                evasive_moves: true,
+            },
+        }
+    }
+}
+
+/// The knobs a difficulty turns.
+#[derive(Clone, Debug)]
+pub struct BotProfile {
+    /// Seconds between decisions, so also how long it takes to notice a new throw
+    pub reaction_secs: f32,
+    /// How many seconds before it arrives a ball is seen coming
+    pub awareness_secs: f32,
+    /// 0 always dodges, 1 always tries to catch
+    pub catch_bias: f32,
+    /// Up to this many seconds early or late pressing catch
+    pub catch_timing_error: f32,
+    /// Seconds spent lining up a throw
+    pub aim_secs: f32,
+    /// Up to this many metres off target
+    pub aim_error: f32,
+    /// Aims where a moving target will be rather than where it is
+    pub leads_targets: bool,
+    /// Dashes out of the way of balls, and jumps over low ones
+    pub evasive_moves: bool,
+}
+
+/// Drives this entity's `PlayerActions`.
+#[derive(Component, Default)]
+pub struct Bot {
+    /// Plays at this difficulty rather than the global `BotDifficulty`
+    pub difficulty: Option<BotDifficulty>,
+    intent: Intent,
+    think_secs: f32,
+    aim_secs: f32,
+    aim_offset: Vec3,
+    // Catch is pressed when the ball is this many seconds away
+    catch_lead_secs: f32,
+    catch_pressed: bool,
+    dash_pressed: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+enum Intent {
+    #[default]
+    Reposition,
+    Dodge(Entity),
+    Catch(Entity),
+    Fetch(Entity),
+    Throw(Entity),
+}
+
+/// Stands in for a bot's camera so `player_movement` and `throw_ball` have something to steer by.
+#[derive(Component)]
+struct BotView;
+
+fn spawn_bot_views(mut commands: Commands, bot_q: Query<Entity, Added<Bot>>) {
+    for player in bot_q.iter() {
+        commands.entity(player).insert(NavAgent::default());
+        commands.spawn((
+            TransformBundle::default(),
+            BotView,
+            PlayerCamera { player },
+            Name::new("BotView"),
+        ));
+    }
+}
+
+// When `ball` will be closest to `position`, and how close it gets
+fn closest_approach(
+    position: Vec3,
+    ball_position: Vec3,
+    ball_velocity: Vec3,
+) -> Option<(f32, Vec3)> {
+    let speed_squared = ball_velocity.length_squared();
+    if speed_squared < 1.0 {
+        return None;
+    }
+    let secs = (position - ball_position).dot(ball_velocity) / speed_squared;
+    (secs > 0.0).then(|| (secs, ball_position + ball_velocity * secs))
+}
+
+fn is_opponent(team: Option<&Team>, other: Option<&Team>) -> bool {
+    match (team, other) {
+        (Some(team), Some(other)) => team != other,
+        // Without teams it's everyone for themselves
+        _ => true,
+    }
+}
+
+// Scores every option and picks the best, every `reaction_secs`
+fn choose_bot_intents(
+    time: Res<Time>,
+    difficulty: Res<BotDifficulty>,
+    mut bot_q: Query<
+        (Entity, &Transform, Option<&Team>, &mut Bot, Has<HeldBall>),
+        (With<Player>, Without<Eliminated>),
+    >,
+    target_q: Query<
+        (
+            Entity,
+            &Transform,
+            Option<&Team>,
+            Option<&CatchState>,
+            Has<HitStun>,
+        ),
+        (With<Player>, Without<Eliminated>),
+    >,
+    ball_q: Query<
+        (Entity, &Transform, &Velocity, Option<&ThrownBy>),
+        (With<Ball>, Without<RigidBodyDisabled>),
+    >,
+    team_q: Query<&Team>,
+) {
+    let mut rng = thread_rng();
+    for (entity, transform, team, mut bot, holding) in bot_q.iter_mut() {
+        bot.think_secs -= time.delta_seconds();
+        if bot.think_secs > 0.0 {
+            continue;
+        }
+        let profile = bot.difficulty.unwrap_or(*difficulty).profile();
+        bot.think_secs = profile.reaction_secs * rng.gen_range(0.75..1.25);
+        let position = transform.translation;
+
+        let mut options = vec![(Intent::Reposition, REPOSITION_WEIGHT)];
+        for (ball, ball_transform, velocity, thrown_by) in ball_q.iter() {
+            let ball_position = ball_transform.translation;
+            match thrown_by {
+                Some(thrown_by) => {
+                    if thrown_by.player == entity
+                        || !is_opponent(team, team_q.get(thrown_by.player).ok())
+                    {
+                        continue;
+                    }
+                    let Some((secs, closest)) =
+                        closest_approach(position, ball_position, velocity.linvel)
+                    else {
+                        continue;
+                    };
+                    if secs > profile.awareness_secs {
+                        continue;
+                    }
+                    let closeness = 1.0 - (closest.distance(position) / DANGER_RADIUS).min(1.0);
+                    let urgency = 1.0 - secs / profile.awareness_secs;
+                    let threat = closeness * (0.5 + 0.5 * urgency);
+                    options.push((
+                        Intent::Dodge(ball),
+                        DODGE_WEIGHT * threat * (1.0 - profile.catch_bias),
+                    ));
+                    // Hands are full
+                    if !holding {
+                        options.push((
+                            Intent::Catch(ball),
+                            CATCH_WEIGHT * threat * profile.catch_bias,
+                        ));
+                    }
+                }
+                None => {
+                    // Only loose balls on our own half can be reached
+                    let reachable = team.map_or(true, |team| ball_position.x * team.side() > 0.0);
+                    if holding || !reachable {
+                        continue;
+                    }
+                    let nearness = 1.0 - (ball_position.distance(position) / FETCH_RANGE).min(0.9);
+                    options.push((Intent::Fetch(ball), FETCH_WEIGHT * nearness));
+                }
+            }
+        }
+        if holding {
+            for (target, target_transform, target_team, catch_state, stunned) in target_q.iter() {
+                if target == entity || !is_opponent(team, target_team) {
+                    continue;
+                }
+                // Someone ready to catch is a bad target, someone knocked down is a great one
+                let openness = if stunned {
+                    1.0
+                } else if catch_state.is_some_and(|catch_state| catch_state.is_catching()) {
+                    0.2
+                } else {
+                    0.7
+                };
+                let nearness =
+                    1.0 - (target_transform.translation.distance(position) / THROW_RANGE).min(0.9);
+                options.push((Intent::Throw(target), THROW_WEIGHT * openness * nearness));
+            }
+        }
+
+        let current = bot.intent;
+        let (best, _) = options
+            .into_iter()
+            .map(|(intent, score)| {
+                let bonus = if intent == current { COMMITMENT } else { 0.0 };
+                (intent, score + bonus)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        if best != current {
+            bot.aim_secs = 0.0;
+            let error = profile.aim_error;
+            bot.aim_offset = Vec3::new(
+                rng.gen_range(-error..=error),
+                rng.gen_range(-error..=error) * 0.5,
+                rng.gen_range(-error..=error),
+            );
+            let timing = profile.catch_timing_error;
+            bot.catch_lead_secs = 0.12 + rng.gen_range(-timing..=timing);
+        }
+        bot.intent = best;
+    }
+}
+
+// Turns each bot's intent into this frame's actions
+fn drive_bots(
+    time: Res<Time>,
+    difficulty: Res<BotDifficulty>,
+    mut bot_q: Query<
+        (
+            Entity,
+            &Transform,
+            Option<&Team>,
+            &mut Bot,
+            &mut PlayerActions,
+            Has<HeldBall>,
+            Option<&DashState>,
+            Option<&mut NavAgent>,
+        ),
+        (With<Player>, Without<Eliminated>),
+    >,
+    target_q: Query<(&Transform, &Velocity), (With<Player>, Without<Eliminated>)>,
+    ball_q: Query<(&Transform, &Velocity, Has<ThrownBy>), (With<Ball>, Without<RigidBodyDisabled>)>,
+    mut view_q: Query<
+        (&PlayerCamera, &mut Transform),
+        (With<BotView>, Without<Player>, Without<Ball>),
+    >,
+) {
+    for (entity, transform, team, mut bot, mut actions, holding, dash_state, nav_agent) in
+        bot_q.iter_mut()
+    {
+        *actions = PlayerActions::default();
+        let profile = bot.difficulty.unwrap_or(*difficulty).profile();
+        let position = transform.translation;
+        let mut move_to = None;
+        let mut look_at = None;
+
+        let intent = bot.intent;
+        match intent {
+            Intent::Dodge(ball) => match ball_q.get(ball) {
+                Ok((ball_transform, velocity, true)) => {
+                    let ball_position = ball_transform.translation;
+                    let across = velocity.linvel.cross(Vec3::Y).normalize_or(Vec3::Z);
+                    let away = if (position - ball_position).dot(across) >= 0.0 {
+                        across
+                    } else {
+                        -across
+                    };
+                    move_to = Some(position + away * DODGE_STEP);
+                    if let Some((secs, closest)) =
+                        closest_approach(position, ball_position, velocity.linvel)
+                    {
+                        if profile.evasive_moves && secs < 0.35 {
+                            actions.dash =
+                                dash_state.is_some_and(|dash| dash.is_ready()) && !bot.dash_pressed;
+                            actions.jump = closest.y < position.y && secs < 0.25;
+                        }
+                    }
+                }
+                _ => bot.think_secs = 0.0,
+            },
+            Intent::Catch(ball) => match ball_q.get(ball) {
+                Ok((ball_transform, velocity, true)) => {
+                    let ball_position = ball_transform.translation;
+                    look_at = Some(ball_position);
+                    actions.catch = closest_approach(position, ball_position, velocity.linvel)
+                        .is_some_and(|(secs, _)| secs < bot.catch_lead_secs);
+                }
+                _ => bot.think_secs = 0.0,
+            },
+            Intent::Fetch(ball) => match ball_q.get(ball) {
+                Ok((ball_transform, _, false)) if !holding => {
+                    let ball_position = ball_transform.translation;
+                    move_to = Some(ball_position);
+                    // Catch only triggers on a fresh press, so keep tapping it
+                    actions.catch =
+                        ball_position.distance(position) < PICKUP_RANGE && !bot.catch_pressed;
+                }
+                _ => bot.think_secs = 0.0,
+            },
+            Intent::Throw(target) => match target_q.get(target) {
+                Ok((target_transform, target_velocity)) if holding => {
+                    bot.aim_secs += time.delta_seconds();
+                    let mut aim_point = target_transform.translation + bot.aim_offset;
+                    if profile.leads_targets {
+                        let flight_secs = aim_point.distance(position) / THROW_SPEED;
+                        aim_point += target_velocity.linvel.with_y(0.0) * flight_secs;
+                    }
+                    look_at = Some(aim_point);
+                    actions.throw = bot.aim_secs >= profile.aim_secs;
+                }
+                _ => bot.think_secs = 0.0,
+            },
+            Intent::Reposition => {
+                // Back to the middle of our own half, or stay put without one
+                if let Some(team) = team {
+                    move_to = Some(Vec3::new(team.side() * 3.0, position.y, position.z * 0.5));
+                }
+            }
+        }
+        bot.catch_pressed = actions.catch;
+        bot.dash_pressed = actions.dash;
+
+        let mut heading = move_to.map(|target| (target - position).with_y(0.0));
+        if let Some(mut agent) = nav_agent {
+            agent.destination =
+                move_to.filter(|target| target.distance(position) > NAV_MIN_DISTANCE);
+            if let (Some(_), Some(steer)) = (agent.destination, agent.steer) {
+                heading = Some(steer);
+                actions.jump |= agent.jump;
+            }
+        }
+
+        let Some((_, mut view)) = view_q
+            .iter_mut()
+            .find(|(camera, _)| camera.player == entity)
+        else {
+            continue;
+        };
+        view.translation = position;
+        if let Some(target) = look_at {
+            view.look_at(target, Vec3::Y);
+        } else if let Some(direction) = heading {
+            if direction.length() > 0.3 {
+                view.look_to(direction, Vec3::Y);
+                actions.movement = Vec2::Y;
+            }
+        }
+    }
+}
@@ -1,25 +1,148 @@
+use crate::input::{LocalPlayer, PlayerActions};
+use crate::player::LocalPlayers;
 use bevy::prelude::*;
+use bevy::render::camera::Viewport;
+use bevy::window::{PrimaryWindow, WindowResized};
 use bevy_third_person_camera::*;
 
+const SPLIT_SCREEN_DISTANCE: f32 = 8.0;
+const SPLIT_SCREEN_MIN_PITCH: f32 = -1.2;
+const SPLIT_SCREEN_MAX_PITCH: f32 = 0.3;
+// How quickly a camera with nobody turning it swings round behind its player
+const SPLIT_SCREEN_AUTO_FOLLOW: f32 = 1.5;
+
 pub struct CameraPlugin;
 
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, spawn_camera);
+        app.add_systems(Update, (spawn_camera, set_split_screen_viewports))
+            .add_systems(
+                PostUpdate,
+                follow_split_screen_players.before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+/// The camera a player steers relative to.
+#[derive(Component)]
+pub struct PlayerCamera {
+    pub player: Entity,
+}
+
+/// Orbits one player when several share the screen, since `ThirdPersonCamera` only follows one target.
+#[derive(Component)]
+pub struct SplitScreenCamera {
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+// Every local player gets a camera once they've spawned
+fn spawn_camera(
+    mut commands: Commands,
+    local_players: Res<LocalPlayers>,
+    player_q: Query<(Entity, &LocalPlayer), Added<LocalPlayer>>,
+) {
+    for (player, local_player) in player_q.iter() {
+        if local_players.count == 1 {
+            let camera = (
+                Camera3dBundle {
+                    transform: Transform::from_xyz(-2.0, 2.5, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
+                    ..default()
+                },
+                ThirdPersonCamera {
+                    zoom: Zoom::new(1.5, 20.0),
+                    sensitivity: Vec2::new(2.5, 2.5),
+                    ..default()
+                },
+                PlayerCamera { player },
+            );
+            commands.spawn(camera);
+        } else {
+            let camera = (
+                Camera3dBundle {
+                    camera: Camera {
+                        order: local_player.slot as isize,
+                        ..default()
+                    },
+                    ..default()
+                },
+                SplitScreenCamera {
+                    yaw: 0.0,
+                    pitch: -0.4,
+                },
+                PlayerCamera { player },
+                Name::new(format!("Camera {}", local_player.slot + 1)),
+            );
+            commands.spawn(camera);
+        }
     }
 }
 
-fn spawn_camera(mut commands: Commands) {
-    let camera = (
-        Camera3dBundle {
-            transform: Transform::from_xyz(-2.0, 2.5, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
-            ..default()
-        },
-        ThirdPersonCamera {
-            zoom: Zoom::new(1.5, 20.0),
-            sensitivity: Vec2::new(2.5, 2.5),
-            ..default()
-        },
-    );
-    commands.spawn(camera);
+// Two players split the screen left and right, three or four get a quarter each
+fn split_screen_viewport(slot: usize, player_count: usize, window_size: UVec2) -> Viewport {
+    let (columns, rows) = if player_count == 2 { (2, 1) } else { (2, 2) };
+    let size = UVec2::new(window_size.x / columns, window_size.y / rows);
+    let cell = UVec2::new(slot as u32 % columns, slot as u32 / columns);
+    Viewport {
+        physical_position: cell * size,
+        physical_size: size,
+        ..default()
+    }
+}
+
+fn set_split_screen_viewports(
+    local_players: Res<LocalPlayers>,
+    mut resize_events: EventReader<WindowResized>,
+    window_q: Query<&Window, With<PrimaryWindow>>,
+    mut camera_q: Query<(&mut Camera, &PlayerCamera, Ref<SplitScreenCamera>)>,
+    slot_q: Query<&LocalPlayer>,
+) {
+    let resized = resize_events.read().count() > 0;
+    let Ok(window) = window_q.get_single() else {
+        return;
+    };
+    for (mut camera, player_camera, split_screen) in camera_q.iter_mut() {
+        if !resized && !split_screen.is_added() {
+            continue;
+        }
+        let Ok(local_player) = slot_q.get(player_camera.player) else {
+            continue;
+        };
+        camera.viewport = Some(split_screen_viewport(
+            local_player.slot,
+            local_players.count,
+            window.physical_size(),
+        ));
+    }
+}
+
+pub(crate) fn follow_split_screen_players(
+    time: Res<Time>,
+    player_q: Query<(&Transform, &PlayerActions), Without<SplitScreenCamera>>,
+    mut camera_q: Query<(&mut Transform, &mut SplitScreenCamera, &PlayerCamera)>,
+) {
+    for (mut transform, mut orbit, player_camera) in camera_q.iter_mut() {
+        let Ok((player_transform, actions)) = player_q.get(player_camera.player) else {
+            continue;
+        };
+        if actions.look == Vec2::ZERO {
+            // Drift round behind the way the player is facing
+            let facing = player_transform.forward();
+            let behind_yaw = f32::atan2(-facing.x, -facing.z);
+            let turn = (behind_yaw - orbit.yaw + std::f32::consts::PI)
+                .rem_euclid(std::f32::consts::TAU)
+                - std::f32::consts::PI;
+            orbit.yaw += turn * (SPLIT_SCREEN_AUTO_FOLLOW * time.delta_seconds()).min(1.0);
+        } else {
+            orbit.yaw -= actions.look.x;
+            orbit.pitch = (orbit.pitch - actions.look.y)
+                .clamp(SPLIT_SCREEN_MIN_PITCH, SPLIT_SCREEN_MAX_PITCH);
+        }
+
+        let rotation = Quat::from_euler(EulerRot::YXZ, orbit.yaw, orbit.pitch, 0.0);
+        let focus = player_transform.translation + Vec3::Y;
+        *transform =
+            Transform::from_translation(focus + rotation * Vec3::Z * SPLIT_SCREEN_DISTANCE)
+                .looking_at(focus, Vec3::Y);
+    }
 }
@@ -0,0 +1,205 @@
+//! Keeps the player in view.
+//!
+//! Each player camera casts a ray from its player out to where it wants to be. If static or moving
+//! geometry is in the way the camera pulls in in front of it, then eases back out once it's clear.
+//! This runs after `ThirdPersonCamera` and the split screen cameras have placed themselves, so it
+//! works for both.
+//!
+//! Anything left between a camera and its player, or between the camera and a ball heading for
+//! that player, fades out until it's out of the way. A faded mesh gets a copy of its material to
+//! fade, so meshes sharing a material fade on their own, and gets the shared one back once it's
+//! fully faded back in.
+
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use bevy_rapier3d::prelude::*;
+
+use crate::ball::Ball;
+use crate::camera::{follow_split_screen_players, PlayerCamera};
+use crate::player::Player;
+
+// How far the camera stays off whatever it's pulled in against
+const CAMERA_RADIUS: f32 = 0.3;
+const MIN_CAMERA_DISTANCE: f32 = 0.5;
+// Metres per second the camera moves back out once the way is clear
+const EASE_OUT_SPEED: f32 = 4.0;
+
+const FADED_OPACITY: f32 = 0.25;
+// Opacity per second
+const FADE_SPEED: f32 = 4.0;
+// Balls closer than this to the player, and heading their way, are kept in view too
+const THREAT_RANGE: f32 = 15.0;
+
+pub struct CameraCollisionPlugin;
+
+impl Plugin for CameraCollisionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            (add_camera_collision, pull_cameras_in, fade_occluders)
+                .chain()
+                .after(follow_split_screen_players)
+                .before(TransformSystem::TransformPropagate),
+        );
+    }
+}
+
+/// How far out a camera can currently go, `None` when nothing is in the way.
+#[derive(Component, Default)]
+pub struct CameraCollision {
+    pub distance: Option<f32>,
+}
+
+/// A mesh that's been faded out of the way, and the material to put back once it isn't.
+#[derive(Component)]
+struct Faded {
+    opacity: f32,
+    alpha: f32,
+    original: Handle<StandardMaterial>,
+}
+
+// Only the world gets in the way, not balls or other players
+fn blocking_filter() -> QueryFilter<'static> {
+    QueryFilter::new().exclude_dynamic().exclude_sensors()
+}
+
+fn add_camera_collision(
+    mut commands: Commands,
+    camera_q: Query<Entity, (Added<PlayerCamera>, With<Camera>)>,
+) {
+    for camera in camera_q.iter() {
+        commands.entity(camera).insert(CameraCollision::default());
+    }
+}
+
+pub(crate) fn pull_cameras_in(
+    time: Res<Time>,
+    rapier_context: Res<RapierContext>,
+    mut camera_q: Query<(&PlayerCamera, &mut Transform, &mut CameraCollision)>,
+    player_q: Query<&Transform, (With<Player>, Without<PlayerCamera>)>,
+) {
+    for (player_camera, mut transform, mut collision) in camera_q.iter_mut() {
+        let Ok(player_transform) = player_q.get(player_camera.player) else {
+            continue;
+        };
+        let focus = player_transform.translation;
+        let offset = transform.translation - focus;
+        let wanted = offset.length();
+        if wanted < MIN_CAMERA_DISTANCE {
+            continue;
+        }
+        let direction = offset / wanted;
+        let clear = rapier_context
+            .cast_ray(
+                focus,
+                direction,
+                wanted + CAMERA_RADIUS,
+                true,
+                blocking_filter(),
+            )
+            .map_or(wanted, |(_, toi)| {
+                (toi - CAMERA_RADIUS).max(MIN_CAMERA_DISTANCE)
+            });
+        // Snap in so nothing clips, but ease out so the camera doesn't jump about
+        let distance = match collision.distance {
+            Some(distance) if clear > distance => {
+                (distance + EASE_OUT_SPEED * time.delta_seconds()).min(clear)
+            }
+            _ => clear,
+        };
+        collision.distance = (distance < wanted).then_some(distance);
+        transform.translation = focus + direction * distance.min(wanted);
+    }
+}
+
+fn fade_occluders(
+    mut commands: Commands,
+    time: Res<Time>,
+    rapier_context: Res<RapierContext>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    camera_q: Query<(&PlayerCamera, &Transform), With<Camera>>,
+    player_q: Query<&Transform, With<Player>>,
+    ball_q: Query<(&Transform, &Velocity), (With<Ball>, Without<RigidBodyDisabled>)>,
+    parent_q: Query<&Parent>,
+    material_q: Query<&Handle<StandardMaterial>>,
+    mut faded_q: Query<(Entity, &Handle<StandardMaterial>, &mut Faded)>,
+) {
+    let mut occluding = HashSet::new();
+    for (player_camera, camera_transform) in camera_q.iter() {
+        let Ok(player_transform) = player_q.get(player_camera.player) else {
+            continue;
+        };
+        let player_position = player_transform.translation;
+        let incoming = ball_q.iter().filter_map(|(ball_transform, velocity)| {
+            let to_player = player_position - ball_transform.translation;
+            let heading_our_way = velocity.linvel.dot(to_player) > 0.0;
+            (heading_our_way && to_player.length() < THREAT_RANGE)
+                .then_some(ball_transform.translation)
+        });
+        for target in std::iter::once(player_position).chain(incoming) {
+            let origin = camera_transform.translation;
+            let to_target = target - origin;
+            let Some(direction) = to_target.try_normalize() else {
+                continue;
+            };
+            rapier_context.intersections_with_ray(
+                origin,
+                direction,
+                to_target.length(),
+                true,
+                blocking_filter(),
+                |collider, _| {
+                    // Colliders are often a child of the mesh they belong to
+                    let mesh = std::iter::once(collider)
+                        .chain(parent_q.get(collider).map(|parent| parent.get()))
+                        .find(|&entity| material_q.contains(entity));
+                    occluding.extend(mesh);
+                    true
+                },
+            );
+        }
+    }
+
+    for &entity in occluding.iter() {
+        if faded_q.contains(entity) {
+            continue;
+        }
+        let Ok(original) = material_q.get(entity) else {
+            continue;
+        };
+        let Some(mut material) = materials.get(original).cloned() else {
+            continue;
+        };
+        let alpha = material.base_color.alpha();
+        material.alpha_mode = AlphaMode::Blend;
+        commands.entity(entity).insert((
+            materials.add(material),
+            Faded {
+                opacity: 1.0,
+                alpha,
+                original: original.clone(),
+            },
+        ));
+    }
+
+    for (entity, handle, mut faded) in faded_q.iter_mut() {
+        let target = if occluding.contains(&entity) {
+            FADED_OPACITY
+        } else {
+            1.0
+        };
+        let step = FADE_SPEED * time.delta_seconds();
+        faded.opacity += (target - faded.opacity).clamp(-step, step);
+        let Some(material) = materials.get_mut(handle) else {
+            continue;
+        };
+        if faded.opacity >= 1.0 && target >= 1.0 {
+            commands
+                .entity(entity)
+                .insert(faded.original.clone())
+                .remove::<Faded>();
+        } else {
+            material.base_color.set_alpha(faded.alpha * faded.opacity);
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Camera effects on top of wherever the camera has put itself.
+//!
+//! Getting hit, or a heavy ball landing nearby, adds trauma to a camera. The camera shakes by the
+//! square of its trauma, which wears off over about a second. Getting hit also slows time right
+//! down for a moment, the hit-stop. When several balls are heading for a player their camera pulls
+//! back so they can all be seen.
+//!
+//! Each of these can be turned down or off in the settings. The effects only move the camera, never
+//! turn it, because `ThirdPersonCamera` keeps its orbit in the camera's rotation.
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::ball::{Ball, BallKind};
+use crate::camera::{follow_split_screen_players, PlayerCamera};
+use crate::camera_collision::pull_cameras_in;
+use crate::input::LocalPlayer;
+use crate::net::NetMode;
+use crate::player::{HitStun, Player};
+use crate::rollback::RollbackSession;
+use crate::settings::Settings;
+
+const HIT_TRAUMA: f32 = 0.6;
+const HEAVY_IMPACT_TRAUMA: f32 = 0.4;
+// Heavy impacts further than this from the player don't shake their camera
+const HEAVY_IMPACT_RANGE: f32 = 12.0;
+const MIN_HEAVY_IMPACT_SPEED: f32 = 6.0;
+// Trauma lost per second, in real time so it wears off during hit-stop too
+const TRAUMA_DECAY: f32 = 1.2;
+const MAX_SHAKE_OFFSET: f32 = 0.35;
+
+const HIT_STOP_SECS: f32 = 0.12;
+const HIT_STOP_SPEED: f32 = 0.05;
+
+// A ball counts as a threat if it will reach the player within this many seconds
+const THREAT_SECS: f32 = 1.5;
+// Pull back once this many balls are threats
+const MIN_THREATS: usize = 2;
+// How far back to pull for each threat past the first
+const FRAMING_DISTANCE_PER_THREAT: f32 = 1.5;
+const MAX_FRAMING_DISTANCE: f32 = 5.0;
+// Metres per second the framing distance moves towards where it wants to be
+const FRAMING_SPEED: f32 = 3.0;
+
+pub struct CameraEffectsPlugin;
+
+impl Plugin for CameraEffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HitStop>()
+            .add_systems(
+                Update,
+                (
+                    add_camera_effects,
+                    shake_on_hits,
+                    shake_on_heavy_impacts,
+                    // Slowing time would throw networked and rollback games out of step
+                    start_hit_stop.run_if(
+                        not(resource_exists::<NetMode>)
+                            .and_then(not(resource_exists::<RollbackSession>)),
+                    ),
+                    end_hit_stop,
+                ),
+            )
+            .add_systems(
+                PostUpdate,
+                (
+                    frame_threats
+                        .after(follow_split_screen_players)
+                        .before(pull_cameras_in),
+                    shake_cameras.after(pull_cameras_in),
+                )
+                    .before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+/// Shake and framing state for one player camera.
+#[derive(Component, Default)]
+pub struct CameraEffects {
+    /// 0 to 1, shake grows with its square
+    pub trauma: f32,
+    /// How far the camera is pulled back to fit in incoming balls
+    framing_distance: f32,
+}
+
+impl CameraEffects {
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).min(1.0);
+    }
+}
+
+/// Real seconds left of the current hit-stop.
+#[derive(Resource, Default)]
+struct HitStop {
+    secs_left: f32,
+}
+
+fn add_camera_effects(
+    mut commands: Commands,
+    camera_q: Query<Entity, (Added<PlayerCamera>, With<Camera>)>,
+) {
+    for camera in camera_q.iter() {
+        commands.entity(camera).insert(CameraEffects::default());
+    }
+}
+
+fn shake_on_hits(
+    hit_q: Query<Entity, (Added<HitStun>, With<LocalPlayer>)>,
+    mut camera_q: Query<(&PlayerCamera, &mut CameraEffects)>,
+) {
+    for player in hit_q.iter() {
+        for (player_camera, mut effects) in camera_q.iter_mut() {
+            if player_camera.player == player {
+                effects.add_trauma(HIT_TRAUMA);
+            }
+        }
+    }
+}
+
+fn shake_on_heavy_impacts(
+    mut contact_force_events: EventReader<ContactForceEvent>,
+    ball_q: Query<(&Transform, &Velocity, &BallKind), With<Ball>>,
+    player_q: Query<&Transform, With<Player>>,
+    mut camera_q: Query<(&PlayerCamera, &mut CameraEffects)>,
+) {
+    for event in contact_force_events.read() {
+        let Some((ball_transform, _, _)) = [event.collider1, event.collider2]
+            .into_iter()
+            .filter_map(|entity| ball_q.get(entity).ok())
+            .find(|(_, velocity, kind)| {
+                **kind == BallKind::Heavy && velocity.linvel.length() > MIN_HEAVY_IMPACT_SPEED
+            })
+        else {
+            continue;
+        };
+        for (player_camera, mut effects) in camera_q.iter_mut() {
+            let Ok(player_transform) = player_q.get(player_camera.player) else {
+                continue;
+            };
+            let distance = player_transform
+                .translation
+                .distance(ball_transform.translation);
+            let falloff = 1.0 - (distance / HEAVY_IMPACT_RANGE).min(1.0);
+            effects.add_trauma(HEAVY_IMPACT_TRAUMA * falloff);
+        }
+    }
+}
+
+fn start_hit_stop(
+    settings: Res<Settings>,
+    mut hit_stop: ResMut<HitStop>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+    hit_q: Query<(), (Added<HitStun>, With<LocalPlayer>)>,
+) {
+    if !settings.hit_stop || hit_q.is_empty() {
+        return;
+    }
+    hit_stop.secs_left = HIT_STOP_SECS;
+    virtual_time.set_relative_speed(HIT_STOP_SPEED);
+}
+
+fn end_hit_stop(
+    real_time: Res<Time<Real>>,
+    mut hit_stop: ResMut<HitStop>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+) {
+    if hit_stop.secs_left <= 0.0 {
+        return;
+    }
+    hit_stop.secs_left -= real_time.delta_seconds();
+    if hit_stop.secs_left <= 0.0 {
+        virtual_time.set_relative_speed(1.0);
+    }
+}
+
+// Pushes the camera further out along the way it's already looking, before collision has its say
+fn frame_threats(
+    real_time: Res<Time<Real>>,
+    settings: Res<Settings>,
+    mut camera_q: Query<(&PlayerCamera, &mut Transform, &mut CameraEffects)>,
+    player_q: Query<&Transform, (With<Player>, Without<PlayerCamera>)>,
+    ball_q: Query<
+        (&Transform, &Velocity),
+        (
+            With<Ball>,
+            Without<RigidBodyDisabled>,
+            Without<PlayerCamera>,
+        ),
+    >,
+) {
+    for (player_camera, mut transform, mut effects) in camera_q.iter_mut() {
+        let Ok(player_transform) = player_q.get(player_camera.player) else {
+            continue;
+        };
+        let focus = player_transform.translation;
+        let threats = ball_q
+            .iter()
+            .filter(|(ball_transform, velocity)| {
+                let to_player = focus - ball_transform.translation;
+                let closing_speed = velocity.linvel.dot(to_player.normalize_or_zero());
+                closing_speed > 0.0 && to_player.length() / closing_speed < THREAT_SECS
+            })
+            .count();
+        let wanted = if settings.threat_framing && threats >= MIN_THREATS {
+            ((threats - 1) as f32 * FRAMING_DISTANCE_PER_THREAT).min(MAX_FRAMING_DISTANCE)
+        } else {
+            0.0
+        };
+        let step = FRAMING_SPEED * real_time.delta_seconds();
+        effects.framing_distance += (wanted - effects.framing_distance).clamp(-step, step);
+        let away = (transform.translation - focus).normalize_or_zero();
+        transform.translation += away * effects.framing_distance;
+    }
+}
+
+fn shake_cameras(
+    real_time: Res<Time<Real>>,
+    settings: Res<Settings>,
+    mut camera_q: Query<(&mut Transform, &mut CameraEffects)>,
+) {
+    let t = real_time.elapsed_seconds();
+    for (mut transform, mut effects) in camera_q.iter_mut() {
+        effects.trauma = (effects.trauma - TRAUMA_DECAY * real_time.delta_seconds()).max(0.0);
+        let shake = effects.trauma * effects.trauma * settings.screen_shake;
+        if shake <= 0.0 {
+            continue;
+        }
+        // Sines at unrelated frequencies make for a smooth wobble that doesn't repeat
+        let wobble = Vec2::new(
+            (t * 37.3).sin() + 0.5 * (t * 71.9 + 0.4).sin(),
+            (t * 41.7 + 1.3).sin() + 0.5 * (t * 63.1 + 2.1).sin(),
+        ) / 1.5;
+        let offset = transform.right() * wobble.x + transform.up() * wobble.y;
+        transform.translation += offset * MAX_SHAKE_OFFSET * shake;
+    }
+}
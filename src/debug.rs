@@ -0,0 +1,24 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+pub struct DebugPlugin;
+
+impl Plugin for DebugPlugin {
+    fn build(&self, app: &mut App) {
+        // Collider wireframes are off in normal play, F3 brings them back
+        app.add_plugins(RapierDebugRenderPlugin {
+            enabled: false,
+            ..default()
+        })
+        .add_systems(Update, toggle_debug_render);
+    }
+}
+
+fn toggle_debug_render(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut debug_render: ResMut<DebugRenderContext>,
+) {
+    if keys.just_pressed(KeyCode::F3) {
+        debug_render.enabled = !debug_render.enabled;
+    }
+}
@@ -0,0 +1,556 @@
+//! Team dodgeball.
+//!
+//! Two teams play on opposite halves of the floor and can't cross the centre line. A player hit by
+//! a ball an opponent threw is out and goes to the bench behind their own half. Catching an
+//! opponent's throw puts the thrower out instead, and brings the catcher's longest-benched
+//! teammate back in. A team wins the round when the other has nobody left, and the match at
+//! `ROUNDS_TO_WIN` rounds. If both teams' last players go out together the round is a draw.
+//!
+//! Start a match with `--match [players per team]`, or `--headless` for bots only.
+//! Fox bots fill any slots local players don't.
+
+use crate::ball::{release_ball, Ball, BallPool, ThrownBy};
+use crate::bot::Headless;
+use crate::fox::spawn_fox_player;
+use crate::input::{PlayerActions, PlayerInputSet};
+use crate::player::{BallCaught, HeldBall, HitStun, LocalPlayers, Player, Team};
+use crate::powerup::{has_power_up, ActivePowerUp, PowerUpKind, ShieldBlocked};
+use crate::ragdoll::RagdollLimb;
+use crate::world::SimulationSet;
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::geometry::CollisionEventFlags;
+
+// The court is the floor, with the centre line across the middle at x = 0
+const COURT_HALF_LENGTH: f32 = 5.0;
+const COURT_HALF_WIDTH: f32 = 5.0;
+// Players are stopped this far short of the centre line
+const CENTER_LINE_MARGIN: f32 = 0.4;
+// Benched players stand on the lower ground behind their own half
+const BENCH_X: f32 = COURT_HALF_LENGTH + 1.5;
+const BENCH_Y: f32 = -1.1;
+const BENCH_SPACING: f32 = 1.2;
+const DEFAULT_TEAM_SIZE: usize = 3;
+const MAX_TEAM_SIZE: usize = 6;
+const ROUNDS_TO_WIN: u32 = 3;
+const ROUND_OVER_SECS: f32 = 3.0;
+const MATCH_OVER_SECS: f32 = 6.0;
+
+pub struct DodgeballPlugin;
+
+impl Plugin for DodgeballPlugin {
+    fn build(&self, app: &mut App) {
+        let Some(config) = MatchConfig::from_args() else {
+            return;
+        };
+        println!(
+            "Dodgeball match, {} a side, first to {} rounds",
+            config.team_size, ROUNDS_TO_WIN
+        );
+        app.insert_resource(config)
+            .init_resource::<Match>()
+            .add_systems(Startup, (spawn_court_lines, fill_teams_with_foxes))
+            .add_systems(PreUpdate, hold_eliminated_players.after(PlayerInputSet))
+            .add_systems(Update, start_round.before(SimulationSet))
+            .add_systems(
+                Update,
+                (
+                    keep_off_center_line,
+                    eliminate_on_hit,
+                    return_on_catch,
+                    check_round_over,
+                )
+                    .chain()
+                    .after(SimulationSet),
+            );
+    }
+}
+
+/// From `--match [players per team]`. Headless runs always play a match.
+#[derive(Resource, Clone, Debug)]
+pub struct MatchConfig {
+    pub team_size: usize,
+}
+
+impl MatchConfig {
+    pub fn from_args() -> Option<Self> {
+        let args: Vec<String> = std::env::args().collect();
+        let team_size = match args.iter().position(|arg| arg == "--match") {
+            Some(i) => args.get(i + 1).and_then(|size| size.parse().ok()),
+            None if Headless::from_args().is_some() => None,
+            None => return None,
+        };
+        let team_size = team_size
+            .unwrap_or(DEFAULT_TEAM_SIZE)
+            .clamp(1, MAX_TEAM_SIZE);
+        Some(Self { team_size })
+    }
+}
+
+#[derive(Reflect, Clone, Debug, Default, PartialEq)]
+pub enum MatchState {
+    /// Everyone is put back on court at the start of the next frame
+    #[default]
+    Starting,
+    Playing,
+    RoundOver {
+        secs_left: f32,
+    },
+    MatchOver {
+        secs_left: f32,
+    },
+}
+
+#[derive(Resource, Default, Debug)]
+pub struct Match {
+    pub state: MatchState,
+    pub round: u32,
+    /// Rounds won, indexed by `Team as usize`
+    pub wins: [u32; 2],
+    /// Players who are out, longest out first
+    pub benched: Vec<Entity>,
+    /// Seconds since the match started
+    pub secs: f32,
+}
+
+/// The player is out and sitting on the bench until a teammate catches them back in.
+#[derive(Component)]
+pub struct Eliminated;
+
+// Where a team's players line up at the start of a round
+fn court_position(team: Team, index: usize, team_size: usize) -> Vec3 {
+    let spacing = 2.0 * COURT_HALF_WIDTH / (team_size + 1) as f32;
+    let z = -COURT_HALF_WIDTH + spacing * (index + 1) as f32;
+    Vec3::new(team.side() * COURT_HALF_LENGTH * 0.6, 1.8, z)
+}
+
+fn bench_position(team: Team, index: usize) -> Vec3 {
+    let z = -COURT_HALF_WIDTH + 1.0 + index as f32 * BENCH_SPACING;
+    Vec3::new(team.side() * BENCH_X, BENCH_Y, z)
+}
+
+fn spawn_court_lines(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let line = (
+        PbrBundle {
+            mesh: meshes.add(Cuboid::new(0.15, 0.02, COURT_HALF_WIDTH * 2.0)),
+            material: materials.add(StandardMaterial {
+                base_color: Color::WHITE,
+                unlit: true,
+                ..default()
+            }),
+            transform: Transform::from_xyz(0.0, 0.01, 0.0),
+            ..default()
+        },
+        Name::new("CenterLine"),
+    );
+    commands.spawn(line);
+}
+
+// Local players take the first slots on each team, foxes take the rest
+fn fill_teams_with_foxes(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    config: Res<MatchConfig>,
+    local_players: Res<LocalPlayers>,
+) {
+    for team in [Team::Home, Team::Away] {
+        let humans = (0..local_players.count)
+            .filter(|&slot| Team::for_slot(slot) == team)
+            .count();
+        for index in humans..config.team_size {
+            let fox = spawn_fox_player(
+                &mut commands,
+                &assets,
+                court_position(team, index, config.team_size),
+            );
+            commands
+                .entity(fox)
+                .insert((team, Name::new(format!("Fox {} ({:?})", index + 1, team))));
+        }
+    }
+}
+
+// Puts every player back on court and clears the balls away
+fn start_round(
+    mut commands: Commands,
+    config: Res<MatchConfig>,
+    mut game: ResMut<Match>,
+    mut pool: ResMut<BallPool>,
+    mut player_q: Query<(Entity, &Team, &mut Transform, &mut Velocity), With<Player>>,
+    held_q: Query<&HeldBall>,
+    ball_q: Query<Entity, (With<Ball>, Without<RigidBodyDisabled>)>,
+) {
+    if game.state != MatchState::Starting {
+        return;
+    }
+    let mut lined_up = [0, 0];
+    for (player, team, mut transform, mut velocity) in player_q.iter_mut() {
+        let index = &mut lined_up[*team as usize];
+        *transform = Transform::from_translation(court_position(*team, *index, config.team_size))
+            .looking_to(Vec3::X * -team.side(), Vec3::Y);
+        *velocity = Velocity::zero();
+        *index += 1;
+        if let Ok(held_ball) = held_q.get(player) {
+            release_ball(&mut commands, &mut pool, held_ball.ball);
+        }
+        commands
+            .entity(player)
+            .remove::<(
+                Eliminated,
+                HeldBall,
+                HitStun,
+                RigidBodyDisabled,
+                ColliderDisabled,
+            )>()
+            .insert(LockedAxes::ROTATION_LOCKED);
+    }
+    for ball in ball_q.iter() {
+        release_ball(&mut commands, &mut pool, ball);
+    }
+    game.benched.clear();
+    if game.round == 0 {
+        game.secs = 0.0;
+    }
+    game.round += 1;
+    game.state = MatchState::Playing;
+    println!(
+        "Round {}: Home {} - {} Away",
+        game.round,
+        game.wins[Team::Home as usize],
+        game.wins[Team::Away as usize]
+    );
+}
+
+// Benched players can't do anything until they're back in
+fn hold_eliminated_players(mut player_q: Query<&mut PlayerActions, With<Eliminated>>) {
+    for mut actions in player_q.iter_mut() {
+        *actions = PlayerActions::default();
+    }
+}
+
+fn keep_off_center_line(
+    mut player_q: Query<
+        (&Team, &mut Transform, &mut Velocity),
+        (With<Player>, Without<Eliminated>),
+    >,
+) {
+    for (team, mut transform, mut velocity) in player_q.iter_mut() {
+        let side = team.side();
+        if transform.translation.x * side < CENTER_LINE_MARGIN {
+            transform.translation.x = CENTER_LINE_MARGIN * side;
+            // Only stop them moving further over, they can still be pushed back
+            if velocity.linvel.x * side < 0.0 {
+                velocity.linvel.x = 0.0;
+            }
+        }
+    }
+}
+
+fn eliminate(
+    commands: &mut Commands,
+    game: &mut Match,
+    pool: &mut BallPool,
+    player: Entity,
+    team: Team,
+    name: &Name,
+    held_ball: Option<&HeldBall>,
+) {
+    let bench_index = game.benched.len();
+    game.benched.push(player);
+    if let Some(held_ball) = held_ball {
+        release_ball(commands, pool, held_ball.ball);
+    }
+    commands
+        .entity(player)
+        .remove::<(HeldBall, HitStun)>()
+        .insert((
+            Eliminated,
+            RigidBodyDisabled,
+            ColliderDisabled,
+            LockedAxes::ROTATION_LOCKED,
+            Velocity::zero(),
+            Transform::from_translation(bench_position(team, bench_index))
+                .looking_to(Vec3::X * -team.side(), Vec3::Y),
+        ));
+    println!("{} is out", name);
+}
+
+// A thrown ball puts out the first opponent it touches. Touching anything else first makes it dead.
+fn eliminate_on_hit(
+    mut commands: Commands,
+    mut collision_events: EventReader<CollisionEvent>,
+    mut blocked_events: EventWriter<ShieldBlocked>,
+    mut game: ResMut<Match>,
+    mut pool: ResMut<BallPool>,
+    player_q: Query<
+        (&Team, &Name, Option<&HeldBall>, Option<&ActivePowerUp>),
+        (With<Player>, Without<Eliminated>),
+    >,
+    team_q: Query<&Team>,
+    limb_q: Query<&RagdollLimb>,
+    ball_q: Query<&ThrownBy, (With<Ball>, Without<RigidBodyDisabled>)>,
+) {
+    let mut dead = Vec::new();
+    for collision_event in collision_events.read() {
+        let CollisionEvent::Started(entity1, entity2, flags) = collision_event else {
+            continue;
+        };
+        // Sensors, like players' ground sensors, pickups and wind zones, don't stop a throw
+        if flags.contains(CollisionEventFlags::SENSOR) {
+            continue;
+        }
+        let (ball, other) = match (ball_q.contains(*entity1), ball_q.contains(*entity2)) {
+            (true, _) => (*entity1, *entity2),
+            (_, true) => (*entity2, *entity1),
+            _ => continue,
+        };
+        if dead.contains(&ball) {
+            continue;
+        }
+        dead.push(ball);
+        commands.entity(ball).remove::<ThrownBy>();
+
+        if game.state != MatchState::Playing {
+            continue;
+        }
+        // A limp limb is part of the player it hangs off
+        let other = limb_q.get(other).map_or(other, |limb| limb.player);
+        let Ok((team, name, held_ball, power_up)) = player_q.get(other) else {
+            continue;
+        };
+        let thrown_by = ball_q.get(ball).unwrap();
+        if team_q
+            .get(thrown_by.player)
+            .is_ok_and(|thrower_team| thrower_team == team)
+        {
+            continue;
+        }
+        // The same player can be hit by two balls at once
+        if game.benched.contains(&other) {
+            continue;
+        }
+        if has_power_up(power_up, PowerUpKind::Shield) {
+            blocked_events.send(ShieldBlocked { player: other });
+            continue;
+        }
+        eliminate(
+            &mut commands,
+            &mut game,
+            &mut pool,
+            other,
+            *team,
+            name,
+            held_ball,
+        );
+    }
+}
+
+// Catching an opponent's throw puts them out and brings a teammate back in
+fn return_on_catch(
+    mut commands: Commands,
+    mut caught_events: EventReader<BallCaught>,
+    mut game: ResMut<Match>,
+    mut pool: ResMut<BallPool>,
+    player_q: Query<(&Team, &Name, Option<&HeldBall>), With<Player>>,
+    eliminated_q: Query<(), With<Eliminated>>,
+) {
+    for caught in caught_events.read() {
+        let Some(thrower) = caught.thrower else {
+            continue;
+        };
+        if game.state != MatchState::Playing {
+            continue;
+        }
+        let (Ok((&catcher_team, catcher_name, _)), Ok((&thrower_team, thrower_name, held_ball))) =
+            (player_q.get(caught.catcher), player_q.get(thrower))
+        else {
+            continue;
+        };
+        if catcher_team == thrower_team {
+            continue;
+        }
+        println!("{} caught {}'s throw", catcher_name, thrower_name);
+        if !eliminated_q.contains(thrower) && !game.benched.contains(&thrower) {
+            eliminate(
+                &mut commands,
+                &mut game,
+                &mut pool,
+                thrower,
+                thrower_team,
+                thrower_name,
+                held_ball,
+            );
+        }
+
+        let returning = game.benched.iter().position(|&benched| {
+            player_q
+                .get(benched)
+                .is_ok_and(|(&team, ..)| team == catcher_team)
+        });
+        if let Some(index) = returning {
+            let player = game.benched.remove(index);
+            commands
+                .entity(player)
+                .remove::<(Eliminated, RigidBodyDisabled, ColliderDisabled)>()
+                .insert((
+                    Velocity::zero(),
+                    Transform::from_translation(court_position(catcher_team, 0, 1))
+                        .looking_to(Vec3::X * -catcher_team.side(), Vec3::Y),
+                ));
+            if let Ok((_, name, _)) = player_q.get(player) {
+                println!("{} is back in", name);
+            }
+        }
+    }
+}
+
+fn check_round_over(
+    time: Res<Time>,
+    headless: Option<Res<Headless>>,
+    mut exit: EventWriter<AppExit>,
+    mut game: ResMut<Match>,
+    player_q: Query<(&Team, Has<Eliminated>), With<Player>>,
+) {
+    let game = &mut *game;
+    game.secs += time.delta_seconds();
+    match game.state {
+        MatchState::Playing => {
+            let mut in_play = [0, 0];
+            for (team, eliminated) in player_q.iter() {
+                if !eliminated {
+                    in_play[*team as usize] += 1;
+                }
+            }
+            let winner = match in_play {
+                // Nobody on court yet
+                [0, 0] if player_q.is_empty() => return,
+                // Both teams' last players went out together
+                [0, 0] => {
+                    println!(
+                        "Round {} is a draw: Home {} - {} Away",
+                        game.round,
+                        game.wins[Team::Home as usize],
+                        game.wins[Team::Away as usize]
+                    );
+                    game.state = MatchState::RoundOver {
+                        secs_left: ROUND_OVER_SECS,
+                    };
+                    return;
+                }
+                [_, 0] => Team::Home,
+                [0, _] => Team::Away,
+                _ => return,
+            };
+            game.wins[winner as usize] += 1;
+            println!(
+                "{:?} wins round {}: Home {} - {} Away",
+                winner,
+                game.round,
+                game.wins[Team::Home as usize],
+                game.wins[Team::Away as usize]
+            );
+            game.state = if game.wins[winner as usize] >= ROUNDS_TO_WIN {
+                println!("{:?} wins the match!", winner);
+                MatchState::MatchOver {
+                    secs_left: MATCH_OVER_SECS,
+                }
+            } else {
+                MatchState::RoundOver {
+                    secs_left: ROUND_OVER_SECS,
+                }
+            };
+        }
+        MatchState::RoundOver { ref mut secs_left } => {
+            *secs_left -= time.delta_seconds();
+            if *secs_left <= 0.0 {
+                game.state = MatchState::Starting;
+            }
+        }
+        MatchState::MatchOver { ref mut secs_left } => {
+            *secs_left -= time.delta_seconds();
+            if headless.is_some() {
+                exit.send(AppExit::Success);
+            } else if *secs_left <= 0.0 {
+                // Straight into a fresh match
+                game.wins = [0, 0];
+                game.round = 0;
+                game.state = MatchState::Starting;
+            }
+        }
+        MatchState::Starting => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    // A home player's throw on its way to an away player
+    fn throw() -> (World, Entity, Entity) {
+        let mut world = World::new();
+        world.init_resource::<Events<CollisionEvent>>();
+        world.init_resource::<Events<ShieldBlocked>>();
+        world.init_resource::<BallPool>();
+        world.insert_resource(Match {
+            state: MatchState::Playing,
+            ..default()
+        });
+        let thrower = world.spawn((Player, Team::Home, Name::new("Thrower"))).id();
+        let target = world.spawn((Player, Team::Away, Name::new("Target"))).id();
+        let ball = world.spawn((Ball, ThrownBy { player: thrower })).id();
+        (world, ball, target)
+    }
+
+    #[test]
+    fn hits_put_players_out() {
+        let (mut world, ball, target) = throw();
+        world.send_event(CollisionEvent::Started(
+            ball,
+            target,
+            CollisionEventFlags::empty(),
+        ));
+        world.run_system_once(eliminate_on_hit);
+        assert!(world.get::<ThrownBy>(ball).is_none());
+        assert!(world.get::<Eliminated>(target).is_some());
+    }
+
+    #[test]
+    fn shields_stop_a_hit() {
+        let (mut world, ball, target) = throw();
+        world
+            .entity_mut(target)
+            .insert(ActivePowerUp::new(PowerUpKind::Shield));
+        world.send_event(CollisionEvent::Started(
+            ball,
+            target,
+            CollisionEventFlags::empty(),
+        ));
+        world.run_system_once(eliminate_on_hit);
+        assert!(world.get::<Eliminated>(target).is_none());
+        let blocked: Vec<Entity> = world
+            .resource_mut::<Events<ShieldBlocked>>()
+            .drain()
+            .map(|event| event.player)
+            .collect();
+        assert_eq!(blocked, [target]);
+    }
+
+    #[test]
+    fn sensors_dont_stop_a_throw() {
+        let (mut world, ball, target) = throw();
+        let ground_sensor = world.spawn(Sensor).set_parent(target).id();
+        world.send_event(CollisionEvent::Started(
+            ball,
+            ground_sensor,
+            CollisionEventFlags::SENSOR,
+        ));
+        world.run_system_once(eliminate_on_hit);
+        assert!(world.get::<ThrownBy>(ball).is_some());
+        assert!(world.get::<Eliminated>(target).is_none());
+    }
+}
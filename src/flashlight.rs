@@ -0,0 +1,234 @@
+//! Flashlights, and arenas played in the dark.
+//!
+//! Every player carries a flashlight, switched on and off with the flashlight action. In the dark its
+//! battery runs down while it's on, and charges back up while it's off. Low on charge it dims and
+//! flickers, and flat it switches itself off. In daylight the battery charges whatever, and its
+//! charge isn't shown.
+//!
+//! In night arenas, and after dark in a day/night cycle, the flashlights and the launchers' warning
+//! lights are most of what there is to see by. Balls glow then once they get close to a local
+//! player, so nothing hits out of total darkness.
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_rapier3d::prelude::*;
+use rand::prelude::*;
+
+use crate::ball::{Ball, BallAssets, BallKind};
+use crate::camera::PlayerCamera;
+use crate::input::{LocalPlayer, PlayerActions};
+use crate::lighting::Lighting;
+use crate::player::Player;
+
+pub(crate) const FLASHLIGHT_INTENSITY: f32 = 1_000_000.0;
+// Seconds of light on a full battery, and to charge a flat one
+const BATTERY_SECS: f32 = 20.0;
+const RECHARGE_SECS: f32 = 10.0;
+// Below this much charge the light dims and flickers
+const LOW_BATTERY: f32 = 0.2;
+// Chance each frame that a low flashlight cuts out for a moment
+const FLICKER_CHANCE: f64 = 0.08;
+
+// Balls closer than this to a local player glow
+const BALL_GLOW_RADIUS: f32 = 5.0;
+const BALL_GLOW: f32 = 4.0;
+
+pub struct FlashlightPlugin;
+
+impl Plugin for FlashlightPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                (toggle_flashlights, run_down_batteries, shine_flashlights).chain(),
+                glow_nearby_balls.run_if(|lighting: Res<Lighting>| lighting.is_dark()),
+                add_battery_huds,
+                update_battery_huds,
+            ),
+        );
+    }
+}
+
+/// A player's flashlight.
+#[derive(Component, Clone)]
+pub struct Flashlight {
+    pub on: bool,
+    /// 1 is full, 0 is flat
+    pub battery: f32,
+    was_pressed: bool,
+}
+
+impl Default for Flashlight {
+    fn default() -> Self {
+        Self {
+            on: true,
+            battery: 1.0,
+            was_pressed: false,
+        }
+    }
+}
+
+/// Marks the spotlight on a player, lit by its parent's `Flashlight`.
+#[derive(Component)]
+pub struct FlashlightBeam;
+
+/// Text showing a player's battery, shown in the dark while it isn't full.
+#[derive(Component)]
+struct BatteryHud {
+    player: Entity,
+}
+
+fn toggle_flashlights(mut player_q: Query<(&PlayerActions, &mut Flashlight)>) {
+    for (actions, mut flashlight) in player_q.iter_mut() {
+        let pressed = actions.flashlight && !flashlight.was_pressed;
+        flashlight.was_pressed = actions.flashlight;
+        // A flat flashlight won't come on
+        if pressed && (flashlight.on || flashlight.battery > 0.0) {
+            flashlight.on = !flashlight.on;
+        }
+    }
+}
+
+fn run_down_batteries(
+    time: Res<Time>,
+    lighting: Res<Lighting>,
+    mut flashlight_q: Query<&mut Flashlight>,
+) {
+    let delta_time = time.delta_seconds();
+    let dark = lighting.is_dark();
+    for mut flashlight in flashlight_q.iter_mut() {
+        if flashlight.on && dark {
+            flashlight.battery = (flashlight.battery - delta_time / BATTERY_SECS).max(0.0);
+            if flashlight.battery == 0.0 {
+                flashlight.on = false;
+            }
+        } else {
+            flashlight.battery = (flashlight.battery + delta_time / RECHARGE_SECS).min(1.0);
+        }
+    }
+}
+
+fn shine_flashlights(
+    flashlight_q: Query<&Flashlight>,
+    mut beam_q: Query<(&Parent, &mut SpotLight), With<FlashlightBeam>>,
+) {
+    let mut rng = thread_rng();
+    for (parent, mut spot_light) in beam_q.iter_mut() {
+        let Ok(flashlight) = flashlight_q.get(parent.get()) else {
+            continue;
+        };
+        let level = if !flashlight.on {
+            0.0
+        } else if flashlight.battery < LOW_BATTERY {
+            let dim = 0.3 + 0.7 * flashlight.battery / LOW_BATTERY;
+            if rng.gen_bool(FLICKER_CHANCE) {
+                dim * 0.1
+            } else {
+                dim
+            }
+        } else {
+            1.0
+        };
+        spot_light.intensity = FLASHLIGHT_INTENSITY * level;
+    }
+}
+
+// Swaps nearby balls onto a glowing copy of their kind's material, and back once they're away
+fn glow_nearby_balls(
+    mut glow_materials: Local<HashMap<BallKind, Handle<StandardMaterial>>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    ball_assets: Option<Res<BallAssets>>,
+    player_q: Query<&Transform, (With<Player>, With<LocalPlayer>)>,
+    mut ball_q: Query<
+        (&Transform, &BallKind, &mut Handle<StandardMaterial>),
+        (With<Ball>, Without<RigidBodyDisabled>),
+    >,
+) {
+    let Some(ball_assets) = ball_assets else {
+        return;
+    };
+    for (transform, kind, mut material) in ball_q.iter_mut() {
+        let (_, normal) = ball_assets.look(*kind);
+        let near = player_q
+            .iter()
+            .any(|player| player.translation.distance(transform.translation) < BALL_GLOW_RADIUS);
+        let wanted = if near {
+            glow_materials
+                .entry(*kind)
+                .or_insert_with(|| {
+                    let mut glowing = materials.get(&normal).cloned().unwrap_or_default();
+                    glowing.emissive = glowing.base_color.to_linear() * BALL_GLOW;
+                    materials.add(glowing)
+                })
+                .clone()
+        } else {
+            normal
+        };
+        if *material != wanted {
+            *material = wanted;
+        }
+    }
+}
+
+fn add_battery_huds(
+    mut commands: Commands,
+    camera_q: Query<(Entity, &PlayerCamera), (Added<PlayerCamera>, With<Camera>)>,
+) {
+    for (camera, player_camera) in camera_q.iter() {
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        right: Val::Px(12.0),
+                        bottom: Val::Px(12.0),
+                        ..default()
+                    },
+                    ..default()
+                },
+                TargetCamera(camera),
+                Name::new("BatteryHud"),
+            ))
+            .with_children(|parent| {
+                parent.spawn((
+                    TextBundle::from_section(
+                        "",
+                        TextStyle {
+                            font_size: 24.0,
+                            ..default()
+                        },
+                    ),
+                    BatteryHud {
+                        player: player_camera.player,
+                    },
+                ));
+            });
+    }
+}
+
+fn update_battery_huds(
+    lighting: Res<Lighting>,
+    mut hud_q: Query<(&BatteryHud, &mut Text)>,
+    flashlight_q: Query<&Flashlight>,
+) {
+    let dark = lighting.is_dark();
+    for (hud, mut text) in hud_q.iter_mut() {
+        let section = &mut text.sections[0];
+        match flashlight_q.get(hud.player) {
+            Ok(flashlight) if dark && (flashlight.battery < 1.0 || !flashlight.on) => {
+                let state = if flashlight.on { "" } else { " off" };
+                section.value = format!(
+                    "Flashlight{} {:.0}%",
+                    state,
+                    (flashlight.battery * 100.0).floor()
+                );
+                section.style.color = if flashlight.battery < LOW_BATTERY {
+                    Srgba::hex("#e63946").unwrap().into()
+                } else {
+                    Srgba::rgba_u8(216, 224, 69, 255).into()
+                };
+            }
+            _ => section.value.clear(),
+        }
+    }
+}
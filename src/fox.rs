@@ -1,8 +1,12 @@
-//! Plays animations from a skinned glTF.
+//! Plays animations from a skinned glTF, and spawns the fox bots that fill empty dodgeball slots.
 
 use std::f32::consts::PI;
 use std::time::Duration;
 
+use crate::bot::Bot;
+use crate::lock_on::LockOnTarget;
+use crate::player::spawn_player;
+use crate::player_animation::{claim_rigs, PlayerRig};
 use bevy::{
     animation::{animate_targets, RepeatAnimation},
     pbr::CascadeShadowConfigBuilder,
@@ -13,8 +17,12 @@ pub struct FoxPlugin;
 
 impl Plugin for FoxPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup)
-            .add_systems(Update, setup_scene_once_loaded.before(animate_targets));
+        app.add_systems(Startup, setup).add_systems(
+            Update,
+            setup_scene_once_loaded
+                .after(claim_rigs)
+                .before(animate_targets),
+        );
         // .add_systems(Update, keyboard_animation_control);
     }
 }
@@ -66,6 +74,10 @@ fn setup(
             ..default()
         },
         Name::new("Fox"),
+        // Aim for its middle rather than its feet
+        LockOnTarget {
+            offset: Vec3::Y * 1.0,
+        },
     ));
 
     println!("Animation controls:");
@@ -77,11 +89,40 @@ fn setup(
     println!("  - return: change animation");
 }
 
+/// Spawns a player body that looks like a fox and is played by a bot.
+pub fn spawn_fox_player(
+    commands: &mut Commands,
+    assets: &AssetServer,
+    translation: Vec3,
+) -> Entity {
+    let fox = spawn_player(commands, assets, translation);
+    let model = SceneBundle {
+        scene: assets.load(GltfAssetLabel::Scene(0).from_asset("models/animated/Fox.glb")),
+        // Down to the bottom of the player's collider, facing the way the player does
+        transform: Transform::from_xyz(0.0, -0.8, 0.0)
+            .with_rotation(Quat::from_rotation_y(PI))
+            .with_scale(Vec3::splat(0.02)),
+        ..default()
+    };
+    commands
+        .entity(fox)
+        .remove::<Handle<Scene>>()
+        .insert(Bot::default())
+        .with_children(|parent| {
+            parent.spawn(model);
+        });
+    fox
+}
+
 // Once the scene is loaded, start the animation
 fn setup_scene_once_loaded(
     mut commands: Commands,
     animations: Res<Animations>,
-    mut players: Query<(Entity, &mut AnimationPlayer), Added<AnimationPlayer>>,
+    // Player models animate themselves
+    mut players: Query<
+        (Entity, &mut AnimationPlayer),
+        (Added<AnimationPlayer>, Without<PlayerRig>),
+    >,
 ) {
     for (entity, mut player) in &mut players {
         let mut transitions = AnimationTransitions::new();
@@ -0,0 +1,500 @@
+//! Hazards around the arena, laid out in the arena's file.
+//!
+//! - Sweepers are arms spinning round their middle, shoving whatever they hit.
+//! - Pressure plates go down while a player or ball is on them.
+//! - Launchers wind up when triggered, then fire a ball.
+//! - Trapdoors drop open for a while when triggered.
+//! - Wind zones push players and balls inside them along.
+//!
+//! Hazards talk to each other through `HazardTrigger` events on named channels. A pressure plate
+//! sends one when it goes down and another when it comes back up, and everything listening on the
+//! same channel reacts. Sweepers and wind zones without a channel run all the time. With one, a
+//! sweeper starts or stops each time it's triggered, and a wind zone blows while its plate is down.
+//!
+//! Hazards move with the simulation, so they stand still in rollback games, where they aren't part
+//! of the snapshots. Network clients leave launching balls to the server.
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use serde::Deserialize;
+
+use crate::arena::Arena;
+use crate::ball::{launch_ball, Ball, BallAssets, BallKind, BallPool};
+use crate::net::NetMode;
+use crate::particles::{Effect, SpawnEffect};
+use crate::player::Player;
+use crate::telegraph::Telegraph;
+use crate::world::{Ground, SimulationSet};
+
+const SWEEPER_THICKNESS: f32 = 0.3;
+const PLATE_THICKNESS: f32 = 0.05;
+// Plates notice anything up to this far above them
+const PLATE_SENSOR_HEIGHT: f32 = 0.3;
+const TRAPDOOR_THICKNESS: f32 = 0.2;
+const LAUNCHER_SIZE: f32 = 0.6;
+// Balls leave a launcher this far in front of it, clear of its own collider
+const LAUNCHER_MUZZLE_DISTANCE: f32 = 0.8;
+
+pub struct HazardPlugin;
+
+impl Plugin for HazardPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<HazardTrigger>()
+            .add_systems(Startup, spawn_hazards)
+            .add_systems(
+                Update,
+                (
+                    press_plates,
+                    fire_launchers.run_if(|net: Option<Res<NetMode>>| {
+                        !matches!(net.as_deref(), Some(NetMode::Client(_)))
+                    }),
+                    open_trapdoors,
+                    sweep,
+                    blow_wind,
+                )
+                    .chain()
+                    .in_set(SimulationSet),
+            )
+            .add_systems(Update, draw_wind);
+    }
+}
+
+/// One hazard in an arena's file. Positions are where the middle of it goes.
+#[derive(Deserialize, Clone, Debug)]
+pub enum HazardConfig {
+    Sweeper {
+        position: [f32; 3],
+        length: f32,
+        /// Radians per second, anticlockwise seen from above
+        angular_speed: f32,
+        #[serde(default)]
+        channel: Option<String>,
+    },
+    PressurePlate {
+        position: [f32; 3],
+        /// Width along x and depth along z
+        size: [f32; 2],
+        channel: String,
+    },
+    Launcher {
+        position: [f32; 3],
+        direction: [f32; 3],
+        speed: f32,
+        #[serde(default)]
+        kind: BallKind,
+        channel: String,
+        /// How long it winds up before firing, if not the arena's `launcher_lead_secs`
+        #[serde(default)]
+        lead_secs: Option<f32>,
+    },
+    Trapdoor {
+        position: [f32; 3],
+        /// Width along x and depth along z
+        size: [f32; 2],
+        open_secs: f32,
+        channel: String,
+    },
+    WindZone {
+        position: [f32; 3],
+        half_extents: [f32; 3],
+        /// Metres per second squared, the same for everything however heavy it is
+        acceleration: [f32; 3],
+        #[serde(default)]
+        channel: Option<String>,
+    },
+}
+
+/// Something happened on a channel. Pressure plates send these, and any hazard can listen for them.
+#[derive(Event, Clone, Debug)]
+pub struct HazardTrigger {
+    pub channel: String,
+    /// Whether the plate went down, rather than coming back up
+    pub pressed: bool,
+}
+
+#[derive(Component)]
+pub struct Sweeper {
+    pub angular_speed: f32,
+    pub channel: Option<String>,
+    pub running: bool,
+}
+
+#[derive(Component)]
+pub struct PressurePlate {
+    pub channel: String,
+    pub pressed: bool,
+}
+
+/// Its aim and wind-up are in its `Telegraph`.
+#[derive(Component)]
+pub struct Launcher {
+    pub channel: String,
+    pub kind: BallKind,
+}
+
+#[derive(Component)]
+pub struct Trapdoor {
+    pub channel: String,
+    pub open_secs: f32,
+    /// Counts down while the trapdoor is open
+    pub open_timer: Option<Timer>,
+}
+
+#[derive(Component)]
+pub struct WindZone {
+    pub acceleration: Vec3,
+    pub half_extents: Vec3,
+    pub channel: Option<String>,
+    pub blowing: bool,
+}
+
+/// Pressure plate materials, swapped as plates go up and down.
+#[derive(Resource)]
+struct PlateMaterials {
+    up: Handle<StandardMaterial>,
+    down: Handle<StandardMaterial>,
+}
+
+fn spawn_hazards(
+    mut commands: Commands,
+    arena: Res<Arena>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let mut material = |hex: &str| {
+        materials.add(StandardMaterial {
+            base_color: Srgba::hex(hex).unwrap().into(),
+            metallic: 0.2,
+            perceptual_roughness: 0.7,
+            ..default()
+        })
+    };
+    let sweeper_material = material("#e85d04");
+    let launcher_material = material("#343a40");
+    let trapdoor_material = material("#7f5539");
+    let plate_materials = PlateMaterials {
+        up: material("#adb5bd"),
+        down: material("#52b788"),
+    };
+
+    for hazard in arena.config.hazards.iter().cloned() {
+        match hazard {
+            HazardConfig::Sweeper {
+                position,
+                length,
+                angular_speed,
+                channel,
+            } => {
+                commands.spawn((
+                    PbrBundle {
+                        mesh: meshes.add(Cuboid::new(length, SWEEPER_THICKNESS, SWEEPER_THICKNESS)),
+                        material: sweeper_material.clone(),
+                        transform: Transform::from_translation(position.into()),
+                        ..default()
+                    },
+                    RigidBody::KinematicPositionBased,
+                    Collider::cuboid(
+                        length / 2.0,
+                        SWEEPER_THICKNESS / 2.0,
+                        SWEEPER_THICKNESS / 2.0,
+                    ),
+                    Sweeper {
+                        angular_speed,
+                        running: channel.is_none(),
+                        channel,
+                    },
+                    Name::new("Sweeper"),
+                ));
+            }
+            HazardConfig::PressurePlate {
+                position,
+                size,
+                channel,
+            } => {
+                commands.spawn((
+                    PbrBundle {
+                        mesh: meshes.add(Cuboid::new(size[0], PLATE_THICKNESS, size[1])),
+                        material: plate_materials.up.clone(),
+                        transform: Transform::from_translation(position.into()),
+                        ..default()
+                    },
+                    Collider::cuboid(size[0] / 2.0, PLATE_SENSOR_HEIGHT, size[1] / 2.0),
+                    Sensor,
+                    PressurePlate {
+                        channel,
+                        pressed: false,
+                    },
+                    Name::new("PressurePlate"),
+                ));
+            }
+            HazardConfig::Launcher {
+                position,
+                direction,
+                speed,
+                kind,
+                channel,
+                lead_secs,
+            } => {
+                let direction = Vec3::from(direction).normalize_or(Vec3::X);
+                let position = Vec3::from(position);
+                commands.spawn((
+                    PbrBundle {
+                        mesh: meshes.add(Cuboid::from_length(LAUNCHER_SIZE)),
+                        material: launcher_material.clone(),
+                        transform: Transform::from_translation(position)
+                            .looking_to(direction, Vec3::Y),
+                        ..default()
+                    },
+                    Collider::cuboid(
+                        LAUNCHER_SIZE / 2.0,
+                        LAUNCHER_SIZE / 2.0,
+                        LAUNCHER_SIZE / 2.0,
+                    ),
+                    Launcher { channel, kind },
+                    Telegraph {
+                        remaining_secs: None,
+                        lead_secs: lead_secs.unwrap_or(arena.config.launcher_lead_secs),
+                        kind,
+                        muzzle: position + direction * LAUNCHER_MUZZLE_DISTANCE,
+                        velocity: direction * speed * kind.speed_scale(),
+                    },
+                    Name::new("Launcher"),
+                ));
+            }
+            HazardConfig::Trapdoor {
+                position,
+                size,
+                open_secs,
+                channel,
+            } => {
+                commands.spawn((
+                    PbrBundle {
+                        mesh: meshes.add(Cuboid::new(size[0], TRAPDOOR_THICKNESS, size[1])),
+                        material: trapdoor_material.clone(),
+                        transform: Transform::from_translation(position.into()),
+                        ..default()
+                    },
+                    Collider::cuboid(size[0] / 2.0, TRAPDOOR_THICKNESS / 2.0, size[1] / 2.0),
+                    Ground,
+                    Trapdoor {
+                        channel,
+                        open_secs,
+                        open_timer: None,
+                    },
+                    Name::new("Trapdoor"),
+                ));
+            }
+            HazardConfig::WindZone {
+                position,
+                half_extents,
+                acceleration,
+                channel,
+            } => {
+                let half_extents = Vec3::from(half_extents);
+                commands.spawn((
+                    TransformBundle::from(Transform::from_translation(position.into())),
+                    Collider::cuboid(half_extents.x, half_extents.y, half_extents.z),
+                    Sensor,
+                    WindZone {
+                        acceleration: acceleration.into(),
+                        half_extents,
+                        blowing: channel.is_none(),
+                        channel,
+                    },
+                    Name::new("WindZone"),
+                ));
+            }
+        }
+    }
+    commands.insert_resource(plate_materials);
+}
+
+// Everything a sensor is touching right now
+fn touching(rapier_context: &RapierContext, sensor: Entity) -> impl Iterator<Item = Entity> + '_ {
+    rapier_context
+        .intersection_pairs_with(sensor)
+        .filter(|(_, _, intersecting)| *intersecting)
+        .map(move |(entity1, entity2, _)| if entity1 == sensor { entity2 } else { entity1 })
+}
+
+fn press_plates(
+    rapier_context: Res<RapierContext>,
+    plate_materials: Res<PlateMaterials>,
+    mut plate_q: Query<(Entity, &mut PressurePlate, &mut Handle<StandardMaterial>)>,
+    body_q: Query<(), Or<(With<Player>, With<Ball>)>>,
+    mut triggers: EventWriter<HazardTrigger>,
+) {
+    for (plate, mut pressure_plate, mut material) in plate_q.iter_mut() {
+        let pressed = touching(&rapier_context, plate).any(|entity| body_q.contains(entity));
+        if pressed == pressure_plate.pressed {
+            continue;
+        }
+        pressure_plate.pressed = pressed;
+        *material = if pressed {
+            plate_materials.down.clone()
+        } else {
+            plate_materials.up.clone()
+        };
+        triggers.send(HazardTrigger {
+            channel: pressure_plate.channel.clone(),
+            pressed,
+        });
+    }
+}
+
+fn fire_launchers(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut triggers: EventReader<HazardTrigger>,
+    mut pool: ResMut<BallPool>,
+    ball_assets: Res<BallAssets>,
+    mut effects: EventWriter<SpawnEffect>,
+    mut launcher_q: Query<(&Launcher, &mut Telegraph)>,
+) {
+    let triggered: Vec<String> = triggers
+        .read()
+        .filter(|trigger| trigger.pressed)
+        .map(|trigger| trigger.channel.clone())
+        .collect();
+    for (launcher, mut telegraph) in launcher_q.iter_mut() {
+        // Triggering a launcher that's already winding up doesn't start it over
+        if telegraph.remaining_secs.is_none() && triggered.contains(&launcher.channel) {
+            telegraph.remaining_secs = Some(telegraph.lead_secs);
+        }
+        let Some(remaining) = telegraph.remaining_secs.as_mut() else {
+            continue;
+        };
+        *remaining -= time.delta_seconds();
+        if *remaining > 0.0 {
+            continue;
+        }
+        telegraph.remaining_secs = None;
+        launch_ball(
+            &mut commands,
+            &mut pool,
+            &ball_assets,
+            launcher.kind,
+            Transform::from_translation(telegraph.muzzle),
+            Velocity {
+                linvel: telegraph.velocity,
+                angvel: launcher.kind.launch_angvel(),
+            },
+        );
+        effects.send(SpawnEffect {
+            effect: Effect::MuzzleBurst,
+            position: telegraph.muzzle,
+            direction: telegraph.velocity,
+            strength: 1.0,
+        });
+    }
+}
+
+fn open_trapdoors(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut triggers: EventReader<HazardTrigger>,
+    mut trapdoor_q: Query<(Entity, &mut Trapdoor)>,
+) {
+    let opened: Vec<String> = triggers
+        .read()
+        .filter(|trigger| trigger.pressed)
+        .map(|trigger| trigger.channel.clone())
+        .collect();
+    for (entity, mut trapdoor) in trapdoor_q.iter_mut() {
+        if opened.contains(&trapdoor.channel) {
+            // Triggering an open trapdoor keeps it open for longer
+            if trapdoor.open_timer.is_none() {
+                commands
+                    .entity(entity)
+                    .insert((ColliderDisabled, Visibility::Hidden));
+            }
+            trapdoor.open_timer = Some(Timer::from_seconds(trapdoor.open_secs, TimerMode::Once));
+            continue;
+        }
+        let Some(timer) = trapdoor.open_timer.as_mut() else {
+            continue;
+        };
+        timer.tick(time.delta());
+        if timer.finished() {
+            trapdoor.open_timer = None;
+            commands
+                .entity(entity)
+                .remove::<ColliderDisabled>()
+                .insert(Visibility::Inherited);
+        }
+    }
+}
+
+fn sweep(
+    time: Res<Time>,
+    mut triggers: EventReader<HazardTrigger>,
+    mut sweeper_q: Query<(&mut Sweeper, &mut Transform)>,
+) {
+    let toggled: Vec<String> = triggers
+        .read()
+        .filter(|trigger| trigger.pressed)
+        .map(|trigger| trigger.channel.clone())
+        .collect();
+    for (mut sweeper, mut transform) in sweeper_q.iter_mut() {
+        if sweeper
+            .channel
+            .as_ref()
+            .is_some_and(|channel| toggled.contains(channel))
+        {
+            sweeper.running = !sweeper.running;
+        }
+        if sweeper.running {
+            transform.rotate_y(sweeper.angular_speed * time.delta_seconds());
+        }
+    }
+}
+
+fn blow_wind(
+    time: Res<Time>,
+    rapier_context: Res<RapierContext>,
+    mut triggers: EventReader<HazardTrigger>,
+    mut zone_q: Query<(Entity, &mut WindZone)>,
+    mut body_q: Query<&mut Velocity, Or<(With<Player>, With<Ball>)>>,
+) {
+    let triggers: Vec<HazardTrigger> = triggers.read().cloned().collect();
+    for (zone, mut wind) in zone_q.iter_mut() {
+        if let Some(trigger) = triggers
+            .iter()
+            .filter(|trigger| wind.channel.as_ref() == Some(&trigger.channel))
+            .last()
+        {
+            wind.blowing = trigger.pressed;
+        }
+        if !wind.blowing {
+            continue;
+        }
+        for entity in touching(&rapier_context, zone) {
+            if let Ok(mut velocity) = body_q.get_mut(entity) {
+                velocity.linvel += wind.acceleration * time.delta_seconds();
+            }
+        }
+    }
+}
+
+// Wind zones have no mesh, so show which way they blow
+fn draw_wind(mut gizmos: Gizmos, zone_q: Query<(&WindZone, &Transform)>) {
+    for (wind, transform) in zone_q.iter() {
+        let color = if wind.blowing {
+            Srgba::hex("#90e0ef").unwrap()
+        } else {
+            Srgba::hex("#90e0ef").unwrap().with_alpha(0.2)
+        };
+        gizmos.cuboid(
+            Transform::from_translation(transform.translation).with_scale(wind.half_extents * 2.0),
+            color,
+        );
+        if wind.blowing {
+            let along = wind.acceleration.normalize_or_zero() * wind.half_extents.min_element();
+            gizmos.arrow(
+                transform.translation - along,
+                transform.translation + along,
+                color,
+            );
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+
+use crate::settings::{Settings, SettingsMenu};
+use crate::spectator::Spectator;
+
+pub struct InputPlugin;
+
+impl Plugin for InputPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PreUpdate, read_local_input.in_set(PlayerInputSet));
+    }
+}
+
+/// Everything that fills in `PlayerActions` runs in here, so gameplay can read them in `Update`.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PlayerInputSet;
+
+/// What a player wants to do this frame, whoever is driving them.
+#[derive(Component, Default, Debug)]
+pub struct PlayerActions {
+    /// x is right, y is forward, relative to the player's camera. Length is at most 1.
+    pub movement: Vec2,
+    /// Camera orbit, in radians this frame
+    pub look: Vec2,
+    pub jump: bool,
+    /// Held to catch a ball coming at us, or pick up a loose one
+    pub catch: bool,
+    /// Throws the ball we're holding
+    pub throw: bool,
+    /// A quick burst of speed the way the player is facing
+    pub dash: bool,
+    /// Locks on to the opponent we're looking at, or lets go of them
+    pub lock_on: bool,
+    /// Moves a lock on to the next opponent round
+    pub cycle_target: bool,
+    /// Switches the flashlight on or off
+    pub flashlight: bool,
+}
+
+/// The physical device a local player is using.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InputDevice {
+    /// WASD to move, E to jump, mouse to look, right click to catch, left click to throw,
+    /// left shift to dash, Q to lock on, tab to change target, F for the flashlight
+    KeyboardMouse,
+    /// Arrow keys to move, right ctrl to jump, right shift to catch, enter to throw,
+    /// right alt to dash, end to lock on, page down to change target, page up for the flashlight,
+    /// so two people can share a keyboard
+    KeyboardArrows,
+    /// The nth connected gamepad, left stick to move, right stick to look, south button to jump,
+    /// left trigger to catch, right trigger to throw, east button to dash, right stick click to
+    /// lock on, right bumper to change target, d-pad up for the flashlight
+    Gamepad(usize),
+}
+
+impl InputDevice {
+    pub fn for_slot(slot: usize) -> Self {
+        match slot {
+            0 => InputDevice::KeyboardMouse,
+            1 => InputDevice::KeyboardArrows,
+            n => InputDevice::Gamepad(n - 2),
+        }
+    }
+}
+
+/// A player controlled by someone sitting at this machine.
+#[derive(Component)]
+pub struct LocalPlayer {
+    pub slot: usize,
+    pub device: InputDevice,
+}
+
+const MOUSE_LOOK_SENSITIVITY: f32 = 0.005;
+const GAMEPAD_LOOK_SPEED: f32 = 3.0;
+
+pub(crate) fn key_axis(keys: &ButtonInput<KeyCode>, negative: KeyCode, positive: KeyCode) -> f32 {
+    let mut axis = 0.0;
+    if keys.pressed(negative) {
+        axis -= 1.0;
+    }
+    if keys.pressed(positive) {
+        axis += 1.0;
+    }
+    axis
+}
+
+fn read_local_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    time: Res<Time>,
+    settings: Res<Settings>,
+    menu: Res<SettingsMenu>,
+    spectator: Option<Res<Spectator>>,
+    mut player_q: Query<(&LocalPlayer, &mut PlayerActions)>,
+) {
+    let mouse_delta: Vec2 = mouse_motion.read().map(|motion| motion.delta).sum();
+    // Gamepads are handed out in the order they were connected
+    let mut connected: Vec<Gamepad> = gamepads.iter().collect();
+    connected.sort_by_key(|gamepad| gamepad.id);
+
+    for (local_player, mut actions) in player_q.iter_mut() {
+        // The keyboard and mouse belong to the settings menu or the spectator camera while they're up
+        if (menu.open || spectator.is_some())
+            && !matches!(local_player.device, InputDevice::Gamepad(_))
+        {
+            *actions = PlayerActions::default();
+            continue;
+        }
+        *actions = match local_player.device {
+            InputDevice::KeyboardMouse => PlayerActions {
+                movement: Vec2::new(
+                    key_axis(&keys, KeyCode::KeyA, KeyCode::KeyD),
+                    key_axis(&keys, KeyCode::KeyS, KeyCode::KeyW),
+                )
+                .normalize_or_zero(),
+                look: mouse_delta * MOUSE_LOOK_SENSITIVITY * settings.look_scale(),
+                jump: keys.pressed(KeyCode::KeyE),
+                catch: mouse_buttons.pressed(MouseButton::Right),
+                throw: mouse_buttons.pressed(MouseButton::Left),
+                dash: keys.pressed(KeyCode::ShiftLeft),
+                lock_on: keys.pressed(KeyCode::KeyQ),
+                cycle_target: keys.pressed(KeyCode::Tab),
+                flashlight: keys.pressed(KeyCode::KeyF),
+            },
+            InputDevice::KeyboardArrows => PlayerActions {
+                movement: Vec2::new(
+                    key_axis(&keys, KeyCode::ArrowLeft, KeyCode::ArrowRight),
+                    key_axis(&keys, KeyCode::ArrowDown, KeyCode::ArrowUp),
+                )
+                .normalize_or_zero(),
+                look: Vec2::ZERO,
+                jump: keys.pressed(KeyCode::ControlRight),
+                catch: keys.pressed(KeyCode::ShiftRight),
+                throw: keys.pressed(KeyCode::Enter),
+                dash: keys.pressed(KeyCode::AltRight),
+                lock_on: keys.pressed(KeyCode::End),
+                cycle_target: keys.pressed(KeyCode::PageDown),
+                flashlight: keys.pressed(KeyCode::PageUp),
+            },
+            InputDevice::Gamepad(index) => match connected.get(index) {
+                Some(&gamepad) => {
+                    let button = |button_type| {
+                        gamepad_buttons.pressed(GamepadButton::new(gamepad, button_type))
+                    };
+                    let axis = |axis_type| {
+                        gamepad_axes
+                            .get(GamepadAxis::new(gamepad, axis_type))
+                            .unwrap_or(0.0)
+                    };
+                    PlayerActions {
+                        movement: Vec2::new(
+                            axis(GamepadAxisType::LeftStickX),
+                            axis(GamepadAxisType::LeftStickY),
+                        )
+                        .clamp_length_max(1.0),
+                        look: Vec2::new(
+                            axis(GamepadAxisType::RightStickX),
+                            -axis(GamepadAxisType::RightStickY),
+                        ) * GAMEPAD_LOOK_SPEED
+                            * settings.look_scale()
+                            * time.delta_seconds(),
+                        jump: button(GamepadButtonType::South),
+                        catch: button(GamepadButtonType::LeftTrigger2),
+                        throw: button(GamepadButtonType::RightTrigger2),
+                        dash: button(GamepadButtonType::East),
+                        lock_on: button(GamepadButtonType::RightThumb),
+                        cycle_target: button(GamepadButtonType::RightTrigger),
+                        flashlight: button(GamepadButtonType::DPadUp),
+                    }
+                }
+                None => PlayerActions::default(),
+            },
+        };
+    }
+}
@@ -0,0 +1,343 @@
+//! The sun, the sky, ambient light and fog, set per arena.
+//!
+//! An arena's `lighting` picks a preset and can override any part of it. Without one, arenas get
+//! the day preset, or the night one if they're a night arena. The sun's direction is given as an
+//! elevation above the horizon and an azimuth, both in degrees, with azimuth measured round from
+//! +x towards +z.
+//!
+//! With a `day_night` cycle the sun goes round over `day_secs`, rising at the azimuth and peaking at
+//! the preset's elevation. In a dodgeball match the day starts over with each match, otherwise it
+//! runs from when the game started. It fades and reddens towards the horizon, and once it's down
+//! a dim moon lights the arena from the other side, so the flashlights come into play.
+
+use bevy::pbr::{CascadeShadowConfigBuilder, FogFalloff};
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::f32::consts::TAU;
+
+use crate::arena::{Arena, ArenaConfig};
+use crate::dodgeball::Match;
+
+const SUN_DISTANCE: f32 = 50.0;
+// How high above the horizon the sun is when it's at full strength, as a sine of its elevation
+const FULL_DAYLIGHT: f32 = 0.25;
+// Below this much daylight it counts as dark
+const DARK_BELOW: f32 = 0.3;
+const SUNSET_COLOR: &str = "#ff7b3a";
+
+pub struct LightingPlugin;
+
+impl Plugin for LightingPlugin {
+    fn build(&self, app: &mut App) {
+        let lighting = Lighting::from_arena(&app.world().resource::<Arena>().config);
+        app.insert_resource(ClearColor(lighting.sky_color))
+            .insert_resource(AmbientLight {
+                color: lighting.ambient_color,
+                brightness: lighting.ambient_brightness,
+            })
+            .insert_resource(lighting)
+            .add_systems(Startup, spawn_sun)
+            .add_systems(
+                Update,
+                (
+                    add_fog,
+                    turn_day_and_night
+                        .run_if(|lighting: Res<Lighting>| lighting.day_night.is_some()),
+                ),
+            );
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LightingPreset {
+    /// Warm afternoon sun
+    Day,
+    /// Low orange sun and a haze
+    Dusk,
+    /// Moonlight, barely enough to see by
+    Night,
+    /// Flat grey light and thick fog
+    Overcast,
+}
+
+/// Fog that fades in between `start` and `end` metres from the camera.
+#[derive(Deserialize, Clone, Debug)]
+pub struct FogConfig {
+    pub color: String,
+    pub start: f32,
+    pub end: f32,
+}
+
+/// How the sun's shadows are split up with distance.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct ShadowCascades {
+    pub count: usize,
+    /// Where the first, sharpest cascade ends
+    pub first_bound: f32,
+    /// No shadows past this
+    pub max_distance: f32,
+}
+
+impl Default for ShadowCascades {
+    fn default() -> Self {
+        Self {
+            count: 4,
+            first_bound: 5.0,
+            max_distance: 1000.0,
+        }
+    }
+}
+
+/// The sun going round.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct DayNightCycle {
+    /// Seconds for a whole day and night
+    pub day_secs: f32,
+    /// How far through the day it starts, 0 at sunrise, 0.25 at noon, 0.5 at sunset
+    pub start: f32,
+}
+
+impl Default for DayNightCycle {
+    fn default() -> Self {
+        Self {
+            day_secs: 300.0,
+            start: 0.1,
+        }
+    }
+}
+
+/// An arena's lighting as written in its file. Anything left out comes from the preset.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct LightingConfig {
+    pub preset: Option<LightingPreset>,
+    pub sun_elevation: Option<f32>,
+    pub sun_azimuth: Option<f32>,
+    pub sun_color: Option<String>,
+    /// Lux
+    pub sun_illuminance: Option<f32>,
+    pub ambient_color: Option<String>,
+    pub ambient_brightness: Option<f32>,
+    pub sky_color: Option<String>,
+    pub fog: Option<FogConfig>,
+    pub shadow_cascades: Option<ShadowCascades>,
+    pub day_night: Option<DayNightCycle>,
+}
+
+/// The lighting in play, with the preset and the arena's overrides worked out.
+#[derive(Resource, Clone, Debug)]
+pub struct Lighting {
+    pub sun_elevation: f32,
+    pub sun_azimuth: f32,
+    pub sun_color: Color,
+    pub sun_illuminance: f32,
+    pub ambient_color: Color,
+    pub ambient_brightness: f32,
+    pub sky_color: Color,
+    pub fog: Option<(Color, f32, f32)>,
+    pub shadow_cascades: ShadowCascades,
+    pub day_night: Option<DayNightCycle>,
+    /// 1 in full sun, 0 once it's down. Only changes with a day/night cycle.
+    pub daylight: f32,
+    // A night arena, or lit with the night preset
+    night: bool,
+}
+
+/// Marks the arena's directional light.
+#[derive(Component)]
+pub struct Sun;
+
+fn hex(text: &str) -> Color {
+    Srgba::hex(text)
+        .unwrap_or_else(|_| {
+            println!("Ignoring bad lighting colour {:?}", text);
+            Srgba::WHITE
+        })
+        .into()
+}
+
+impl LightingPreset {
+    fn lighting(self) -> Lighting {
+        let (elevation, azimuth, sun_color, illuminance, ambient_color, ambient, sky) = match self {
+            LightingPreset::Day => (35.3, 45.0, "#897b2b", 1_000.0, "#ffffff", 80.0, "#2b2c2f"),
+            LightingPreset::Dusk => (8.0, 200.0, "#ff8c42", 600.0, "#ffb38a", 40.0, "#3d2a3a"),
+            LightingPreset::Night => (50.0, 120.0, "#9bb4ff", 5.0, "#8fa3ff", 2.0, "#03030a"),
+            LightingPreset::Overcast => (60.0, 45.0, "#c9d1d9", 400.0, "#dfe6ee", 150.0, "#8d99a6"),
+        };
+        let fog = match self {
+            LightingPreset::Day => None,
+            LightingPreset::Dusk => Some(("#6b4a5a", 25.0, 90.0)),
+            LightingPreset::Night => Some(("#03030a", 10.0, 45.0)),
+            LightingPreset::Overcast => Some(("#8d99a6", 5.0, 40.0)),
+        };
+        Lighting {
+            sun_elevation: elevation,
+            sun_azimuth: azimuth,
+            sun_color: hex(sun_color),
+            sun_illuminance: illuminance,
+            ambient_color: hex(ambient_color),
+            ambient_brightness: ambient,
+            sky_color: hex(sky),
+            fog: fog.map(|(color, start, end)| (hex(color), start, end)),
+            shadow_cascades: ShadowCascades::default(),
+            day_night: None,
+            daylight: 1.0,
+            night: self == LightingPreset::Night,
+        }
+    }
+}
+
+impl Lighting {
+    pub fn from_arena(arena: &ArenaConfig) -> Self {
+        let config = &arena.lighting;
+        let preset = config.preset.unwrap_or(if arena.night {
+            LightingPreset::Night
+        } else {
+            LightingPreset::Day
+        });
+        let mut lighting = preset.lighting();
+        lighting.night |= arena.night;
+        if let Some(elevation) = config.sun_elevation {
+            lighting.sun_elevation = elevation;
+        }
+        if let Some(azimuth) = config.sun_azimuth {
+            lighting.sun_azimuth = azimuth;
+        }
+        if let Some(color) = &config.sun_color {
+            lighting.sun_color = hex(color);
+        }
+        if let Some(illuminance) = config.sun_illuminance {
+            lighting.sun_illuminance = illuminance;
+        }
+        if let Some(color) = &config.ambient_color {
+            lighting.ambient_color = hex(color);
+        }
+        if let Some(brightness) = config.ambient_brightness {
+            lighting.ambient_brightness = brightness;
+        }
+        if let Some(color) = &config.sky_color {
+            lighting.sky_color = hex(color);
+        }
+        if let Some(fog) = &config.fog {
+            lighting.fog = Some((hex(&fog.color), fog.start, fog.end));
+        }
+        if let Some(cascades) = config.shadow_cascades {
+            lighting.shadow_cascades = cascades;
+        }
+        lighting.day_night = config.day_night;
+        lighting
+    }
+
+    /// Whether it's too dark to see much without a flashlight.
+    pub fn is_dark(&self) -> bool {
+        self.night || self.daylight < DARK_BELOW
+    }
+
+    // Unit vector towards the sun at its set elevation and azimuth
+    fn sun_direction(&self) -> Vec3 {
+        let elevation = self.sun_elevation.to_radians();
+        let azimuth = self.sun_azimuth.to_radians();
+        Vec3::new(
+            elevation.cos() * azimuth.cos(),
+            elevation.sin(),
+            elevation.cos() * azimuth.sin(),
+        )
+    }
+}
+
+fn spawn_sun(mut commands: Commands, lighting: Res<Lighting>) {
+    let cascades = lighting.shadow_cascades;
+    commands.spawn((
+        DirectionalLightBundle {
+            transform: Transform::from_translation(lighting.sun_direction() * SUN_DISTANCE)
+                .looking_at(Vec3::ZERO, Vec3::Y),
+            directional_light: DirectionalLight {
+                illuminance: lighting.sun_illuminance,
+                shadows_enabled: true,
+                color: lighting.sun_color,
+                ..default()
+            },
+            cascade_shadow_config: CascadeShadowConfigBuilder {
+                num_cascades: cascades.count.max(1),
+                first_cascade_far_bound: cascades.first_bound,
+                maximum_distance: cascades.max_distance,
+                ..default()
+            }
+            .into(),
+            ..default()
+        },
+        Sun,
+        Name::new("DirectionalLight"),
+    ));
+}
+
+fn add_fog(
+    mut commands: Commands,
+    lighting: Res<Lighting>,
+    camera_q: Query<Entity, Added<Camera3d>>,
+) {
+    let Some((color, start, end)) = lighting.fog else {
+        return;
+    };
+    for camera in camera_q.iter() {
+        commands.entity(camera).insert(FogSettings {
+            color,
+            falloff: FogFalloff::Linear { start, end },
+            ..default()
+        });
+    }
+}
+
+fn turn_day_and_night(
+    time: Res<Time>,
+    game: Option<Res<Match>>,
+    mut lighting: ResMut<Lighting>,
+    mut ambient: ResMut<AmbientLight>,
+    mut clear_color: ResMut<ClearColor>,
+    mut sun_q: Query<(&mut DirectionalLight, &mut Transform), With<Sun>>,
+) {
+    let Some(cycle) = lighting.day_night else {
+        return;
+    };
+    let night = LightingPreset::Night.lighting();
+    let secs = game.map_or(time.elapsed_seconds(), |game| game.secs);
+    let angle = TAU * (cycle.start + secs / cycle.day_secs.max(1.0));
+
+    // Rises in the east, the azimuth, and crosses the sky at the set elevation
+    let noon = lighting.sun_direction();
+    let east = Vec3::new(noon.x, 0.0, noon.z).normalize_or(Vec3::X);
+    let across = Vec3::Y.cross(east);
+    let elevation = lighting.sun_elevation.to_radians();
+    let up = across * elevation.cos() + Vec3::Y * elevation.sin();
+    let towards_sun = east * angle.cos() + up * angle.sin();
+    let height = towards_sun.y;
+    let daylight = (height / FULL_DAYLIGHT).clamp(0.0, 1.0);
+    lighting.daylight = daylight;
+
+    // The moon lights from the other side once the sun's gone down
+    let towards_light = if height >= 0.0 {
+        towards_sun
+    } else {
+        -towards_sun
+    };
+    let sun_color = hex(SUNSET_COLOR).mix(&lighting.sun_color, daylight);
+    for (mut light, mut transform) in sun_q.iter_mut() {
+        *transform = Transform::from_translation(towards_light * SUN_DISTANCE)
+            .looking_at(Vec3::ZERO, Vec3::Y);
+        light.illuminance = night
+            .sun_illuminance
+            .lerp(lighting.sun_illuminance, daylight);
+        light.color = if height >= 0.0 {
+            sun_color
+        } else {
+            night.sun_color
+        };
+    }
+    ambient.brightness = night
+        .ambient_brightness
+        .lerp(lighting.ambient_brightness, daylight);
+    ambient.color = night.ambient_color.mix(&lighting.ambient_color, daylight);
+    clear_color.0 = night.sky_color.mix(&lighting.sky_color, daylight);
+}
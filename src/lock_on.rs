@@ -0,0 +1,304 @@
+//! Locking on to an opponent to throw at.
+//!
+//! Locking on picks the opponent nearest the middle of the player's view, and changing target moves
+//! round to the next one on the right. While locked on the player turns to face their target, their
+//! camera swings round behind them, and throws are pulled towards where the target will be when the
+//! ball gets there. How hard they're pulled is the aim assist setting.
+//!
+//! Only players at this machine lock on. Rollback games leave it out, since the other machine
+//! can't know which throws were pulled.
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use std::f32::consts::{PI, TAU};
+
+use crate::camera::{follow_split_screen_players, PlayerCamera, SplitScreenCamera};
+use crate::dodgeball::Eliminated;
+use crate::input::{LocalPlayer, PlayerActions};
+use crate::player::{AimAssist, HeldBall, HitStun, Player, Team, THROW_SPEED};
+use crate::rollback::RollbackSession;
+use crate::settings::Settings;
+use crate::world::SimulationSet;
+
+// Opponents further away than this can't be locked on to, and drop the lock
+const LOCK_RANGE: f32 = 30.0;
+// A fresh lock only picks from opponents within this angle of where the camera looks
+const LOCK_CONE: f32 = 0.6;
+// How quickly the camera swings round behind the player, per second
+const CAMERA_TURN_SPEED: f32 = 6.0;
+const MARKER_RADIUS: f32 = 0.6;
+const MARKER_HEIGHT: f32 = 1.4;
+
+pub struct LockOnPlugin;
+
+impl Plugin for LockOnPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                add_lock_on,
+                lock_on_to_targets
+                    .run_if(not(resource_exists::<RollbackSession>))
+                    .before(SimulationSet),
+                face_targets.after(SimulationSet),
+                draw_lock_on_markers,
+            ),
+        )
+        .add_systems(
+            PostUpdate,
+            turn_cameras_to_targets.before(follow_split_screen_players),
+        );
+    }
+}
+
+/// Something that can be locked on to. Every player is, and so is the fox.
+#[derive(Component, Default)]
+pub struct LockOnTarget {
+    /// Where to aim, from the target's origin
+    pub offset: Vec3,
+}
+
+/// Who a local player is locked on to.
+#[derive(Component, Default)]
+pub struct LockOn {
+    pub target: Option<Entity>,
+    // Buttons held last frame, so holding them down doesn't keep switching
+    lock_held: bool,
+    cycle_held: bool,
+}
+
+fn add_lock_on(
+    mut commands: Commands,
+    player_q: Query<Entity, Added<Player>>,
+    local_q: Query<Entity, Added<LocalPlayer>>,
+) {
+    for player in player_q.iter() {
+        commands.entity(player).insert(LockOnTarget::default());
+    }
+    for player in local_q.iter() {
+        commands.entity(player).insert(LockOn::default());
+    }
+}
+
+// Wraps an angle into -PI..PI
+fn wrap_angle(angle: f32) -> f32 {
+    (angle + PI).rem_euclid(TAU) - PI
+}
+
+// The yaw a camera looking along `direction` has
+fn yaw_of(direction: Vec3) -> f32 {
+    f32::atan2(-direction.x, -direction.z)
+}
+
+// Where a target moving at `velocity` will be once a ball thrown at `speed` reaches it
+fn predict(from: Vec3, target: Vec3, velocity: Vec3, speed: f32) -> Vec3 {
+    let mut point = target;
+    // Each pass gets closer to where the ball and the target meet
+    for _ in 0..2 {
+        let flight_secs = from.distance(point) / speed;
+        point = target + velocity.with_y(0.0) * flight_secs;
+    }
+    point
+}
+
+fn lock_on_to_targets(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mut player_q: Query<
+        (
+            Entity,
+            &Transform,
+            &PlayerActions,
+            &mut LockOn,
+            Option<&Team>,
+            Option<&HeldBall>,
+            Has<Eliminated>,
+            Has<AimAssist>,
+        ),
+        With<LocalPlayer>,
+    >,
+    target_q: Query<(
+        Entity,
+        &Transform,
+        &LockOnTarget,
+        Option<&Velocity>,
+        Option<&Team>,
+        Has<Eliminated>,
+    )>,
+    cam_q: Query<(&Transform, &PlayerCamera), Without<Player>>,
+) {
+    for (player, transform, actions, mut lock_on, team, held_ball, eliminated, assisted) in
+        player_q.iter_mut()
+    {
+        let lock_pressed = actions.lock_on && !lock_on.lock_held;
+        let cycle_pressed = actions.cycle_target && !lock_on.cycle_held;
+        lock_on.lock_held = actions.lock_on;
+        lock_on.cycle_held = actions.cycle_target;
+        let Some((cam, _)) = cam_q.iter().find(|(_, camera)| camera.player == player) else {
+            continue;
+        };
+
+        // Opponents in range, and how far round to the right of the camera's view each one is
+        let position = transform.translation;
+        let forward = cam.forward().with_y(0.0);
+        let right = cam.right().with_y(0.0);
+        let mut candidates: Vec<(Entity, f32)> = target_q
+            .iter()
+            .filter(
+                |(target, target_transform, _, _, target_team, target_eliminated)| {
+                    let opponent = match (team, target_team) {
+                        (Some(team), Some(target_team)) => team != target_team,
+                        _ => true,
+                    };
+                    *target != player
+                        && opponent
+                        && !target_eliminated
+                        && target_transform.translation.distance(position) < LOCK_RANGE
+                },
+            )
+            .map(|(target, target_transform, ..)| {
+                let to_target = target_transform.translation - position;
+                (
+                    target,
+                    f32::atan2(to_target.dot(right), to_target.dot(forward)),
+                )
+            })
+            .collect();
+        candidates.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        let in_view = || {
+            candidates
+                .iter()
+                .filter(|(_, angle)| angle.abs() < LOCK_CONE)
+                .min_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))
+                .map(|&(target, _)| target)
+        };
+        let current = lock_on
+            .target
+            .and_then(|target| candidates.iter().find(|(entity, _)| *entity == target));
+
+        lock_on.target = if eliminated {
+            None
+        } else if lock_pressed {
+            match lock_on.target {
+                Some(_) => None,
+                None => in_view(),
+            }
+        } else if cycle_pressed {
+            match current {
+                Some(&(_, angle)) => candidates
+                    .iter()
+                    .find(|(_, other)| *other > angle)
+                    .or(candidates.first())
+                    .map(|&(target, _)| target),
+                None => in_view(),
+            }
+        } else {
+            current.map(|&(target, _)| target)
+        };
+
+        let target = lock_on.target.and_then(|target| target_q.get(target).ok());
+        match target {
+            Some((_, target_transform, lock_on_target, velocity, ..)) => {
+                let speed = THROW_SPEED * held_ball.map_or(1.0, |held| held.kind.speed_scale());
+                let point = predict(
+                    position,
+                    target_transform.translation + lock_on_target.offset,
+                    velocity.map_or(Vec3::ZERO, |velocity| velocity.linvel),
+                    speed,
+                );
+                commands.entity(player).insert(AimAssist {
+                    point,
+                    strength: settings.aim_assist,
+                });
+            }
+            None if assisted => {
+                commands.entity(player).remove::<AimAssist>();
+            }
+            None => {}
+        }
+    }
+}
+
+// After movement has had its go, so running sideways still keeps us facing the target
+fn face_targets(
+    lock_q: Query<(Entity, &LockOn), (With<Player>, Without<HitStun>)>,
+    mut transform_q: Query<&mut Transform, With<LockOnTarget>>,
+) {
+    for (player, lock_on) in lock_q.iter() {
+        let Some(target) = lock_on
+            .target
+            .and_then(|target| transform_q.get(target).ok())
+            .map(|transform| transform.translation)
+        else {
+            continue;
+        };
+        let Ok(mut transform) = transform_q.get_mut(player) else {
+            continue;
+        };
+        let direction = (target - transform.translation).with_y(0.0);
+        if direction.length_squared() > 0.0 {
+            transform.look_to(direction, Vec3::Y);
+        }
+    }
+}
+
+// Only turns the camera side to side, so looking up and down is still the player's
+fn turn_cameras_to_targets(
+    time: Res<Time>,
+    lock_q: Query<&LockOn>,
+    target_q: Query<(&Transform, &LockOnTarget), Without<PlayerCamera>>,
+    mut camera_q: Query<(
+        &PlayerCamera,
+        &mut Transform,
+        Option<&mut SplitScreenCamera>,
+    )>,
+) {
+    let step = (CAMERA_TURN_SPEED * time.delta_seconds()).min(1.0);
+    for (player_camera, mut transform, split_screen) in camera_q.iter_mut() {
+        let Some(target) = lock_q
+            .get(player_camera.player)
+            .ok()
+            .and_then(|lock_on| lock_on.target)
+        else {
+            continue;
+        };
+        let (Ok((player_transform, _)), Ok((target_transform, lock_on_target))) =
+            (target_q.get(player_camera.player), target_q.get(target))
+        else {
+            continue;
+        };
+        let focus = player_transform.translation;
+        let direction = (target_transform.translation + lock_on_target.offset - focus).with_y(0.0);
+        if direction.length_squared() <= 0.0 {
+            continue;
+        }
+        let wanted_yaw = yaw_of(direction);
+        match split_screen {
+            // The split screen camera puts itself back together from its yaw
+            Some(mut orbit) => {
+                orbit.yaw += wrap_angle(wanted_yaw - orbit.yaw) * step;
+            }
+            // `ThirdPersonCamera` orbits by the camera's rotation, so swing the camera round
+            None => {
+                let turn = wrap_angle(wanted_yaw - yaw_of(*transform.forward())) * step;
+                transform.rotate_around(focus, Quat::from_rotation_y(turn));
+            }
+        }
+    }
+}
+
+fn draw_lock_on_markers(
+    mut gizmos: Gizmos,
+    lock_q: Query<&LockOn>,
+    target_q: Query<(&Transform, &LockOnTarget)>,
+) {
+    for lock_on in lock_q.iter() {
+        let Some((transform, lock_on_target)) =
+            lock_on.target.and_then(|target| target_q.get(target).ok())
+        else {
+            continue;
+        };
+        let position = transform.translation + lock_on_target.offset + Vec3::Y * MARKER_HEIGHT;
+        gizmos.circle(position, Dir3::Y, MARKER_RADIUS, Srgba::RED);
+    }
+}
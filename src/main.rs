@@ -1,33 +1,149 @@
+use bevy::app::ScheduleRunnerPlugin;
 use bevy::prelude::*;
+use bevy::render::{settings::WgpuSettings, RenderPlugin};
+use bevy::window::ExitCondition;
+use bevy::winit::WinitPlugin;
 use bevy_rapier3d::prelude::*;
+use std::time::Duration;
 
 pub use bevy_third_person_camera::ThirdPersonCameraPlugin;
 
+mod arena;
+mod audio;
+mod ball;
+mod bot;
 mod camera;
+mod camera_collision;
+mod camera_effects;
+mod debug;
+mod dodgeball;
+mod flashlight;
 mod fox;
+mod hazard;
+mod input;
+mod lighting;
+mod lock_on;
+mod nav;
+mod net;
+mod particles;
 mod player;
+mod player_animation;
+mod powerup;
+mod ragdoll;
+mod rollback;
+mod save;
+mod settings;
+mod spectator;
+mod telegraph;
+mod trajectory;
 mod world;
 
+use arena::ArenaPlugin;
+use audio::SoundPlugin;
+use ball::BallPlugin;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
+use bot::{BotPlugin, Headless};
 use camera::CameraPlugin;
+use camera_collision::CameraCollisionPlugin;
+use camera_effects::CameraEffectsPlugin;
+use debug::DebugPlugin;
+use dodgeball::DodgeballPlugin;
+use flashlight::FlashlightPlugin;
 use fox::FoxPlugin;
-use player::PlayerPlugin;
+use hazard::HazardPlugin;
+use input::InputPlugin;
+use lighting::LightingPlugin;
+use lock_on::LockOnPlugin;
+use nav::NavPlugin;
+use net::NetPlugin;
+use particles::ParticlePlugin;
+use player::{LocalPlayers, PlayerPlugin};
+use player_animation::PlayerAnimationPlugin;
+use powerup::PowerUpPlugin;
+use ragdoll::RagdollPlugin;
+use rollback::{RollbackConfig, RollbackPlugin};
+use save::SavePlugin;
+use settings::SettingsPlugin;
+use spectator::SpectatorPlugin;
+use telegraph::TelegraphPlugin;
+use trajectory::TrajectoryPlugin;
 use world::WorldPlugin;
 
 fn main() {
-    App::new()
+    let rollback = RollbackConfig::from_args();
+    // A rollback session steps physics itself, from inside its own schedule
+    let physics_systems = rollback.is_none();
+
+    let mut app = App::new();
+    if let Some(headless) = Headless::from_args() {
+        // Everything but the window and the GPU, ticking at 60 Hz, with bots in every slot
+        app.add_plugins((
+            DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: None,
+                    exit_condition: ExitCondition::DontExit,
+                    ..default()
+                })
+                .set(RenderPlugin {
+                    render_creation: WgpuSettings {
+                        backends: None,
+                        ..default()
+                    }
+                    .into(),
+                    ..default()
+                })
+                .disable::<WinitPlugin>(),
+            ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(1.0 / 60.0)),
+        ))
+        .insert_resource(headless)
+        .insert_resource(LocalPlayers { count: 0 });
+    } else {
+        app.add_plugins((
+            DefaultPlugins,
+            ThirdPersonCameraPlugin,
+            WorldInspectorPlugin::new(),
+        ))
+        .insert_resource(LocalPlayers::from_args());
+    }
+
+    app
         // .insert_resource(Time::<Fixed>::from_seconds(0.25))
         .add_plugins((
-            DefaultPlugins,
+            InputPlugin,
             PlayerPlugin,
             FoxPlugin,
             CameraPlugin,
             WorldPlugin,
-            ThirdPersonCameraPlugin,
-            WorldInspectorPlugin::new(),
+            BallPlugin,
+            DebugPlugin,
+            TrajectoryPlugin,
+            NetPlugin,
+            RollbackPlugin { config: rollback },
+            DodgeballPlugin,
+            BotPlugin,
+            NavPlugin,
+            RagdollPlugin,
+        ))
+        .add_plugins((
+            SavePlugin,
+            SettingsPlugin,
+            SoundPlugin,
+            CameraCollisionPlugin,
+            CameraEffectsPlugin,
+            LockOnPlugin,
+            SpectatorPlugin,
+            PowerUpPlugin,
+            ArenaPlugin,
+            HazardPlugin,
+            ParticlePlugin,
+            TelegraphPlugin,
+            PlayerAnimationPlugin,
+            FlashlightPlugin,
+            LightingPlugin,
         ))
-        .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
-        .add_plugins(RapierDebugRenderPlugin::default())
+        .add_plugins(
+            RapierPhysicsPlugin::<NoUserData>::default().with_default_system_setup(physics_systems),
+        )
         // .add_systems(Update, print_ball_altitude)
         .run();
 }
@@ -0,0 +1,554 @@
+//! Navigation for bots.
+//!
+//! The navmesh is a grid laid over the arena. A cell is walkable if a ray dropped from above lands
+//! on a flat, static surface with room to stand on it. Neighbouring cells are linked by walking if
+//! they're about level, jumping if one is a little higher and dropping if it's lower, so paths can
+//! lead up onto and off platforms. Only the top surface in each column is kept.
+//!
+//! Moving platforms aren't part of the mesh. Instead, every frame the cells they're in the way over
+//! are blocked, agents whose path crosses one plan again, and agents steer away from platforms
+//! they get close to.
+//!
+//! N toggles drawing the navmesh and every agent's path.
+
+use bevy::utils::HashMap;
+use bevy::{input::common_conditions::input_just_pressed, prelude::*};
+use bevy_rapier3d::prelude::*;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+use std::f32::consts::FRAC_PI_2;
+
+use crate::input::PlayerInputSet;
+
+// The grid covers the court and the ground around it
+const NAV_HALF_EXTENT: f32 = 12.0;
+const CELL_SIZE: f32 = 0.5;
+const RAY_START_HEIGHT: f32 = 30.0;
+// Surfaces steeper than this aren't walkable
+const MIN_UP_NORMAL: f32 = 0.7;
+
+// Players are a cone 1.6 tall, with their origin in the middle
+const AGENT_HEIGHT: f32 = 1.6;
+const AGENT_FOOT_OFFSET: f32 = 0.8;
+const AGENT_RADIUS: f32 = 0.4;
+const STEP_HEIGHT: f32 = 0.4;
+const MAX_JUMP_HEIGHT: f32 = 2.5;
+const MAX_DROP: f32 = 5.0;
+// Jumping and dropping cost this much more than walking the same distance
+const JUMP_COST: f32 = 3.0;
+const DROP_COST: f32 = 1.0;
+
+const ARRIVE_RADIUS: f32 = 0.4;
+// Jump when this close to a waypoint that needs one
+const JUMP_TRIGGER_DISTANCE: f32 = 1.2;
+const REPATH_SECS: f32 = 0.5;
+// Plan again once the destination has moved this far
+const REPATH_DISTANCE: f32 = 0.75;
+// Start steering away from moving platforms this far out
+const AVOID_RANGE: f32 = 1.5;
+// Goals off the mesh are moved to the nearest walkable cell within this many cells
+const NEAREST_CELL_SEARCH: i32 = 6;
+
+pub struct NavPlugin;
+
+impl Plugin for NavPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NavDebug>()
+            .add_systems(
+                Update,
+                build_navmesh.run_if(not(resource_exists::<NavMesh>)),
+            )
+            .add_systems(
+                PreUpdate,
+                (block_moving_obstacles, update_nav_agents)
+                    .chain()
+                    .run_if(resource_exists::<NavMesh>)
+                    .before(PlayerInputSet),
+            )
+            .add_systems(
+                Update,
+                (
+                    toggle_nav_debug.run_if(input_just_pressed(KeyCode::KeyN)),
+                    draw_navmesh.run_if(|debug: Res<NavDebug>| debug.enabled),
+                ),
+            );
+    }
+}
+
+/// A moving box agents path and steer around. Not part of the navmesh itself.
+#[derive(Component)]
+pub struct NavObstacle {
+    pub half_extents: Vec3,
+}
+
+impl NavObstacle {
+    // Bounds grown by the agent's radius, so agents keep their whole body clear
+    fn bounds(&self, translation: Vec3) -> (Vec3, Vec3) {
+        let inflate = Vec3::new(AGENT_RADIUS, 0.0, AGENT_RADIUS);
+        (
+            translation - self.half_extents - inflate,
+            translation + self.half_extents + inflate,
+        )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NavAction {
+    Walk,
+    Jump,
+    Drop,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct NavWaypoint {
+    /// A point on the surface to stand on
+    pub position: Vec3,
+    /// How to get here from the waypoint before
+    pub action: NavAction,
+}
+
+/// Something that finds its way to `destination`. Nav fills in `steer` and `jump` every frame.
+#[derive(Component, Default)]
+pub struct NavAgent {
+    pub destination: Option<Vec3>,
+    /// Which way to go, flat and normalized, or `None` if there's nowhere to go
+    pub steer: Option<Vec3>,
+    /// A jump is needed to reach the next waypoint
+    pub jump: bool,
+    path: VecDeque<NavWaypoint>,
+    planned_for: Option<Vec3>,
+    repath_secs: f32,
+}
+
+/// Whether the navmesh and paths are drawn. Toggled with N.
+#[derive(Resource, Default)]
+pub struct NavDebug {
+    pub enabled: bool,
+}
+
+/// Walkable cells over the arena, and which of them moving obstacles are in the way over.
+#[derive(Resource)]
+pub struct NavMesh {
+    origin: Vec2,
+    columns: i32,
+    rows: i32,
+    /// Surface height of each walkable cell
+    heights: Vec<Option<f32>>,
+    blocked: Vec<bool>,
+}
+
+// A* frontier entry, ordered so the heap pops the cheapest first
+#[derive(PartialEq)]
+struct Frontier {
+    estimate: f32,
+    cell: usize,
+}
+
+impl Eq for Frontier {}
+
+impl Ord for Frontier {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+impl PartialOrd for Frontier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl NavMesh {
+    /// Drops a ray onto every cell, against fixed colliders only.
+    pub fn build(rapier_context: &RapierContext) -> Self {
+        let columns = (NAV_HALF_EXTENT * 2.0 / CELL_SIZE) as i32;
+        let rows = columns;
+        let origin = Vec2::splat(-NAV_HALF_EXTENT);
+        let filter = QueryFilter::new()
+            .exclude_dynamic()
+            .exclude_kinematic()
+            .exclude_sensors();
+        let mut heights = Vec::with_capacity((columns * rows) as usize);
+        for row in 0..rows {
+            for column in 0..columns {
+                let center = origin + (Vec2::new(column as f32, row as f32) + 0.5) * CELL_SIZE;
+                let top = Vec3::new(center.x, RAY_START_HEIGHT, center.y);
+                let height = rapier_context
+                    .cast_ray_and_get_normal(top, Vec3::NEG_Y, RAY_START_HEIGHT * 2.0, true, filter)
+                    .filter(|(_, hit)| hit.normal.y >= MIN_UP_NORMAL)
+                    .map(|(_, hit)| hit.point.y)
+                    // Somewhere to stand needs headroom too
+                    .filter(|&height| {
+                        let feet = Vec3::new(center.x, height + 0.05, center.y);
+                        rapier_context
+                            .cast_ray(feet, Vec3::Y, AGENT_HEIGHT, true, filter)
+                            .is_none()
+                    });
+                heights.push(height);
+            }
+        }
+        Self {
+            origin,
+            columns,
+            rows,
+            blocked: vec![false; heights.len()],
+            heights,
+        }
+    }
+
+    fn walkable_count(&self) -> usize {
+        self.heights
+            .iter()
+            .filter(|height| height.is_some())
+            .count()
+    }
+
+    fn coords(&self, position: Vec3) -> IVec2 {
+        ((Vec2::new(position.x, position.z) - self.origin) / CELL_SIZE)
+            .floor()
+            .as_ivec2()
+    }
+
+    fn index(&self, coords: IVec2) -> Option<usize> {
+        let in_bounds =
+            coords.x >= 0 && coords.y >= 0 && coords.x < self.columns && coords.y < self.rows;
+        in_bounds.then(|| (coords.y * self.columns + coords.x) as usize)
+    }
+
+    fn open(&self, cell: usize) -> bool {
+        self.heights[cell].is_some() && !self.blocked[cell]
+    }
+
+    fn surface(&self, cell: usize) -> Vec3 {
+        let coords = IVec2::new(cell as i32 % self.columns, cell as i32 / self.columns);
+        let center = self.origin + (coords.as_vec2() + 0.5) * CELL_SIZE;
+        Vec3::new(center.x, self.heights[cell].unwrap_or(0.0), center.y)
+    }
+
+    /// The open cell under `position`, or the nearest one to it
+    fn nearest_open_cell(&self, position: Vec3) -> Option<usize> {
+        let coords = self.coords(position);
+        (-NEAREST_CELL_SEARCH..=NEAREST_CELL_SEARCH)
+            .flat_map(|dy| {
+                (-NEAREST_CELL_SEARCH..=NEAREST_CELL_SEARCH).map(move |dx| IVec2::new(dx, dy))
+            })
+            .filter_map(|offset| self.index(coords + offset))
+            .filter(|&cell| self.open(cell))
+            .min_by(|&a, &b| {
+                let distance = |cell| self.surface(cell).distance_squared(position);
+                distance(a).total_cmp(&distance(b))
+            })
+    }
+
+    // How to get from one cell to a neighbouring one, if you can
+    fn link(&self, from: usize, to: usize) -> Option<NavAction> {
+        let rise = self.heights[to]? - self.heights[from]?;
+        if rise.abs() <= STEP_HEIGHT {
+            Some(NavAction::Walk)
+        } else if rise > 0.0 && rise <= MAX_JUMP_HEIGHT {
+            Some(NavAction::Jump)
+        } else if rise < 0.0 && -rise <= MAX_DROP {
+            Some(NavAction::Drop)
+        } else {
+            None
+        }
+    }
+
+    fn neighbours(&self, cell: usize) -> impl Iterator<Item = (usize, NavAction, f32)> + '_ {
+        let coords = IVec2::new(cell as i32 % self.columns, cell as i32 / self.columns);
+        (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| IVec2::new(dx, dy)))
+            .filter(|offset| *offset != IVec2::ZERO)
+            .filter_map(move |offset| {
+                let neighbour = self.index(coords + offset)?;
+                if !self.open(neighbour) {
+                    return None;
+                }
+                // No cutting corners on diagonals
+                if offset.x != 0 && offset.y != 0 {
+                    let side_a = self.index(coords + IVec2::new(offset.x, 0))?;
+                    let side_b = self.index(coords + IVec2::new(0, offset.y))?;
+                    if !self.open(side_a) || !self.open(side_b) {
+                        return None;
+                    }
+                }
+                let action = self.link(cell, neighbour)?;
+                let distance = offset.as_vec2().length() * CELL_SIZE;
+                let cost = match action {
+                    NavAction::Walk => distance,
+                    NavAction::Jump => distance + JUMP_COST,
+                    NavAction::Drop => distance + DROP_COST,
+                };
+                Some((neighbour, action, cost))
+            })
+    }
+
+    /// A* from the cell under `from` to the cell under `to`, with straight walks merged.
+    pub fn find_path(&self, from: Vec3, to: Vec3) -> Option<Vec<NavWaypoint>> {
+        let start = self.nearest_open_cell(from)?;
+        let goal = self.nearest_open_cell(to)?;
+        let goal_surface = self.surface(goal);
+        let heuristic = |cell: usize| self.surface(cell).xz().distance(goal_surface.xz());
+
+        let mut frontier = BinaryHeap::new();
+        let mut came_from: HashMap<usize, (usize, NavAction)> = HashMap::new();
+        let mut cost_so_far: HashMap<usize, f32> = HashMap::new();
+        frontier.push(Frontier {
+            estimate: heuristic(start),
+            cell: start,
+        });
+        cost_so_far.insert(start, 0.0);
+
+        while let Some(Frontier { cell, .. }) = frontier.pop() {
+            if cell == goal {
+                break;
+            }
+            let cost = cost_so_far[&cell];
+            for (neighbour, action, step_cost) in self.neighbours(cell) {
+                let new_cost = cost + step_cost;
+                if cost_so_far
+                    .get(&neighbour)
+                    .is_some_and(|&known| known <= new_cost)
+                {
+                    continue;
+                }
+                cost_so_far.insert(neighbour, new_cost);
+                came_from.insert(neighbour, (cell, action));
+                frontier.push(Frontier {
+                    estimate: new_cost + heuristic(neighbour),
+                    cell: neighbour,
+                });
+            }
+        }
+
+        if start != goal && !came_from.contains_key(&goal) {
+            return None;
+        }
+        let mut path = Vec::new();
+        let mut cell = goal;
+        while let Some(&(previous, action)) = came_from.get(&cell) {
+            path.push(NavWaypoint {
+                position: self.surface(cell),
+                action,
+            });
+            cell = previous;
+        }
+        path.reverse();
+        Some(self.smooth(from, path))
+    }
+
+    // Skips walking waypoints that can be reached in a straight line from the last one kept
+    fn smooth(&self, from: Vec3, path: Vec<NavWaypoint>) -> Vec<NavWaypoint> {
+        let mut smoothed: Vec<NavWaypoint> = Vec::new();
+        let mut anchor = from;
+        for (i, waypoint) in path.iter().enumerate() {
+            let next = path.get(i + 1);
+            let skippable = waypoint.action == NavAction::Walk
+                && next.is_some_and(|next| {
+                    next.action == NavAction::Walk && self.walkable_line(anchor, next.position)
+                });
+            if !skippable {
+                smoothed.push(*waypoint);
+                anchor = waypoint.position;
+            }
+        }
+        smoothed
+    }
+
+    // Every cell along the line is open and about level with the one before
+    fn walkable_line(&self, from: Vec3, to: Vec3) -> bool {
+        let steps = (from.xz().distance(to.xz()) / (CELL_SIZE * 0.5))
+            .ceil()
+            .max(1.0) as usize;
+        let mut previous_height = None;
+        (0..=steps).all(|step| {
+            let point = from.lerp(to, step as f32 / steps as f32);
+            let Some(cell) = self.index(self.coords(point)) else {
+                return false;
+            };
+            let (true, Some(height)) = (self.open(cell), self.heights[cell]) else {
+                return false;
+            };
+            let level = previous_height.map_or(true, |previous: f32| {
+                (height - previous).abs() <= STEP_HEIGHT
+            });
+            previous_height = Some(height);
+            level
+        })
+    }
+
+    fn blocks(&self, waypoint: &NavWaypoint) -> bool {
+        self.index(self.coords(waypoint.position))
+            .is_some_and(|cell| self.blocked[cell])
+    }
+}
+
+// The query pipeline is empty until physics has stepped once, so keep trying until it isn't
+fn build_navmesh(mut commands: Commands, rapier_context: Res<RapierContext>) {
+    let navmesh = NavMesh::build(&rapier_context);
+    let walkable = navmesh.walkable_count();
+    if walkable == 0 {
+        return;
+    }
+    println!(
+        "Built navmesh: {} of {} cells walkable",
+        walkable,
+        navmesh.heights.len()
+    );
+    commands.insert_resource(navmesh);
+}
+
+// Cells with a moving platform in the way, too high to step onto, are closed for now
+fn block_moving_obstacles(
+    mut navmesh: ResMut<NavMesh>,
+    obstacle_q: Query<(&GlobalTransform, &NavObstacle)>,
+) {
+    let navmesh = &mut *navmesh;
+    navmesh.blocked.fill(false);
+    for (transform, obstacle) in obstacle_q.iter() {
+        let (min, max) = obstacle.bounds(transform.translation());
+        let min_coords = navmesh.coords(min);
+        let max_coords = navmesh.coords(max);
+        for y in min_coords.y..=max_coords.y {
+            for x in min_coords.x..=max_coords.x {
+                let Some(cell) = navmesh.index(IVec2::new(x, y)) else {
+                    continue;
+                };
+                let Some(height) = navmesh.heights[cell] else {
+                    continue;
+                };
+                navmesh.blocked[cell] |=
+                    min.y < height + AGENT_HEIGHT && max.y > height + STEP_HEIGHT;
+            }
+        }
+    }
+}
+
+fn update_nav_agents(
+    time: Res<Time>,
+    navmesh: Res<NavMesh>,
+    obstacle_q: Query<(&GlobalTransform, &NavObstacle)>,
+    mut agent_q: Query<(&Transform, &mut NavAgent)>,
+) {
+    for (transform, mut agent) in agent_q.iter_mut() {
+        agent.repath_secs -= time.delta_seconds();
+        agent.jump = false;
+        let Some(destination) = agent.destination else {
+            agent.path.clear();
+            agent.planned_for = None;
+            agent.steer = None;
+            continue;
+        };
+        let feet = transform.translation - Vec3::Y * AGENT_FOOT_OFFSET;
+
+        let moved = agent.planned_for.map_or(true, |planned_for| {
+            planned_for.distance(destination) > REPATH_DISTANCE
+        });
+        let blocked = agent.path.iter().any(|waypoint| navmesh.blocks(waypoint));
+        if agent.planned_for.is_none() || ((moved || blocked) && agent.repath_secs <= 0.0) {
+            agent.path = navmesh
+                .find_path(feet, destination)
+                .unwrap_or_default()
+                .into();
+            agent.planned_for = Some(destination);
+            agent.repath_secs = REPATH_SECS;
+        }
+
+        while agent
+            .path
+            .front()
+            .is_some_and(|waypoint| waypoint.position.xz().distance(feet.xz()) < ARRIVE_RADIUS)
+        {
+            agent.path.pop_front();
+        }
+
+        // With no path, head straight there and hope
+        let (target, action) = agent
+            .path
+            .front()
+            .map_or((destination, NavAction::Walk), |waypoint| {
+                (waypoint.position, waypoint.action)
+            });
+        let to_target = (target - feet).with_y(0.0);
+        if to_target.length() < ARRIVE_RADIUS {
+            agent.steer = None;
+            continue;
+        }
+        agent.jump = action == NavAction::Jump
+            && to_target.length() < JUMP_TRIGGER_DISTANCE
+            && target.y > feet.y + STEP_HEIGHT;
+
+        // Push away from any moving platform we're about to walk into
+        let mut push = Vec3::ZERO;
+        for (obstacle_transform, obstacle) in obstacle_q.iter() {
+            let (min, max) = obstacle.bounds(obstacle_transform.translation());
+            if min.y > feet.y + AGENT_HEIGHT || max.y < feet.y + STEP_HEIGHT {
+                continue;
+            }
+            let closest = feet.clamp(min, max).with_y(feet.y);
+            let away = (feet - closest).with_y(0.0);
+            let distance = away.length();
+            if distance < AVOID_RANGE {
+                let direction = away.normalize_or(-to_target.normalize_or_zero());
+                push += direction * (1.0 - distance / AVOID_RANGE);
+            }
+        }
+        let direction = to_target.normalize();
+        agent.steer = Some((direction + push).normalize_or(direction));
+    }
+}
+
+fn toggle_nav_debug(mut debug: ResMut<NavDebug>) {
+    debug.enabled = !debug.enabled;
+    println!(
+        "Navmesh drawing {}",
+        if debug.enabled { "on" } else { "off" }
+    );
+}
+
+fn draw_navmesh(
+    mut gizmos: Gizmos,
+    navmesh: Option<Res<NavMesh>>,
+    agent_q: Query<(&Transform, &NavAgent)>,
+) {
+    let Some(navmesh) = navmesh else {
+        return;
+    };
+    let flat = Quat::from_rotation_x(FRAC_PI_2);
+    let cell_size = Vec2::splat(CELL_SIZE * 0.8);
+    for cell in 0..navmesh.heights.len() {
+        if navmesh.heights[cell].is_none() {
+            continue;
+        }
+        let color = if navmesh.blocked[cell] {
+            Srgba::RED.with_alpha(0.6)
+        } else {
+            Srgba::GREEN.with_alpha(0.25)
+        };
+        gizmos.rect(
+            navmesh.surface(cell) + Vec3::Y * 0.02,
+            flat,
+            cell_size,
+            color,
+        );
+    }
+
+    for (transform, agent) in agent_q.iter() {
+        if agent.path.is_empty() {
+            continue;
+        }
+        let feet = transform.translation - Vec3::Y * AGENT_FOOT_OFFSET;
+        let points = std::iter::once(feet)
+            .chain(agent.path.iter().map(|waypoint| waypoint.position))
+            .map(|point| point + Vec3::Y * 0.1);
+        gizmos.linestrip(points, Srgba::hex("#ffd60a").unwrap());
+        for waypoint in agent.path.iter() {
+            let color = match waypoint.action {
+                NavAction::Walk => continue,
+                NavAction::Jump => Srgba::hex("#f15bb5").unwrap(),
+                NavAction::Drop => Srgba::hex("#00bbf9").unwrap(),
+            };
+            gizmos.circle(waypoint.position + Vec3::Y * 0.1, Dir3::Y, 0.3, color);
+        }
+    }
+}
@@ -0,0 +1,1079 @@
+//! Client-server multiplayer over UDP.
+//!
+//! The server runs the real simulation and sends snapshots of every player, ball and platform.
+//! Clients send their inputs, draw everyone else a little in the past by interpolating between
+//! snapshots, and predict their own player locally, nudging it back whenever the server disagrees.
+//!
+//! Try it on one machine with `--server 127.0.0.1:5000` in one process and
+//! `--connect 127.0.0.1:5000` in another.
+
+use crate::ball::{release_ball, Ball, BallAssets, BallKind, BallPool, BallSpawnConfig};
+use crate::camera::PlayerCamera;
+use crate::input::{LocalPlayer, PlayerActions, PlayerInputSet};
+use crate::player::{spawn_player, HeldBall, Player};
+use crate::world::Cubeovator;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_rapier3d::prelude::*;
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+
+const SNAPSHOT_INTERVAL_SECS: f32 = 1.0 / 20.0;
+// Remote entities are drawn this far behind the newest snapshot so there is always one to lerp towards
+const INTERPOLATION_DELAY_SECS: f32 = 0.1;
+const SNAPSHOT_BUFFER_LEN: usize = 32;
+const HELLO_RETRY_SECS: f32 = 1.0;
+const CLIENT_TIMEOUT_SECS: f64 = 5.0;
+// Prediction errors smaller than this are left alone rather than snapping the player around
+const RECONCILE_THRESHOLD: f32 = 0.25;
+const MAX_PACKET_BYTES: usize = 65_507;
+// Platform ids are derived from their names so both sides agree without talking about it
+const PLATFORM_NET_ID_BIT: u32 = 1 << 31;
+
+pub struct NetPlugin;
+
+impl Plugin for NetPlugin {
+    fn build(&self, app: &mut App) {
+        if let Some(net_mode) = NetMode::from_args() {
+            app.insert_resource(net_mode);
+        }
+        app.init_resource::<NextNetId>()
+            .add_systems(Startup, open_socket)
+            .add_systems(
+                Update,
+                assign_platform_net_ids.run_if(|net: Option<Res<NetMode>>| net.is_some()),
+            )
+            .add_systems(
+                PreUpdate,
+                server_receive
+                    .after(PlayerInputSet)
+                    .run_if(resource_exists::<Server>),
+            )
+            .add_systems(
+                Update,
+                (assign_net_ids, server_send_snapshots).run_if(resource_exists::<Server>),
+            )
+            .add_systems(
+                PreUpdate,
+                client_receive
+                    .after(PlayerInputSet)
+                    .run_if(resource_exists::<Client>),
+            )
+            .add_systems(
+                Update,
+                (
+                    hold_ball_launcher,
+                    (client_send_input, client_record_prediction).chain(),
+                )
+                    .run_if(resource_exists::<Client>),
+            )
+            .add_systems(
+                PostUpdate,
+                client_interpolate
+                    .before(PhysicsSet::SyncBackend)
+                    .run_if(resource_exists::<Client>),
+            );
+    }
+}
+
+/// Whether this process is hosting or joining a networked match, from `--server <addr>` or `--connect <addr>`.
+#[derive(Resource, Clone, Copy, Debug)]
+pub enum NetMode {
+    Server(SocketAddr),
+    Client(SocketAddr),
+}
+
+impl NetMode {
+    pub fn from_args() -> Option<Self> {
+        let args: Vec<String> = std::env::args().collect();
+        let value_of = |flag: &str| {
+            args.iter()
+                .position(|arg| arg == flag)
+                .and_then(|i| args.get(i + 1))
+                .and_then(|addr| addr.parse().ok())
+        };
+        value_of("--server")
+            .map(NetMode::Server)
+            .or_else(|| value_of("--connect").map(NetMode::Client))
+    }
+}
+
+/// Identifies an entity across the network. Stable for as long as the entity exists on the server.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct NetId(pub u32);
+
+#[derive(Resource, Default)]
+struct NextNetId(u32);
+
+/// Stands in for a remote player's camera on the server so `player_movement` can steer them the same way.
+#[derive(Component)]
+struct RemoteCameraProxy;
+
+struct RemoteClient {
+    net_id: NetId,
+    player: Entity,
+    camera: Entity,
+    last_input: u32,
+    last_heard: f64,
+}
+
+#[derive(Resource)]
+struct Server {
+    socket: UdpSocket,
+    clients: HashMap<SocketAddr, RemoteClient>,
+    snapshot_timer: Timer,
+}
+
+struct PredictedState {
+    sequence: u32,
+    translation: Vec3,
+}
+
+#[derive(Resource)]
+struct Client {
+    socket: UdpSocket,
+    server: SocketAddr,
+    /// Our player's id on the server, once it has welcomed us
+    net_id: Option<NetId>,
+    hello_timer: Timer,
+    input_sequence: u32,
+    snapshots: VecDeque<Snapshot>,
+    /// Server clock minus ours, so we know what server time it is now
+    clock_offset: Option<f32>,
+    /// Where we predicted our player would be after each input the server hasn't confirmed yet
+    predictions: VecDeque<PredictedState>,
+    entities: HashMap<NetId, Entity>,
+}
+
+/// Drawn on the client for players, balls and platforms the server owns.
+#[derive(Component)]
+struct Replicated;
+
+// Wire format
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum NetKind {
+    Player,
+    Ball(BallKind),
+    Platform,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct EntityState {
+    net_id: NetId,
+    kind: NetKind,
+    translation: Vec3,
+    rotation: Quat,
+    linvel: Vec3,
+}
+
+#[derive(Clone, Debug)]
+struct Snapshot {
+    server_time: f32,
+    /// The newest input from the receiving client that this snapshot includes
+    acked_input: u32,
+    entities: Vec<EntityState>,
+}
+
+#[derive(Debug)]
+enum ClientMessage {
+    Hello,
+    Input {
+        sequence: u32,
+        movement: Vec2,
+        jump: bool,
+        catch: bool,
+        throw: bool,
+        dash: bool,
+        camera_rotation: Quat,
+    },
+}
+
+#[derive(Debug)]
+enum ServerMessage {
+    Welcome { net_id: NetId },
+    Snapshot(Snapshot),
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) -> &mut Self {
+        self.0.push(value);
+        self
+    }
+
+    fn u32(&mut self, value: u32) -> &mut Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn f32(&mut self, value: f32) -> &mut Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn vec2(&mut self, value: Vec2) -> &mut Self {
+        self.f32(value.x).f32(value.y)
+    }
+
+    fn vec3(&mut self, value: Vec3) -> &mut Self {
+        self.f32(value.x).f32(value.y).f32(value.z)
+    }
+
+    fn quat(&mut self, value: Quat) -> &mut Self {
+        self.f32(value.x).f32(value.y).f32(value.z).f32(value.w)
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (head, rest) = self.0.split_first_chunk::<N>()?;
+        self.0 = rest;
+        Some(*head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes::<1>().map(|[byte]| byte)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes().map(u32::from_le_bytes)
+    }
+
+    // NaNs and infinities would only poison the simulation, so they count as a bad packet
+    fn f32(&mut self) -> Option<f32> {
+        self.bytes()
+            .map(f32::from_le_bytes)
+            .filter(|value| value.is_finite())
+    }
+
+    fn vec2(&mut self) -> Option<Vec2> {
+        Some(Vec2::new(self.f32()?, self.f32()?))
+    }
+
+    fn vec3(&mut self) -> Option<Vec3> {
+        Some(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    fn quat(&mut self) -> Option<Quat> {
+        Vec4::new(self.f32()?, self.f32()?, self.f32()?, self.f32()?)
+            .try_normalize()
+            .map(Quat::from_vec4)
+    }
+}
+
+const BALL_KINDS: [BallKind; 6] = [
+    BallKind::Standard,
+    BallKind::Heavy,
+    BallKind::Bouncy,
+    BallKind::Curve,
+    BallKind::Splitter,
+    BallKind::Shard,
+];
+
+impl NetKind {
+    fn encode(self) -> u8 {
+        match self {
+            NetKind::Player => 0,
+            NetKind::Platform => 1,
+            NetKind::Ball(kind) => 2 + BALL_KINDS.iter().position(|&k| k == kind).unwrap() as u8,
+        }
+    }
+
+    fn decode(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(NetKind::Player),
+            1 => Some(NetKind::Platform),
+            n => BALL_KINDS
+                .get(n as usize - 2)
+                .map(|&kind| NetKind::Ball(kind)),
+        }
+    }
+}
+
+impl ClientMessage {
+    fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        match self {
+            ClientMessage::Hello => {
+                writer.u8(0);
+            }
+            ClientMessage::Input {
+                sequence,
+                movement,
+                jump,
+                catch,
+                throw,
+                dash,
+                camera_rotation,
+            } => {
+                writer
+                    .u8(1)
+                    .u32(*sequence)
+                    .vec2(*movement)
+                    .u8(*jump as u8
+                        | (*catch as u8) << 1
+                        | (*throw as u8) << 2
+                        | (*dash as u8) << 3)
+                    .quat(*camera_rotation);
+            }
+        }
+        writer.0
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader(bytes);
+        match reader.u8()? {
+            0 => Some(ClientMessage::Hello),
+            1 => {
+                let sequence = reader.u32()?;
+                let movement = reader.vec2()?.clamp_length_max(1.0);
+                let buttons = reader.u8()?;
+                Some(ClientMessage::Input {
+                    sequence,
+                    movement,
+                    jump: buttons & 1 != 0,
+                    catch: buttons & 2 != 0,
+                    throw: buttons & 4 != 0,
+                    dash: buttons & 8 != 0,
+                    camera_rotation: reader.quat()?,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl ServerMessage {
+    fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        match self {
+            ServerMessage::Welcome { net_id } => {
+                writer.u8(0).u32(net_id.0);
+            }
+            ServerMessage::Snapshot(snapshot) => {
+                writer
+                    .u8(1)
+                    .f32(snapshot.server_time)
+                    .u32(snapshot.acked_input)
+                    .u32(snapshot.entities.len() as u32);
+                for state in &snapshot.entities {
+                    writer
+                        .u32(state.net_id.0)
+                        .u8(state.kind.encode())
+                        .vec3(state.translation)
+                        .quat(state.rotation)
+                        .vec3(state.linvel);
+                }
+            }
+        }
+        writer.0
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader(bytes);
+        match reader.u8()? {
+            0 => Some(ServerMessage::Welcome {
+                net_id: NetId(reader.u32()?),
+            }),
+            1 => {
+                let server_time = reader.f32()?;
+                let acked_input = reader.u32()?;
+                let len = reader.u32()? as usize;
+                let mut entities = Vec::with_capacity(len.min(1024));
+                for _ in 0..len {
+                    entities.push(EntityState {
+                        net_id: NetId(reader.u32()?),
+                        kind: NetKind::decode(reader.u8()?)?,
+                        translation: reader.vec3()?,
+                        rotation: reader.quat()?,
+                        linvel: reader.vec3()?,
+                    });
+                }
+                Some(ServerMessage::Snapshot(Snapshot {
+                    server_time,
+                    acked_input,
+                    entities,
+                }))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Setup
+
+fn open_socket(mut commands: Commands, net_mode: Option<Res<NetMode>>) {
+    let Some(net_mode) = net_mode else {
+        return;
+    };
+    match *net_mode {
+        NetMode::Server(addr) => {
+            let socket = UdpSocket::bind(addr).expect("Error binding server socket");
+            socket.set_nonblocking(true).unwrap();
+            println!("Hosting on {}", addr);
+            commands.insert_resource(Server {
+                socket,
+                clients: HashMap::new(),
+                snapshot_timer: Timer::from_seconds(SNAPSHOT_INTERVAL_SECS, TimerMode::Repeating),
+            });
+        }
+        NetMode::Client(server) => {
+            let socket = UdpSocket::bind("0.0.0.0:0").expect("Error binding client socket");
+            socket.set_nonblocking(true).unwrap();
+            println!("Connecting to {}", server);
+            commands.insert_resource(Client {
+                socket,
+                server,
+                net_id: None,
+                hello_timer: Timer::from_seconds(HELLO_RETRY_SECS, TimerMode::Repeating),
+                input_sequence: 0,
+                snapshots: VecDeque::new(),
+                clock_offset: None,
+                predictions: VecDeque::new(),
+                entities: HashMap::new(),
+            });
+        }
+    }
+}
+
+// FNV-1a, so the same platform name gives the same id in every process
+fn platform_net_id(name: &str) -> NetId {
+    let hash = name.bytes().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    });
+    NetId(hash | PLATFORM_NET_ID_BIT)
+}
+
+fn assign_platform_net_ids(
+    mut commands: Commands,
+    platform_q: Query<(Entity, &Name), (With<Cubeovator>, Without<NetId>)>,
+    mut client: Option<ResMut<Client>>,
+) {
+    for (entity, name) in platform_q.iter() {
+        let net_id = platform_net_id(name.as_str());
+        commands.entity(entity).insert(net_id);
+        if let Some(client) = client.as_mut() {
+            // Platforms keep their colliders on the client, the server just tells them where to be
+            client.entities.insert(net_id, entity);
+            commands.entity(entity).insert(Replicated);
+        }
+    }
+}
+
+// Server
+
+fn assign_net_ids(
+    mut commands: Commands,
+    mut next_net_id: ResMut<NextNetId>,
+    new_q: Query<Entity, (Or<(Added<Player>, Added<Ball>)>, Without<NetId>)>,
+) {
+    for entity in new_q.iter() {
+        commands.entity(entity).insert(NetId(next_net_id.0));
+        next_net_id.0 += 1;
+    }
+}
+
+fn server_receive(
+    mut commands: Commands,
+    mut server: ResMut<Server>,
+    mut next_net_id: ResMut<NextNetId>,
+    mut pool: ResMut<BallPool>,
+    assets: Res<AssetServer>,
+    time: Res<Time>,
+    mut player_q: Query<&mut PlayerActions, Without<LocalPlayer>>,
+    held_q: Query<&HeldBall>,
+    mut proxy_q: Query<&mut Transform, With<RemoteCameraProxy>>,
+) {
+    let now = time.elapsed_seconds_f64();
+    let mut buf = [0; MAX_PACKET_BYTES];
+    loop {
+        let (len, addr) = match server.socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => {
+                println!("Error receiving from client: {}", e);
+                break;
+            }
+        };
+        let Some(message) = ClientMessage::decode(&buf[..len]) else {
+            continue;
+        };
+        match message {
+            ClientMessage::Hello => {
+                // A second hello means our welcome got lost, so just say it again
+                let net_id = match server.clients.get(&addr) {
+                    Some(client) => client.net_id,
+                    None => {
+                        let player = spawn_player(&mut commands, &assets, Vec3::new(0.0, 1.8, 3.0));
+                        let net_id = NetId(next_net_id.0);
+                        next_net_id.0 += 1;
+                        commands
+                            .entity(player)
+                            .insert((net_id, Name::new(format!("Remote player {}", addr))));
+                        let camera = commands
+                            .spawn((
+                                TransformBundle::default(),
+                                RemoteCameraProxy,
+                                PlayerCamera { player },
+                            ))
+                            .id();
+                        server.clients.insert(
+                            addr,
+                            RemoteClient {
+                                net_id,
+                                player,
+                                camera,
+                                last_input: 0,
+                                last_heard: now,
+                            },
+                        );
+                        println!("{} joined", addr);
+                        net_id
+                    }
+                };
+                let welcome = ServerMessage::Welcome { net_id }.encode();
+                if let Err(e) = server.socket.send_to(&welcome, addr) {
+                    println!("Error welcoming {}: {}", addr, e);
+                }
+            }
+            ClientMessage::Input {
+                sequence,
+                movement,
+                jump,
+                catch,
+                throw,
+                dash,
+                camera_rotation,
+            } => {
+                let Some(client) = server.clients.get_mut(&addr) else {
+                    continue;
+                };
+                client.last_heard = now;
+                // Inputs can arrive out of order, only the newest counts
+                if sequence <= client.last_input {
+                    continue;
+                }
+                client.last_input = sequence;
+                if let Ok(mut actions) = player_q.get_mut(client.player) {
+                    actions.movement = movement;
+                    actions.jump = jump;
+                    actions.catch = catch;
+                    actions.throw = throw;
+                    actions.dash = dash;
+                }
+                if let Ok(mut camera) = proxy_q.get_mut(client.camera) {
+                    camera.rotation = camera_rotation;
+                }
+            }
+        }
+    }
+
+    // Forget anyone we haven't heard from in a while
+    let timed_out: Vec<SocketAddr> = server
+        .clients
+        .iter()
+        .filter(|(_, client)| now - client.last_heard > CLIENT_TIMEOUT_SECS)
+        .map(|(addr, _)| *addr)
+        .collect();
+    for addr in timed_out {
+        let client = server.clients.remove(&addr).unwrap();
+        // The ball they were holding goes back in the pool rather than with them
+        if let Ok(held_ball) = held_q.get(client.player) {
+            release_ball(&mut commands, &mut pool, held_ball.ball);
+        }
+        commands.entity(client.player).despawn_recursive();
+        commands.entity(client.camera).despawn_recursive();
+        println!("{} timed out", addr);
+    }
+}
+
+fn server_send_snapshots(
+    mut server: ResMut<Server>,
+    time: Res<Time>,
+    entity_q: Query<(
+        &NetId,
+        &Transform,
+        Option<&Velocity>,
+        Option<&BallKind>,
+        Has<Player>,
+        Has<RigidBodyDisabled>,
+    )>,
+) {
+    server.snapshot_timer.tick(time.delta());
+    if !server.snapshot_timer.just_finished() {
+        return;
+    }
+
+    let entities: Vec<EntityState> = entity_q
+        .iter()
+        // Parked balls aren't in play, leaving them out tells clients to hide them
+        .filter(|(_, _, _, _, _, disabled)| !disabled)
+        .map(
+            |(net_id, transform, velocity, ball_kind, is_player, _)| EntityState {
+                net_id: *net_id,
+                kind: match (ball_kind, is_player) {
+                    (Some(&kind), _) => NetKind::Ball(kind),
+                    (None, true) => NetKind::Player,
+                    (None, false) => NetKind::Platform,
+                },
+                translation: transform.translation,
+                rotation: transform.rotation,
+                linvel: velocity.map_or(Vec3::ZERO, |velocity| velocity.linvel),
+            },
+        )
+        .collect();
+
+    for (addr, client) in server.clients.iter() {
+        let snapshot = ServerMessage::Snapshot(Snapshot {
+            server_time: time.elapsed_seconds(),
+            acked_input: client.last_input,
+            entities: entities.clone(),
+        });
+        if let Err(e) = server.socket.send_to(&snapshot.encode(), addr) {
+            println!("Error sending snapshot to {}: {}", addr, e);
+        }
+    }
+}
+
+// Client
+
+fn client_receive(
+    mut commands: Commands,
+    mut client: ResMut<Client>,
+    time: Res<Time>,
+    local_player_q: Query<Entity, With<LocalPlayer>>,
+    mut player_q: Query<&mut Transform, With<LocalPlayer>>,
+) {
+    client.hello_timer.tick(time.delta());
+    if client.net_id.is_none() && client.hello_timer.just_finished() {
+        let _ = client
+            .socket
+            .send_to(&ClientMessage::Hello.encode(), client.server);
+    }
+
+    let mut buf = [0; MAX_PACKET_BYTES];
+    loop {
+        let (len, addr) = match client.socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => {
+                println!("Error receiving from server: {}", e);
+                break;
+            }
+        };
+        if addr != client.server {
+            continue;
+        }
+        match ServerMessage::decode(&buf[..len]) {
+            Some(ServerMessage::Welcome { net_id }) => {
+                if client.net_id.is_none() {
+                    println!("Joined as {:?}", net_id);
+                    client.net_id = Some(net_id);
+                    if let Ok(player) = local_player_q.get_single() {
+                        commands.entity(player).insert(net_id);
+                        client.entities.insert(net_id, player);
+                    }
+                }
+            }
+            Some(ServerMessage::Snapshot(snapshot)) => {
+                // Snapshots can arrive out of order, drop anything older than what we have
+                if client
+                    .snapshots
+                    .back()
+                    .is_some_and(|newest| newest.server_time >= snapshot.server_time)
+                {
+                    continue;
+                }
+                let offset = snapshot.server_time - time.elapsed_seconds();
+                // Packets that took longest to arrive make the server look further behind than it is
+                client.clock_offset = Some(client.clock_offset.map_or(offset, |o| o.max(offset)));
+                reconcile(&mut client, &snapshot, &mut player_q);
+                client.snapshots.push_back(snapshot);
+                if client.snapshots.len() > SNAPSHOT_BUFFER_LEN {
+                    client.snapshots.pop_front();
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+// Only the server launches balls, clients just draw the ones it tells them about
+fn hold_ball_launcher(mut config: ResMut<BallSpawnConfig>) {
+    if !config.timer.paused() {
+        config.timer.pause();
+    }
+}
+
+// Compare where the server says we were with where we predicted we'd be, and shift by the difference
+fn reconcile(
+    client: &mut Client,
+    snapshot: &Snapshot,
+    player_q: &mut Query<&mut Transform, With<LocalPlayer>>,
+) {
+    let Some(net_id) = client.net_id else {
+        return;
+    };
+    let Some(server_state) = snapshot
+        .entities
+        .iter()
+        .find(|state| state.net_id == net_id)
+    else {
+        return;
+    };
+    while client
+        .predictions
+        .front()
+        .is_some_and(|prediction| prediction.sequence < snapshot.acked_input)
+    {
+        client.predictions.pop_front();
+    }
+    let Some(predicted) = client.predictions.front() else {
+        return;
+    };
+    if predicted.sequence != snapshot.acked_input {
+        return;
+    }
+    let error = server_state.translation - predicted.translation;
+    if error.length() < RECONCILE_THRESHOLD {
+        return;
+    }
+    // Inputs the server hasn't seen yet still apply, just from the corrected position
+    for prediction in client.predictions.iter_mut() {
+        prediction.translation += error;
+    }
+    if let Ok(mut transform) = player_q.get_single_mut() {
+        transform.translation += error;
+    }
+}
+
+fn client_send_input(
+    mut client: ResMut<Client>,
+    player_q: Query<(Entity, &PlayerActions), With<LocalPlayer>>,
+    camera_q: Query<(&Transform, &PlayerCamera)>,
+) {
+    if client.net_id.is_none() {
+        return;
+    }
+    let Ok((player, actions)) = player_q.get_single() else {
+        return;
+    };
+    let camera_rotation = camera_q
+        .iter()
+        .find(|(_, camera)| camera.player == player)
+        .map_or(Quat::IDENTITY, |(transform, _)| transform.rotation);
+    client.input_sequence += 1;
+    let input = ClientMessage::Input {
+        sequence: client.input_sequence,
+        movement: actions.movement,
+        jump: actions.jump,
+        catch: actions.catch,
+        throw: actions.throw,
+        dash: actions.dash,
+        camera_rotation,
+    };
+    if let Err(e) = client.socket.send_to(&input.encode(), client.server) {
+        println!("Error sending input: {}", e);
+    }
+}
+
+// Remember where our player is after this frame's input, to compare against the server later
+fn client_record_prediction(
+    mut client: ResMut<Client>,
+    player_q: Query<&Transform, With<LocalPlayer>>,
+) {
+    if client.net_id.is_none() {
+        return;
+    }
+    let Ok(transform) = player_q.get_single() else {
+        return;
+    };
+    let sequence = client.input_sequence;
+    client.predictions.push_back(PredictedState {
+        sequence,
+        translation: transform.translation,
+    });
+    // Something has gone badly wrong if the server is this far behind, don't grow forever
+    if client.predictions.len() > 256 {
+        client.predictions.pop_front();
+    }
+}
+
+fn spawn_replica(
+    commands: &mut Commands,
+    assets: &AssetServer,
+    ball_assets: &BallAssets,
+    state: &EntityState,
+) -> Option<Entity> {
+    let transform = Transform::from_translation(state.translation).with_rotation(state.rotation);
+    let entity = match state.kind {
+        NetKind::Player => commands
+            .spawn((
+                SceneBundle {
+                    scene: assets.load("Player.gltf#Scene0"),
+                    transform,
+                    ..default()
+                },
+                Name::new(format!("Remote player {}", state.net_id.0)),
+            ))
+            .id(),
+        NetKind::Ball(kind) => {
+            let (mesh, material) = ball_assets.look(kind);
+            commands
+                .spawn((
+                    PbrBundle {
+                        mesh,
+                        material,
+                        transform,
+                        ..default()
+                    },
+                    Name::new("Remote ball"),
+                ))
+                .id()
+        }
+        // Platforms already exist locally
+        NetKind::Platform => return None,
+    };
+    commands.entity(entity).insert((state.net_id, Replicated));
+    Some(entity)
+}
+
+fn client_interpolate(
+    mut commands: Commands,
+    mut client: ResMut<Client>,
+    time: Res<Time>,
+    assets: Res<AssetServer>,
+    ball_assets: Res<BallAssets>,
+    mut replica_q: Query<(&mut Transform, &mut Visibility), With<Replicated>>,
+) {
+    let Some(clock_offset) = client.clock_offset else {
+        return;
+    };
+    let render_time = time.elapsed_seconds() + clock_offset - INTERPOLATION_DELAY_SECS;
+
+    // The pair of snapshots either side of render time, or just the newest if we've run ahead
+    let newer_index = client
+        .snapshots
+        .iter()
+        .position(|snapshot| snapshot.server_time >= render_time);
+    let (from, to, t) = match newer_index {
+        Some(0) | None => {
+            let Some(newest) = client.snapshots.back() else {
+                return;
+            };
+            (newest, newest, 0.0)
+        }
+        Some(i) => {
+            let from = &client.snapshots[i - 1];
+            let to = &client.snapshots[i];
+            let t = (render_time - from.server_time) / (to.server_time - from.server_time);
+            (from, to, t.clamp(0.0, 1.0))
+        }
+    };
+
+    let own_net_id = client.net_id;
+    let mut seen = Vec::with_capacity(to.entities.len());
+    let mut spawned = Vec::new();
+    for state in &to.entities {
+        // Our own player is predicted locally
+        if Some(state.net_id) == own_net_id {
+            continue;
+        }
+        seen.push(state.net_id);
+        let (translation, rotation) = match from
+            .entities
+            .iter()
+            .find(|previous| previous.net_id == state.net_id)
+        {
+            Some(previous) => (
+                previous.translation.lerp(state.translation, t),
+                previous.rotation.slerp(state.rotation, t),
+            ),
+            None => (state.translation, state.rotation),
+        };
+        match client.entities.get(&state.net_id) {
+            Some(&entity) => {
+                if let Ok((mut transform, mut visibility)) = replica_q.get_mut(entity) {
+                    transform.translation = translation;
+                    transform.rotation = rotation;
+                    *visibility = Visibility::Inherited;
+                }
+            }
+            None => {
+                if let Some(entity) = spawn_replica(&mut commands, &assets, &ball_assets, state) {
+                    spawned.push((state.net_id, entity));
+                }
+            }
+        }
+    }
+
+    // Anything the server stopped mentioning is gone for now, pooled balls come back with the same id
+    for (net_id, &entity) in client.entities.iter() {
+        if !seen.contains(net_id) {
+            if let Ok((_, mut visibility)) = replica_q.get_mut(entity) {
+                *visibility = Visibility::Hidden;
+            }
+        }
+    }
+    client.entities.extend(spawned);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input() -> ClientMessage {
+        ClientMessage::Input {
+            sequence: 42,
+            movement: Vec2::new(0.3, -0.4),
+            jump: true,
+            catch: false,
+            throw: true,
+            dash: true,
+            camera_rotation: Quat::from_rotation_y(1.2),
+        }
+    }
+
+    fn snapshot() -> ServerMessage {
+        ServerMessage::Snapshot(Snapshot {
+            server_time: 12.5,
+            acked_input: 7,
+            entities: vec![
+                EntityState {
+                    net_id: NetId(3),
+                    kind: NetKind::Player,
+                    translation: Vec3::new(1.0, 2.0, 3.0),
+                    rotation: Quat::from_rotation_y(0.5),
+                    linvel: Vec3::new(-1.0, 0.0, 4.5),
+                },
+                EntityState {
+                    net_id: NetId(9),
+                    kind: NetKind::Ball(BallKind::Splitter),
+                    translation: Vec3::new(-4.0, 1.0, 0.0),
+                    rotation: Quat::IDENTITY,
+                    linvel: Vec3::new(30.0, 1.0, 0.0),
+                },
+                EntityState {
+                    net_id: platform_net_id("Cubeovator"),
+                    kind: NetKind::Platform,
+                    translation: Vec3::new(0.0, 0.5, -6.0),
+                    rotation: Quat::from_rotation_x(-0.3),
+                    linvel: Vec3::ZERO,
+                },
+            ],
+        })
+    }
+
+    #[test]
+    fn client_messages_round_trip() {
+        assert!(matches!(
+            ClientMessage::decode(&ClientMessage::Hello.encode()),
+            Some(ClientMessage::Hello)
+        ));
+
+        let Some(ClientMessage::Input {
+            sequence,
+            movement,
+            jump,
+            catch,
+            throw,
+            dash,
+            camera_rotation,
+        }) = ClientMessage::decode(&input().encode())
+        else {
+            panic!("input didn't decode");
+        };
+        assert_eq!(sequence, 42);
+        assert_eq!(movement, Vec2::new(0.3, -0.4));
+        assert_eq!((jump, catch, throw, dash), (true, false, true, true));
+        assert!(camera_rotation.abs_diff_eq(Quat::from_rotation_y(1.2), 1e-6));
+    }
+
+    #[test]
+    fn server_messages_round_trip() {
+        let Some(ServerMessage::Welcome { net_id }) =
+            ServerMessage::decode(&ServerMessage::Welcome { net_id: NetId(5) }.encode())
+        else {
+            panic!("welcome didn't decode");
+        };
+        assert_eq!(net_id, NetId(5));
+
+        let (ServerMessage::Snapshot(sent), Some(ServerMessage::Snapshot(received))) =
+            (snapshot(), ServerMessage::decode(&snapshot().encode()))
+        else {
+            panic!("snapshot didn't decode");
+        };
+        assert_eq!(received.server_time, sent.server_time);
+        assert_eq!(received.acked_input, sent.acked_input);
+        assert_eq!(received.entities.len(), sent.entities.len());
+        for (received, sent) in received.entities.iter().zip(&sent.entities) {
+            assert_eq!(received.net_id, sent.net_id);
+            assert_eq!(received.kind, sent.kind);
+            assert_eq!(received.translation, sent.translation);
+            assert!(received.rotation.abs_diff_eq(sent.rotation, 1e-6));
+            assert_eq!(received.linvel, sent.linvel);
+        }
+    }
+
+    #[test]
+    fn every_ball_kind_round_trips() {
+        for kind in BALL_KINDS {
+            let kind = NetKind::Ball(kind);
+            assert_eq!(NetKind::decode(kind.encode()), Some(kind));
+        }
+        assert_eq!(NetKind::decode(2 + BALL_KINDS.len() as u8), None);
+        assert_eq!(NetKind::decode(u8::MAX), None);
+    }
+
+    #[test]
+    fn truncated_messages_are_dropped() {
+        let input = input().encode();
+        for len in 0..input.len() {
+            assert!(
+                ClientMessage::decode(&input[..len]).is_none(),
+                "{len} bytes"
+            );
+        }
+        let snapshot = snapshot().encode();
+        for len in 0..snapshot.len() {
+            assert!(
+                ServerMessage::decode(&snapshot[..len]).is_none(),
+                "{len} bytes"
+            );
+        }
+        assert!(ServerMessage::decode(&[0, 1, 2]).is_none());
+    }
+
+    #[test]
+    fn corrupt_messages_are_dropped() {
+        assert!(ClientMessage::decode(&[9]).is_none());
+        assert!(ServerMessage::decode(&[9, 0, 0, 0, 0]).is_none());
+
+        // An entity count far bigger than the packet
+        let mut bytes = snapshot().encode();
+        bytes[9..13].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(ServerMessage::decode(&bytes).is_none());
+
+        // An unknown entity kind
+        let mut bytes = snapshot().encode();
+        bytes[17] = u8::MAX;
+        assert!(ServerMessage::decode(&bytes).is_none());
+
+        // A NaN movement
+        let mut bytes = input().encode();
+        bytes[5..9].copy_from_slice(&f32::NAN.to_le_bytes());
+        assert!(ClientMessage::decode(&bytes).is_none());
+
+        // A camera rotation of all zeroes can't be normalized
+        let mut bytes = input().encode();
+        let len = bytes.len();
+        bytes[len - 16..].fill(0);
+        assert!(ClientMessage::decode(&bytes).is_none());
+    }
+}
@@ -0,0 +1,366 @@
+//! Particle effects, run on the CPU.
+//!
+//! Balls throw up dust when they land on the ground and sparks when they hit anything else,
+//! launchers give a burst as a ball leaves them, and players flash when they're hit. Anything else
+//! can set an effect off by sending a `SpawnEffect`.
+//!
+//! What each effect looks like is in `assets/effects.ron`. Anything missing from the file, a whole
+//! effect or one setting of it, takes `EffectDef`'s plain default.
+//! Particles are small meshes from a pool made up front. When the pool runs dry new particles are
+//! skipped rather than the pool growing, so a busy moment can't run away with the frame rate.
+
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_rapier3d::prelude::*;
+use rand::prelude::*;
+use serde::Deserialize;
+use std::f32::consts::TAU;
+
+use crate::ball::{Ball, BallKind};
+use crate::bot::Headless;
+use crate::player::{HitStun, Player};
+use crate::world::{Ground, SimulationSet};
+
+const EFFECTS_PATH: &str = "assets/effects.ron";
+const MAX_PARTICLES: usize = 512;
+const GRAVITY: f32 = 9.81;
+// Impacts slower than this don't throw anything up
+const MIN_IMPACT_SPEED: f32 = 3.0;
+// Impacts at this speed give the whole effect, slower ones less of it
+const FULL_IMPACT_SPEED: f32 = 20.0;
+// A rolling ball keeps reporting contact forces, so each ball sets an effect off at most this often
+const IMPACT_COOLDOWN_SECS: f32 = 0.15;
+
+pub struct ParticlePlugin;
+
+impl Plugin for ParticlePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SpawnEffect>()
+            .insert_resource(EffectLibrary::load())
+            .add_systems(
+                Startup,
+                setup_particles.run_if(not(resource_exists::<Headless>)),
+            )
+            .add_systems(
+                Update,
+                (
+                    (emit_impact_effects, emit_hit_flashes),
+                    spawn_particles,
+                    update_particles,
+                )
+                    .chain()
+                    .after(SimulationSet)
+                    .run_if(resource_exists::<ParticlePool>),
+            );
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Effect {
+    Dust,
+    Sparks,
+    MuzzleBurst,
+    HitFlash,
+}
+
+impl Effect {
+    const ALL: [Effect; 4] = [
+        Effect::Dust,
+        Effect::Sparks,
+        Effect::MuzzleBurst,
+        Effect::HitFlash,
+    ];
+}
+
+/// Sets off an effect.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct SpawnEffect {
+    pub effect: Effect,
+    pub position: Vec3,
+    /// Which way the particles mostly go
+    pub direction: Vec3,
+    /// Scales how many particles there are, 1 is as many as the effect says
+    pub strength: f32,
+}
+
+/// How one effect looks.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct EffectDef {
+    pub count: usize,
+    /// Shortest and longest a particle lives
+    pub lifetime_secs: [f32; 2],
+    /// Slowest and fastest a particle starts out
+    pub speed: [f32; 2],
+    /// Radians either side of the effect's direction that particles go off in
+    pub spread: f32,
+    /// Size when a particle starts and when it dies
+    pub size: [f32; 2],
+    /// Hex, with alpha if it's see-through
+    pub color: String,
+    /// How much the colour glows, 0 for not at all
+    pub glow: f32,
+    /// Times normal gravity
+    pub gravity: f32,
+    /// Fraction of its speed a particle loses each second
+    pub drag: f32,
+}
+
+impl Default for EffectDef {
+    fn default() -> Self {
+        Self {
+            count: 8,
+            lifetime_secs: [0.3, 0.6],
+            speed: [1.0, 3.0],
+            spread: 0.8,
+            size: [0.1, 0.0],
+            color: "#ffffff".to_string(),
+            glow: 0.0,
+            gravity: 1.0,
+            drag: 1.0,
+        }
+    }
+}
+
+/// Every effect, as read from `assets/effects.ron`.
+#[derive(Resource, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct EffectLibrary {
+    pub dust: EffectDef,
+    pub sparks: EffectDef,
+    pub muzzle_burst: EffectDef,
+    pub hit_flash: EffectDef,
+}
+
+impl EffectLibrary {
+    fn load() -> Self {
+        let text = match std::fs::read_to_string(EFFECTS_PATH) {
+            Ok(text) => text,
+            Err(error) => {
+                println!("Couldn't read {}: {}", EFFECTS_PATH, error);
+                return Self::default();
+            }
+        };
+        ron::from_str(&text).unwrap_or_else(|error| {
+            println!("Ignoring {}: {}", EFFECTS_PATH, error);
+            Self::default()
+        })
+    }
+
+    pub fn get(&self, effect: Effect) -> &EffectDef {
+        match effect {
+            Effect::Dust => &self.dust,
+            Effect::Sparks => &self.sparks,
+            Effect::MuzzleBurst => &self.muzzle_burst,
+            Effect::HitFlash => &self.hit_flash,
+        }
+    }
+}
+
+#[derive(Component)]
+struct Particle {
+    velocity: Vec3,
+    age: f32,
+    lifetime: f32,
+    size: [f32; 2],
+    gravity: f32,
+    drag: f32,
+}
+
+/// Particles that aren't showing, ready to go.
+#[derive(Resource, Default)]
+struct ParticlePool {
+    free: Vec<Entity>,
+}
+
+#[derive(Resource)]
+struct ParticleMaterials(HashMap<Effect, Handle<StandardMaterial>>);
+
+fn setup_particles(
+    mut commands: Commands,
+    library: Res<EffectLibrary>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let effect_materials = Effect::ALL
+        .into_iter()
+        .map(|effect| {
+            let def = library.get(effect);
+            let color = Srgba::hex(&def.color).unwrap_or_else(|_| {
+                println!("Effect {:?} has a bad colour {:?}", effect, def.color);
+                Srgba::WHITE
+            });
+            let material = materials.add(StandardMaterial {
+                base_color: color.into(),
+                emissive: Color::from(color).to_linear() * def.glow,
+                alpha_mode: if color.alpha < 1.0 {
+                    AlphaMode::Blend
+                } else {
+                    AlphaMode::Opaque
+                },
+                ..default()
+            });
+            (effect, material)
+        })
+        .collect();
+
+    // A unit sphere, scaled to each particle's size
+    let mesh = meshes.add(Sphere::new(0.5).mesh().ico(1).unwrap());
+    let mut pool = ParticlePool::default();
+    for _ in 0..MAX_PARTICLES {
+        let particle = commands
+            .spawn((
+                PbrBundle {
+                    mesh: mesh.clone(),
+                    visibility: Visibility::Hidden,
+                    ..default()
+                },
+                NotShadowCaster,
+                Name::new("Particle"),
+            ))
+            .id();
+        pool.free.push(particle);
+    }
+    commands.insert_resource(pool);
+    commands.insert_resource(ParticleMaterials(effect_materials));
+}
+
+fn emit_impact_effects(
+    time: Res<Time>,
+    mut contact_force_events: EventReader<ContactForceEvent>,
+    mut effects: EventWriter<SpawnEffect>,
+    mut last_emitted: Local<HashMap<Entity, f32>>,
+    ball_q: Query<(&Transform, &Velocity, &BallKind), With<Ball>>,
+    ground_q: Query<(), With<Ground>>,
+    player_q: Query<(), With<Player>>,
+) {
+    let now = time.elapsed_seconds();
+    for event in contact_force_events.read() {
+        let (ball, other) = match (
+            ball_q.contains(event.collider1),
+            ball_q.contains(event.collider2),
+        ) {
+            (true, _) => (event.collider1, event.collider2),
+            (_, true) => (event.collider2, event.collider1),
+            _ => continue,
+        };
+        // Hitting a player gets a flash of its own
+        if player_q.contains(other)
+            || last_emitted
+                .get(&ball)
+                .is_some_and(|&emitted| now - emitted < IMPACT_COOLDOWN_SECS)
+        {
+            continue;
+        }
+        let (transform, velocity, kind) = ball_q.get(ball).unwrap();
+        let speed = velocity.linvel.length();
+        if speed < MIN_IMPACT_SPEED {
+            continue;
+        }
+        last_emitted.insert(ball, now);
+
+        let strength = (speed / FULL_IMPACT_SPEED).clamp(0.3, 1.0);
+        let effect = if ground_q.contains(other) {
+            SpawnEffect {
+                effect: Effect::Dust,
+                position: transform.translation - Vec3::Y * kind.radius(),
+                direction: Vec3::Y,
+                strength,
+            }
+        } else {
+            // Sparks fly back the way the ball came
+            SpawnEffect {
+                effect: Effect::Sparks,
+                position: transform.translation,
+                direction: -velocity.linvel,
+                strength,
+            }
+        };
+        effects.send(effect);
+    }
+    last_emitted.retain(|_, emitted| now - *emitted < IMPACT_COOLDOWN_SECS);
+}
+
+fn emit_hit_flashes(
+    mut effects: EventWriter<SpawnEffect>,
+    hit_q: Query<&Transform, (With<Player>, Added<HitStun>)>,
+) {
+    for transform in hit_q.iter() {
+        effects.send(SpawnEffect {
+            effect: Effect::HitFlash,
+            position: transform.translation,
+            direction: Vec3::Y,
+            strength: 1.0,
+        });
+    }
+}
+
+// Somewhere within `spread` radians of `direction`
+fn random_direction(rng: &mut impl Rng, direction: Vec3, spread: f32) -> Vec3 {
+    let axis = direction.normalize_or(Vec3::Y);
+    let tilt = Quat::from_axis_angle(axis.any_orthonormal_vector(), rng.gen_range(0.0..=spread));
+    let turn = Quat::from_axis_angle(axis, rng.gen_range(0.0..TAU));
+    turn * tilt * axis
+}
+
+fn spawn_particles(
+    mut commands: Commands,
+    mut effects: EventReader<SpawnEffect>,
+    library: Res<EffectLibrary>,
+    materials: Res<ParticleMaterials>,
+    mut pool: ResMut<ParticlePool>,
+) {
+    let mut rng = thread_rng();
+    for spawn in effects.read() {
+        let def = library.get(spawn.effect);
+        let count = (def.count as f32 * spawn.strength).round() as usize;
+        for _ in 0..count {
+            let Some(particle) = pool.free.pop() else {
+                return;
+            };
+            let speed = rng.gen_range(def.speed[0]..=def.speed[1].max(def.speed[0]));
+            let lifetime = rng
+                .gen_range(def.lifetime_secs[0]..=def.lifetime_secs[1].max(def.lifetime_secs[0]));
+            commands.entity(particle).insert((
+                Particle {
+                    velocity: random_direction(&mut rng, spawn.direction, def.spread) * speed,
+                    age: 0.0,
+                    lifetime,
+                    size: def.size,
+                    gravity: def.gravity,
+                    drag: def.drag,
+                },
+                materials.0[&spawn.effect].clone(),
+                Transform::from_translation(spawn.position).with_scale(Vec3::splat(def.size[0])),
+                Visibility::Inherited,
+            ));
+        }
+    }
+}
+
+fn update_particles(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut pool: ResMut<ParticlePool>,
+    mut particle_q: Query<(Entity, &mut Particle, &mut Transform)>,
+) {
+    let delta_time = time.delta_seconds();
+    for (entity, mut particle, mut transform) in particle_q.iter_mut() {
+        particle.age += delta_time;
+        if particle.age >= particle.lifetime {
+            commands
+                .entity(entity)
+                .remove::<Particle>()
+                .insert(Visibility::Hidden);
+            pool.free.push(entity);
+            continue;
+        }
+        particle.velocity.y -= GRAVITY * particle.gravity * delta_time;
+        let drag = (1.0 - particle.drag * delta_time).max(0.0);
+        particle.velocity *= drag;
+        transform.translation += particle.velocity * delta_time;
+        let [start, end] = particle.size;
+        transform.scale = Vec3::splat(start.lerp(end, particle.age / particle.lifetime));
+    }
+}
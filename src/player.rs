@@ -1,4 +1,12 @@
-use crate::world::Ground;
+use crate::ball::{activate_ball, park_ball, Ball, BallAssets, BallKind, BallLifetime, ThrownBy};
+use crate::camera::PlayerCamera;
+use crate::flashlight::{Flashlight, FlashlightBeam, FLASHLIGHT_INTENSITY};
+use crate::input::{InputDevice, LocalPlayer, PlayerActions};
+use crate::powerup::{
+    has_power_up, ActivePowerUp, PowerUpKind, ShieldBlocked, MAGNET_CATCH_RADIUS, SPEED_BOOST_SCALE,
+};
+use crate::world::{Ground, SimulationSet};
+use bevy::ecs::schedule::SystemConfigs;
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 use bevy_third_person_camera::*;
@@ -6,20 +14,104 @@ use bevy_third_person_camera::*;
 const MOVEMENT_SPEED: f32 = 8.0;
 const JUMP_SPEED: f32 = 22.8;
 
+// Knockback impulse is the ball's momentum scaled down by this, capped so heavy balls don't launch us into orbit
+const KNOCKBACK_PER_MOMENTUM: f32 = 0.007;
+const MAX_KNOCKBACK_IMPULSE: f32 = 700.0;
+const HIT_STUN_SECS: f32 = 0.6;
+// Balls slower than this are rolling into the player rather than hitting them
+const MIN_HIT_SPEED: f32 = 4.0;
+// Hits harder than this knock the player over rather than just shoving them
+const RAGDOLL_MOMENTUM: f32 = 60_000.0;
+const RAGDOLL_SECS: f32 = 1.5;
+
+// Balls this close to the player's middle can be caught
+const CATCH_RADIUS: f32 = 1.3;
+// How long a catch attempt lasts, and how long before the next one, so catch can't just be held down
+const CATCH_WINDOW_SECS: f32 = 0.35;
+const CATCH_COOLDOWN_SECS: f32 = 0.8;
+pub(crate) const THROW_SPEED: f32 = 28.0;
+// Throws aim a little above the camera's line so they don't go straight into the floor
+const THROW_LIFT: f32 = 0.1;
+const DASH_SPEED: f32 = 14.0;
+const DASH_COOLDOWN_SECS: f32 = 1.2;
+
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, spawn_player)
-            .add_systems(Update, (player_movement, grounded_ungrounded_on_collision))
+        app.add_systems(Startup, spawn_players)
+            .init_resource::<HitReactionSettings>()
+            .add_event::<BallCaught>()
+            .add_systems(Update, simulation_systems().in_set(SimulationSet))
+            .add_systems(Update, carry_held_balls.after(SimulationSet))
             // .add_systems(Update, (read_character_controller_collisions, debug_player_hit))
             ;
     }
 }
 
+/// Player systems that move the game on. A rollback session steps these itself.
+pub(crate) fn simulation_systems() -> SystemConfigs {
+    (
+        grounded_ungrounded_on_collision,
+        player_movement,
+        dash,
+        catch_ball,
+        throw_ball,
+        knockback_on_ball_hit,
+        recover_from_hit,
+    )
+        .chain()
+}
+
 #[derive(Component)]
 pub struct Player;
-#[derive(Component, PartialEq)]
+
+/// How many people are playing on this machine, from `--players <1-4>`.
+#[derive(Resource)]
+pub struct LocalPlayers {
+    pub count: usize,
+}
+
+impl LocalPlayers {
+    pub const MAX: usize = 4;
+
+    pub fn from_args() -> Self {
+        let mut args = std::env::args()
+            .skip_while(|arg| arg != "--players")
+            .skip(1);
+        let count = args
+            .next()
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(1)
+            .clamp(1, Self::MAX);
+        Self { count }
+    }
+}
+
+#[derive(Component, Reflect, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Team {
+    Home,
+    Away,
+}
+
+impl Team {
+    pub fn for_slot(slot: usize) -> Self {
+        if slot % 2 == 0 {
+            Team::Home
+        } else {
+            Team::Away
+        }
+    }
+
+    /// Which half of the arena along x this team plays on
+    pub fn side(self) -> f32 {
+        match self {
+            Team::Home => -1.0,
+            Team::Away => 1.0,
+        }
+    }
+}
+#[derive(Component, PartialEq, Clone)]
 // We also want to track the id of the ground he is touching
 // If he is on one ground only and that ground is moving, then we want to apply the translation to the player
 pub struct Grounded {
@@ -30,69 +122,210 @@ pub struct Grounded {
 #[derive(Component)]
 struct PlayerGroundedSensor;
 
+/// The player has just been hit and can't steer until the timer runs out.
+#[derive(Component, Reflect, Clone, Debug)]
+pub struct HitStun {
+    timer: Timer,
+    /// Rotation is unlocked while knocked over, so the body tumbles instead of sliding, and the
+    /// limbs go limp
+    knocked_over: bool,
+}
+
+impl HitStun {
+    pub fn knocked_over(&self) -> bool {
+        self.knocked_over
+    }
+}
+
+/// A ball the player has caught or picked up. It stays parked, out of the physics, until thrown.
+#[derive(Component, Clone)]
+pub struct HeldBall {
+    pub ball: Entity,
+    pub kind: BallKind,
+    /// Throw has been let go since the catch, so holding it down doesn't throw straight back
+    armed: bool,
+}
+
+impl HeldBall {
+    pub fn new(ball: Entity, kind: BallKind) -> Self {
+        Self {
+            ball,
+            kind,
+            armed: false,
+        }
+    }
+}
+
+/// Pulls this player's throws towards a point, the target's predicted position when locked on.
+#[derive(Component, Clone, Copy)]
+pub struct AimAssist {
+    pub point: Vec3,
+    /// 0 throws where the camera looks, 1 throws straight at `point`
+    pub strength: f32,
+}
+
+/// Where the player is in a catch attempt.
+#[derive(Component, Clone, Default)]
+pub struct CatchState {
+    window_secs: f32,
+    cooldown_secs: f32,
+    was_pressed: bool,
+}
+
+impl CatchState {
+    pub fn is_catching(&self) -> bool {
+        self.window_secs > 0.0
+    }
+}
+
+/// Counts down until the player can dash again.
+#[derive(Component, Clone, Default)]
+pub struct DashState {
+    cooldown_secs: f32,
+    was_pressed: bool,
+}
+
+impl DashState {
+    pub fn is_ready(&self) -> bool {
+        self.cooldown_secs == 0.0
+    }
+
+    /// How long ago the player dashed, up to the cooldown.
+    pub fn secs_since_dash(&self) -> f32 {
+        DASH_COOLDOWN_SECS - self.cooldown_secs
+    }
+}
+
+/// Sent when a player catches a ball or picks one up.
+#[derive(Event, Debug)]
+pub struct BallCaught {
+    pub catcher: Entity,
+    /// Who threw it, if it was caught out of the air
+    pub thrower: Option<Entity>,
+}
+
+#[derive(Resource)]
+pub struct HitReactionSettings {
+    /// Whether big hits knock the player over and leave their limbs limp
+    pub ragdoll: bool,
+}
+
+impl Default for HitReactionSettings {
+    fn default() -> Self {
+        Self { ragdoll: true }
+    }
+}
+
 // Check for Some(Cubeovator)
 fn player_movement(
-    keys: Res<ButtonInput<KeyCode>>,
     time: Res<Time>,
-    mut player_q: Query<(&mut Transform, &mut Velocity, &Grounded), With<Player>>,
-    cam_q: Query<&Transform, (With<Camera3d>, Without<Player>)>,
+    mut player_q: Query<
+        (
+            Entity,
+            &mut Transform,
+            &mut Velocity,
+            &Grounded,
+            &PlayerActions,
+            Option<&HitStun>,
+            Option<&ActivePowerUp>,
+        ),
+        With<Player>,
+    >,
+    cam_q: Query<(&Transform, &PlayerCamera), Without<Player>>,
 ) {
-    // let (mut player_transform, mut _controller, _output) = player_q.single_mut();
-    let (mut player_transform, mut player_velocity, grounded) = player_q.single_mut();
-
-    let cam = match cam_q.get_single() {
-        Ok(c) => c,
-        Err(e) => Err(format!("Error retrieving camera: {}", e)).unwrap(),
-    };
-
-    let mut direction = Vec3::ZERO;
-    let mut movement_linvel = Vec3::ZERO;
-    // We need to remove the y component out of these
-    if keys.pressed(KeyCode::KeyW) {
-        if grounded.count > 0 {
-            direction += (*cam.forward()).with_y(0.0);
+    for (
+        entity,
+        mut player_transform,
+        mut player_velocity,
+        grounded,
+        actions,
+        hit_stun,
+        power_up,
+    ) in player_q.iter_mut()
+    {
+        if hit_stun.is_some() {
+            continue;
         }
-    }
 
-    if keys.pressed(KeyCode::KeyS) {
-        if grounded.count > 0 {
-            direction += (*cam.back()).with_y(0.0);
-        }
-    }
+        let Some((cam, _)) = cam_q.iter().find(|(_, camera)| camera.player == entity) else {
+            continue;
+        };
 
-    if keys.pressed(KeyCode::KeyA) {
+        let mut direction = Vec3::ZERO;
+        let mut movement_linvel = Vec3::ZERO;
+        // We need to remove the y component out of these
         if grounded.count > 0 {
-            direction += (*cam.left()).with_y(0.0);
+            direction += (*cam.forward()).with_y(0.0) * actions.movement.y;
+            direction += (*cam.right()).with_y(0.0) * actions.movement.x;
         }
-    }
 
-    if keys.pressed(KeyCode::KeyD) {
-        if grounded.count > 0 {
-            direction += (*cam.right()).with_y(0.0);
+        if actions.jump {
+            // jump_direction = 1.0;
+            if grounded.count > 0 {
+                movement_linvel += Vec3::new(0.0, JUMP_SPEED, 0.0);
+            }
         }
-    }
 
-    if keys.pressed(KeyCode::KeyE) {
-        // jump_direction = 1.0;
-        if grounded.count > 0 {
-            movement_linvel += Vec3::new(0.0, JUMP_SPEED, 0.0);
-        }
-    }
+        let delta_time = time.delta_seconds();
 
-    let delta_time = time.delta_seconds();
+        // Gamepad sticks can ask for less than full speed
+        let throttle = actions.movement.length().min(1.0);
+        let speed = if has_power_up(power_up, PowerUpKind::SpeedBoost) {
+            MOVEMENT_SPEED * SPEED_BOOST_SCALE
+        } else {
+            MOVEMENT_SPEED
+        };
+        let movement = direction.normalize_or_zero() * throttle * speed * delta_time;
+        movement_linvel += movement * 5.0;
 
-    let movement = direction.normalize_or_zero() * MOVEMENT_SPEED * delta_time;
-    movement_linvel += movement * 5.0;
+        player_transform.translation += movement;
+        player_velocity.linvel += movement_linvel;
 
-    player_transform.translation += movement;
-    player_velocity.linvel += movement_linvel;
+        if direction.length_squared() > 0.0 {
+            player_transform.look_to(direction, Vec3::Y)
+        }
+    }
+}
 
-    if direction.length_squared() > 0.0 {
-        player_transform.look_to(direction, Vec3::Y)
+// Players on the same team start next to each other on their own half
+pub(crate) fn spawn_point(slot: usize, player_count: usize) -> Vec3 {
+    if player_count == 1 {
+        return Vec3::new(0.0, 1.8, 0.0);
     }
+    let team = Team::for_slot(slot);
+    let z = (slot / 2) as f32 * 2.0 - 1.0;
+    Vec3::new(team.side() * 3.0, 1.8, z)
 }
 
-fn spawn_player(mut commands: Commands, assets: Res<AssetServer>) {
+fn spawn_players(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    local_players: Res<LocalPlayers>,
+) {
+    for slot in 0..local_players.count {
+        let player = spawn_player(
+            &mut commands,
+            &assets,
+            spawn_point(slot, local_players.count),
+        );
+        let team = Team::for_slot(slot);
+        commands.entity(player).insert((
+            LocalPlayer {
+                slot,
+                device: InputDevice::for_slot(slot),
+            },
+            team,
+            Name::new(format!("Player {} ({:?})", slot + 1, team)),
+        ));
+        // The third person camera plugin can only follow one target
+        if local_players.count == 1 {
+            commands.entity(player).insert(ThirdPersonCameraTarget);
+        }
+    }
+}
+
+/// Spawns a player body at `translation` with no controller attached.
+pub fn spawn_player(commands: &mut Commands, assets: &AssetServer, translation: Vec3) -> Entity {
     //216, 224, 69, 255
     let flashlight = (
         SpotLightBundle {
@@ -100,7 +333,7 @@ fn spawn_player(mut commands: Commands, assets: Res<AssetServer>) {
                 color: Srgba::rgba_u8(216, 224, 69, 255).into(),
                 outer_angle: 0.6,
                 inner_angle: 0.5,
-                intensity: 1000000.0,
+                intensity: FLASHLIGHT_INTENSITY,
                 shadows_enabled: true,
                 ..default()
             },
@@ -108,16 +341,16 @@ fn spawn_player(mut commands: Commands, assets: Res<AssetServer>) {
 
             ..default()
         },
+        FlashlightBeam,
         Name::new("Flashlight"),
     );
     let player = (
         SceneBundle {
             scene: assets.load("Player.gltf#Scene0"),
-            transform: Transform::from_xyz(0.0, 1.8, 0.0),
+            transform: Transform::from_translation(translation),
             ..default()
         },
         Player,
-        ThirdPersonCameraTarget,
         Name::new("Player"),
         Collider::cone(0.8, 0.3),
         RigidBody::Dynamic,
@@ -125,6 +358,10 @@ fn spawn_player(mut commands: Commands, assets: Res<AssetServer>) {
             count: 0,
             entities: vec![],
         },
+        PlayerActions::default(),
+        CatchState::default(),
+        DashState::default(),
+        Flashlight::default(),
         // HitStatus {is_hit: false, normal1_of_hit: None}
     );
     commands
@@ -137,6 +374,7 @@ fn spawn_player(mut commands: Commands, assets: Res<AssetServer>) {
         .insert(GravityScale(5.5))
         .insert(AdditionalMassProperties::Mass(20.0))
         .insert(LockedAxes::ROTATION_LOCKED)
+        .insert(ExternalImpulse::default())
         .insert(ActiveEvents::COLLISION_EVENTS)
         .with_children(|parent| {
             parent.spawn(flashlight);
@@ -145,60 +383,301 @@ fn spawn_player(mut commands: Commands, assets: Res<AssetServer>) {
                 .spawn((PlayerGroundedSensor, Collider::cone(1.0, 0.3)))
                 .insert(ActiveEvents::COLLISION_EVENTS)
                 .insert(Sensor);
-        });
+        })
+        .id()
 }
 
 // Instead we probably just want to check if the player is in close proximity to the ground.  Less buggy.
 // Doo this with a ground sensor
 fn grounded_ungrounded_on_collision(
     mut collision_events: EventReader<CollisionEvent>,
-    mut player_q: Query<(&mut Grounded, &Children), With<Player>>,
+    mut player_q: Query<&mut Grounded, With<Player>>,
     ground_q: Query<Entity, With<Ground>>,
-    player_ground_sensor_collider_q: Query<Entity, With<PlayerGroundedSensor>>,
+    player_ground_sensor_collider_q: Query<&Parent, With<PlayerGroundedSensor>>,
+) {
+    for collision_event in collision_events.read() {
+        let (entity1, entity2, started) = match collision_event {
+            CollisionEvent::Started(entity1, entity2, _flags) => (entity1, entity2, true),
+            CollisionEvent::Stopped(entity1, entity2, _flags) => (entity1, entity2, false),
+        };
+        // Each sensor is a child of the player it belongs to
+        let (sensor_parent, other_entity) = match (
+            player_ground_sensor_collider_q.get(*entity1),
+            player_ground_sensor_collider_q.get(*entity2),
+        ) {
+            (Ok(parent), _) => (parent, entity2),
+            (_, Ok(parent)) => (parent, entity1),
+            _ => continue,
+        };
+        let Ok(ground) = ground_q.get(*other_entity) else {
+            continue;
+        };
+        let Ok(mut player_grounded) = player_q.get_mut(sensor_parent.get()) else {
+            continue;
+        };
+        if started {
+            player_grounded.count += 1;
+            player_grounded.entities.push(ground);
+            println!("New ground, total count: {}", player_grounded.count);
+        } else {
+            player_grounded.count = player_grounded.count.saturating_sub(1);
+            player_grounded.entities.retain(|&x| x != ground);
+            println!("Left ground, total count: {}", player_grounded.count);
+        }
+    }
+}
+
+// Shove the player away from the ball, harder the more momentum it was carrying
+fn knockback_on_ball_hit(
+    mut commands: Commands,
+    mut collision_events: EventReader<CollisionEvent>,
+    mut blocked_events: EventWriter<ShieldBlocked>,
+    settings: Res<HitReactionSettings>,
+    mut player_q: Query<
+        (
+            Entity,
+            &Transform,
+            &mut ExternalImpulse,
+            Option<&ActivePowerUp>,
+        ),
+        (With<Player>, Without<HitStun>),
+    >,
+    ball_q: Query<
+        (&Transform, &Velocity, &ReadMassProperties),
+        (With<Ball>, Without<RigidBodyDisabled>),
+    >,
 ) {
-    let (mut player_grounded, children) = player_q.single_mut();
-    for &child in children.iter() {
-        if let Ok(player_ground_sensor_id) = player_ground_sensor_collider_q.get(child) {
-            for collision_event in collision_events.read() {
-                // let entity1 = collision_event.0;
-                match collision_event {
-                    CollisionEvent::Started(entity1, entity2, _flags) => {
-                        let (some_player_collision_entity, other_entity) =
-                            match player_ground_sensor_id {
-                                id if id == *entity1 => (Some(entity1), entity2),
-                                id if id == *entity2 => (Some(entity2), entity1),
-                                _ => (None, entity1),
-                            };
-                        if let Some(_player_collision_entity) = some_player_collision_entity {
-                            let first_ground =
-                                ground_q.iter().filter(|&g| g == *other_entity).next();
-                            if let Some(_ground) = first_ground {
-                                player_grounded.count += 1;
-                                player_grounded.entities.push(_ground);
-                                // *player_grounded = Grounded(player_grounded.count + 1);
-                                println!("New ground, total count: {}", player_grounded.count);
-                            }
-                        }
-                    }
-                    CollisionEvent::Stopped(entity1, entity2, _flags) => {
-                        let (some_player_collision_entity, other_entity) =
-                            match player_ground_sensor_id {
-                                id if id == *entity1 => (Some(entity1), entity2),
-                                id if id == *entity2 => (Some(entity2), entity1),
-                                _ => (None, entity1),
-                            };
-                        if let Some(_player_collision_entity) = some_player_collision_entity {
-                            let first_ground =
-                                ground_q.iter().filter(|&g| g == *other_entity).next();
-                            if let Some(_ground) = first_ground {
-                                player_grounded.count = std::cmp::max(0, player_grounded.count - 1);
-                                player_grounded.entities.retain(|&x| x != _ground);
-                                println!("Left ground, total count: {}", player_grounded.count);
-                            }
-                        }
-                    }
+    for collision_event in collision_events.read() {
+        let CollisionEvent::Started(entity1, entity2, _flags) = collision_event else {
+            continue;
+        };
+        let (player_entity, ball_entity) =
+            match (player_q.contains(*entity1), ball_q.contains(*entity2)) {
+                (true, true) => (*entity1, *entity2),
+                _ if player_q.contains(*entity2) && ball_q.contains(*entity1) => {
+                    (*entity2, *entity1)
                 }
-            }
+                _ => continue,
+            };
+        let Ok((_, player_transform, mut impulse, power_up)) = player_q.get_mut(player_entity)
+        else {
+            continue;
+        };
+        let Ok((ball_transform, ball_velocity, ball_mass)) = ball_q.get(ball_entity) else {
+            continue;
+        };
+        if ball_velocity.linvel.length() < MIN_HIT_SPEED {
+            continue;
+        }
+        // The shield itself goes once the frame's hits are all in
+        if has_power_up(power_up, PowerUpKind::Shield) {
+            blocked_events.send(ShieldBlocked {
+                player: player_entity,
+            });
+            continue;
+        }
+
+        // Spheres hit along the line between centres, tipped up a little so the player leaves the ground
+        let normal = (player_transform.translation - ball_transform.translation)
+            .with_y(0.0)
+            .normalize_or(ball_velocity.linvel.with_y(0.0).normalize_or(Vec3::X));
+        let direction = (normal + Vec3::Y * 0.35).normalize();
+        let momentum = ball_mass.get().mass * ball_velocity.linvel.length();
+        let strength = (momentum * KNOCKBACK_PER_MOMENTUM).min(MAX_KNOCKBACK_IMPULSE);
+        impulse.impulse += direction * strength;
+
+        let knocked_over = settings.ragdoll && momentum > RAGDOLL_MOMENTUM;
+        let stun_secs = if knocked_over {
+            // Tip over away from the ball
+            impulse.torque_impulse += Vec3::Y.cross(direction) * strength * 0.05;
+            commands.entity(player_entity).insert(LockedAxes::empty());
+            RAGDOLL_SECS
+        } else {
+            HIT_STUN_SECS
+        };
+        commands.entity(player_entity).insert(HitStun {
+            timer: Timer::from_seconds(stun_secs, TimerMode::Once),
+            knocked_over,
+        });
+    }
+}
+
+// Once the stun wears off, stand the player back up and hand control back
+fn recover_from_hit(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut player_q: Query<(Entity, &mut HitStun, &mut Transform, &mut Velocity), With<Player>>,
+) {
+    for (entity, mut hit_stun, mut transform, mut velocity) in player_q.iter_mut() {
+        hit_stun.timer.tick(time.delta());
+        if !hit_stun.timer.finished() {
+            continue;
         }
+        if hit_stun.knocked_over {
+            let forward = transform.forward().with_y(0.0).normalize_or(Vec3::NEG_Z);
+            transform.look_to(forward, Vec3::Y);
+            velocity.angvel = Vec3::ZERO;
+            commands.entity(entity).insert(LockedAxes::ROTATION_LOCKED);
+        }
+        commands.entity(entity).remove::<HitStun>();
+    }
+}
+
+// A burst of speed the way the player is facing, which is the way they're moving if they are
+fn dash(
+    time: Res<Time>,
+    mut player_q: Query<
+        (&Transform, &PlayerActions, &mut DashState, &mut Velocity),
+        (With<Player>, Without<HitStun>),
+    >,
+) {
+    for (transform, actions, mut dash_state, mut velocity) in player_q.iter_mut() {
+        dash_state.cooldown_secs = (dash_state.cooldown_secs - time.delta_seconds()).max(0.0);
+        let pressed = actions.dash && !dash_state.was_pressed;
+        dash_state.was_pressed = actions.dash;
+        if !pressed || !dash_state.is_ready() {
+            continue;
+        }
+        let direction = transform.forward().with_y(0.0).normalize_or(Vec3::NEG_Z);
+        velocity.linvel += direction * DASH_SPEED;
+        dash_state.cooldown_secs = DASH_COOLDOWN_SECS;
+    }
+}
+
+// Pressing catch opens a short window where any ball close enough is grabbed, in the air or on the floor
+fn catch_ball(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut caught_events: EventWriter<BallCaught>,
+    mut player_q: Query<
+        (
+            Entity,
+            &Transform,
+            &PlayerActions,
+            &mut CatchState,
+            Option<&ActivePowerUp>,
+        ),
+        (With<Player>, Without<HeldBall>, Without<HitStun>),
+    >,
+    ball_q: Query<
+        (Entity, &Transform, &BallKind, Option<&ThrownBy>),
+        (With<Ball>, Without<RigidBodyDisabled>),
+    >,
+) {
+    let mut claimed = Vec::new();
+    for (player, player_transform, actions, mut catch_state, power_up) in player_q.iter_mut() {
+        catch_state.window_secs = (catch_state.window_secs - time.delta_seconds()).max(0.0);
+        catch_state.cooldown_secs = (catch_state.cooldown_secs - time.delta_seconds()).max(0.0);
+        if actions.catch && !catch_state.was_pressed && catch_state.cooldown_secs == 0.0 {
+            catch_state.window_secs = CATCH_WINDOW_SECS;
+            catch_state.cooldown_secs = CATCH_COOLDOWN_SECS;
+        }
+        catch_state.was_pressed = actions.catch;
+        // A magnet catches whatever comes close, as long as we didn't just throw it
+        let magnet = has_power_up(power_up, PowerUpKind::Magnet);
+        if !catch_state.is_catching() && !magnet {
+            continue;
+        }
+        let radius = if magnet {
+            MAGNET_CATCH_RADIUS
+        } else {
+            CATCH_RADIUS
+        };
+
+        let nearest = ball_q
+            .iter()
+            .filter(|(ball, ..)| !claimed.contains(ball))
+            .filter(|(.., thrown_by)| {
+                !magnet || thrown_by.map_or(true, |thrown_by| thrown_by.player != player)
+            })
+            .map(|(ball, transform, kind, thrown_by)| {
+                let distance = transform.translation.distance(player_transform.translation);
+                (ball, *kind, thrown_by.copied(), distance)
+            })
+            .filter(|(.., distance)| *distance < radius)
+            .min_by(|a, b| a.3.total_cmp(&b.3));
+        let Some((ball, kind, thrown_by, _)) = nearest else {
+            continue;
+        };
+
+        claimed.push(ball);
+        catch_state.window_secs = 0.0;
+        park_ball(&mut commands, ball);
+        commands.entity(ball).insert(Visibility::Inherited);
+        commands.entity(player).insert(HeldBall::new(ball, kind));
+        caught_events.send(BallCaught {
+            catcher: player,
+            thrower: thrown_by.map(|thrown_by| thrown_by.player),
+        });
+    }
+}
+
+// Throws the held ball where the player's camera is looking
+fn throw_ball(
+    mut commands: Commands,
+    ball_assets: Res<BallAssets>,
+    mut player_q: Query<
+        (
+            Entity,
+            &Transform,
+            &PlayerActions,
+            &mut HeldBall,
+            Option<&AimAssist>,
+        ),
+        (With<Player>, Without<HitStun>),
+    >,
+    cam_q: Query<(&Transform, &PlayerCamera), Without<Player>>,
+) {
+    for (player, player_transform, actions, mut held_ball, aim_assist) in player_q.iter_mut() {
+        if !actions.throw {
+            held_ball.armed = true;
+            continue;
+        }
+        if !held_ball.armed {
+            continue;
+        }
+        let Some((cam, _)) = cam_q.iter().find(|(_, camera)| camera.player == player) else {
+            continue;
+        };
+
+        let mut aim = (*cam.forward() + Vec3::Y * THROW_LIFT).normalize();
+        if let Some(assist) = aim_assist {
+            let to_point = (assist.point - player_transform.translation).normalize_or(aim);
+            let assisted = (to_point + Vec3::Y * THROW_LIFT).normalize();
+            aim = aim
+                .lerp(assisted, assist.strength.clamp(0.0, 1.0))
+                .normalize_or(aim);
+        }
+        let release_point = player_transform.translation
+            + aim.with_y(0.0).normalize_or(*player_transform.forward())
+            + Vec3::Y * 0.5;
+        activate_ball(
+            &mut commands,
+            &ball_assets,
+            held_ball.ball,
+            held_ball.kind,
+            Transform::from_translation(release_point),
+            Velocity {
+                linvel: aim * THROW_SPEED * held_ball.kind.speed_scale(),
+                angvel: held_ball.kind.launch_angvel(),
+            },
+            BallLifetime::default(),
+        );
+        commands.entity(held_ball.ball).insert(ThrownBy { player });
+        commands.entity(player).remove::<HeldBall>();
+    }
+}
+
+// Held balls are out of the physics, so they're just kept in front of whoever is holding them
+fn carry_held_balls(
+    player_q: Query<(&Transform, &HeldBall), With<Player>>,
+    mut ball_q: Query<&mut Transform, (With<Ball>, Without<Player>)>,
+) {
+    for (player_transform, held_ball) in player_q.iter() {
+        let Ok(mut ball_transform) = ball_q.get_mut(held_ball.ball) else {
+            continue;
+        };
+        ball_transform.translation =
+            player_transform.translation + *player_transform.forward() * 0.6 + Vec3::Y * 0.3;
     }
 }
@@ -0,0 +1,418 @@
+//! Animates player models from the clips in `Player.gltf`.
+//!
+//! Each player's rig is driven by a small state machine. Idle, run, jump and fall follow the
+//! player's movement and `Grounded`, and land, dash, catch and throw play once over the top of
+//! them when they happen. Changes blend over `BLEND_SECS`. Clips are found by name, so a clip the
+//! model doesn't have falls back to a close one, and a model without clips just doesn't animate.
+//!
+//! The top-most bones the clips move have their sideways movement taken out of every clip, so the
+//! physics body alone decides where a player is. That's done to the animation graph's own copies of
+//! the clips, so anything else playing the model's clips still gets them untouched.
+//!
+//! `Player.gltf` is a simple stand-in. Its clips all move one `Body` bone that carries the whole
+//! mesh, bobbing, leaning and squashing it. The arm and leg bones under it aren't animated, they're
+//! there for the ragdoll.
+
+use bevy::animation::{animate_targets, AnimationTarget, AnimationTargetId};
+use bevy::gltf::Gltf;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use bevy_rapier3d::prelude::*;
+use std::time::Duration;
+
+use crate::ball::ThrownBy;
+use crate::player::{BallCaught, DashState, Grounded, Player};
+
+const PLAYER_MODEL: &str = "Player.gltf";
+const BLEND_SECS: f32 = 0.15;
+// Slower than this on the ground counts as standing still
+const MIN_RUN_SPEED: f32 = 0.5;
+// The run clip plays at normal speed at this many metres per second
+const RUN_CLIP_SPEED: f32 = 8.0;
+
+pub struct PlayerAnimationPlugin;
+
+impl Plugin for PlayerAnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, load_player_animations)
+            .add_systems(
+                Update,
+                (
+                    build_animation_graph,
+                    claim_rigs,
+                    strip_root_motion,
+                    animate_players,
+                )
+                    .chain()
+                    .before(animate_targets),
+            );
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PlayerAnimation {
+    Idle,
+    Run,
+    Jump,
+    Fall,
+    Land,
+    Dash,
+    Catch,
+    Throw,
+}
+
+impl PlayerAnimation {
+    const ALL: [PlayerAnimation; 8] = [
+        PlayerAnimation::Idle,
+        PlayerAnimation::Run,
+        PlayerAnimation::Jump,
+        PlayerAnimation::Fall,
+        PlayerAnimation::Land,
+        PlayerAnimation::Dash,
+        PlayerAnimation::Catch,
+        PlayerAnimation::Throw,
+    ];
+
+    /// The clip's name in the model, matched ignoring case
+    fn clip_name(self) -> &'static str {
+        match self {
+            PlayerAnimation::Idle => "idle",
+            PlayerAnimation::Run => "run",
+            PlayerAnimation::Jump => "jump",
+            PlayerAnimation::Fall => "fall",
+            PlayerAnimation::Land => "land",
+            PlayerAnimation::Dash => "dash",
+            PlayerAnimation::Catch => "catch",
+            PlayerAnimation::Throw => "throw",
+        }
+    }
+
+    /// What to play instead when the model has no clip for this
+    fn fallback(self) -> Option<PlayerAnimation> {
+        match self {
+            PlayerAnimation::Idle => None,
+            PlayerAnimation::Run | PlayerAnimation::Jump | PlayerAnimation::Land => {
+                Some(PlayerAnimation::Idle)
+            }
+            PlayerAnimation::Fall => Some(PlayerAnimation::Jump),
+            PlayerAnimation::Dash => Some(PlayerAnimation::Run),
+            PlayerAnimation::Catch | PlayerAnimation::Throw => None,
+        }
+    }
+
+    /// Plays once over movement rather than looping
+    fn is_one_shot(self) -> bool {
+        matches!(
+            self,
+            PlayerAnimation::Land
+                | PlayerAnimation::Dash
+                | PlayerAnimation::Catch
+                | PlayerAnimation::Throw
+        )
+    }
+
+    /// One-shots cut off any one-shot with a lower priority
+    fn priority(self) -> u8 {
+        match self {
+            PlayerAnimation::Throw => 4,
+            PlayerAnimation::Catch => 3,
+            PlayerAnimation::Dash => 2,
+            PlayerAnimation::Land => 1,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Resource)]
+struct PlayerAnimations {
+    gltf: Handle<Gltf>,
+    graph: Option<Handle<AnimationGraph>>,
+    nodes: HashMap<PlayerAnimation, AnimationNodeIndex>,
+    clips: Vec<Handle<AnimationClip>>,
+}
+
+impl PlayerAnimations {
+    // `animation`, or the nearest fallback the model has a clip for, and its node
+    fn resolve(
+        &self,
+        mut animation: PlayerAnimation,
+    ) -> Option<(PlayerAnimation, AnimationNodeIndex)> {
+        loop {
+            if let Some(&node) = self.nodes.get(&animation) {
+                return Some((animation, node));
+            }
+            animation = animation.fallback()?;
+        }
+    }
+}
+
+/// Sits on the entity with a player model's `AnimationPlayer`.
+#[derive(Component)]
+pub struct PlayerRig {
+    pub player: Entity,
+    pub state: PlayerAnimation,
+    was_grounded: bool,
+    secs_since_dash: f32,
+}
+
+fn load_player_animations(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(PlayerAnimations {
+        gltf: asset_server.load(PLAYER_MODEL),
+        graph: None,
+        nodes: HashMap::new(),
+        clips: Vec::new(),
+    });
+}
+
+fn build_animation_graph(
+    mut animations: ResMut<PlayerAnimations>,
+    gltfs: Res<Assets<Gltf>>,
+    mut clip_assets: ResMut<Assets<AnimationClip>>,
+    mut graphs: ResMut<Assets<AnimationGraph>>,
+) {
+    if animations.graph.is_some() {
+        return;
+    }
+    let Some(gltf) = gltfs.get(&animations.gltf) else {
+        return;
+    };
+    let found: Vec<(PlayerAnimation, Handle<AnimationClip>)> = PlayerAnimation::ALL
+        .into_iter()
+        .filter_map(|animation| {
+            gltf.named_animations
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(animation.clip_name()))
+                .map(|(_, clip)| (animation, clip.clone()))
+        })
+        .collect();
+    if found.iter().any(|(_, clip)| !clip_assets.contains(clip)) {
+        return;
+    }
+    let mut graph = AnimationGraph::new();
+    let mut nodes = HashMap::new();
+    let mut clips = Vec::new();
+    for (animation, clip) in found {
+        let copy = clip_assets.get(&clip).unwrap().clone();
+        let copy = clip_assets.add(copy);
+        nodes.insert(animation, graph.add_clip(copy.clone(), 1.0, graph.root));
+        clips.push(copy);
+    }
+    if nodes.is_empty() {
+        println!(
+            "{} has no animations, so players won't animate",
+            PLAYER_MODEL
+        );
+    } else {
+        let missing: Vec<_> = PlayerAnimation::ALL
+            .into_iter()
+            .filter(|animation| !nodes.contains_key(animation))
+            .collect();
+        if !missing.is_empty() {
+            println!("{} is missing {:?}, using fallbacks", PLAYER_MODEL, missing);
+        }
+    }
+    animations.graph = Some(graphs.add(graph));
+    animations.nodes = nodes;
+    animations.clips = clips;
+}
+
+/// Marks the animation players in player models, leaving ones in other models (like a fox bot's)
+/// alone.
+pub(crate) fn claim_rigs(
+    mut commands: Commands,
+    rig_q: Query<Entity, Added<AnimationPlayer>>,
+    parent_q: Query<&Parent>,
+    scene_q: Query<Has<Player>, With<Handle<Scene>>>,
+) {
+    for rig in rig_q.iter() {
+        // The rig belongs to whichever scene it's nearest inside
+        let Some(scene_root) = parent_q
+            .iter_ancestors(rig)
+            .find(|&ancestor| scene_q.contains(ancestor))
+        else {
+            continue;
+        };
+        if !scene_q.get(scene_root).unwrap() {
+            continue;
+        }
+        commands.entity(rig).insert((
+            PlayerRig {
+                player: scene_root,
+                state: PlayerAnimation::Idle,
+                was_grounded: true,
+                secs_since_dash: f32::MAX,
+            },
+            AnimationTransitions::new(),
+        ));
+    }
+}
+
+// Pins the sideways positions of the top-most bones the clips move to where they start in every
+// clip, once a rig shows which bones those are
+fn strip_root_motion(
+    mut stripped: Local<bool>,
+    animations: Res<PlayerAnimations>,
+    mut clips: ResMut<Assets<AnimationClip>>,
+    rig_q: Query<Entity, With<PlayerRig>>,
+    target_q: Query<(Entity, &AnimationTarget)>,
+    parent_q: Query<&Parent>,
+) {
+    if *stripped || animations.clips.is_empty() {
+        return;
+    }
+    let Some(rig) = rig_q.iter().next() else {
+        return;
+    };
+    let animated: HashSet<AnimationTargetId> = target_q
+        .iter()
+        .filter(|(_, target)| target.player == rig)
+        .map(|(_, target)| target.id)
+        .filter(|&id| {
+            animations.clips.iter().any(|handle| {
+                clips
+                    .get(handle)
+                    .is_some_and(|clip| clip.curves_for_target(id).is_some())
+            })
+        })
+        .collect();
+    // Bones the clips move with nothing above them the clips move too
+    let roots: HashSet<AnimationTargetId> = target_q
+        .iter()
+        .filter(|(entity, target)| {
+            animated.contains(&target.id)
+                && !parent_q.iter_ancestors(*entity).any(|ancestor| {
+                    target_q.get(ancestor).is_ok_and(|(_, ancestor)| {
+                        ancestor.player == rig && animated.contains(&ancestor.id)
+                    })
+                })
+        })
+        .map(|(_, target)| target.id)
+        .collect();
+    for handle in animations.clips.iter() {
+        let Some(clip) = clips.get_mut(handle) else {
+            continue;
+        };
+        for &root in roots.iter() {
+            for curve in clip.curves_for_target_mut(root).into_iter().flatten() {
+                let cubic = matches!(curve.interpolation, Interpolation::CubicSpline);
+                let Keyframes::Translation(translations) = &mut curve.keyframes else {
+                    continue;
+                };
+                // Cubic splines keep an in tangent, the value and an out tangent for each keyframe
+                let (step, first) = if cubic { (3, 1) } else { (1, 0) };
+                let Some(&start) = translations.get(first) else {
+                    continue;
+                };
+                for (i, translation) in translations.iter_mut().enumerate() {
+                    let (x, z) = if i % step == first {
+                        (start.x, start.z)
+                    } else {
+                        (0.0, 0.0)
+                    };
+                    translation.x = x;
+                    translation.z = z;
+                }
+            }
+        }
+    }
+    *stripped = true;
+}
+
+fn animate_players(
+    mut commands: Commands,
+    animations: Res<PlayerAnimations>,
+    mut caught_events: EventReader<BallCaught>,
+    thrown_q: Query<&ThrownBy, Added<ThrownBy>>,
+    player_q: Query<(&Grounded, &Velocity, &DashState), With<Player>>,
+    mut rig_q: Query<(
+        Entity,
+        &mut PlayerRig,
+        &mut AnimationPlayer,
+        &mut AnimationTransitions,
+        Has<Handle<AnimationGraph>>,
+    )>,
+) {
+    let Some(graph) = animations.graph.as_ref() else {
+        return;
+    };
+    let caught: HashSet<Entity> = caught_events.read().map(|event| event.catcher).collect();
+    let threw: HashSet<Entity> = thrown_q.iter().map(|thrown_by| thrown_by.player).collect();
+
+    for (entity, mut rig, mut animation_player, mut transitions, has_graph) in rig_q.iter_mut() {
+        if !has_graph {
+            commands.entity(entity).insert(graph.clone());
+        }
+        let Ok((grounded, velocity, dash_state)) = player_q.get(rig.player) else {
+            continue;
+        };
+        let is_grounded = grounded.count > 0;
+        let landed = is_grounded && !rig.was_grounded;
+        let dashed = dash_state.secs_since_dash() < rig.secs_since_dash;
+        rig.was_grounded = is_grounded;
+        rig.secs_since_dash = dash_state.secs_since_dash();
+
+        let horizontal_speed = velocity.linvel.xz().length();
+        let movement = if !is_grounded {
+            if velocity.linvel.y > 0.0 {
+                PlayerAnimation::Jump
+            } else {
+                PlayerAnimation::Fall
+            }
+        } else if horizontal_speed > MIN_RUN_SPEED {
+            PlayerAnimation::Run
+        } else {
+            PlayerAnimation::Idle
+        };
+        // Falling back to a looping clip counts as not having a one-shot at all
+        let triggered = [
+            (threw.contains(&rig.player), PlayerAnimation::Throw),
+            (caught.contains(&rig.player), PlayerAnimation::Catch),
+            (dashed, PlayerAnimation::Dash),
+            (landed, PlayerAnimation::Land),
+        ]
+        .into_iter()
+        .filter(|(happened, _)| *happened)
+        .find_map(|(_, animation)| animations.resolve(animation))
+        .filter(|(animation, _)| animation.is_one_shot());
+
+        // A one-shot keeps playing until it finishes or something more important cuts it off
+        let current_node = transitions.get_main_animation();
+        let one_shot_playing = rig.state.is_one_shot()
+            && current_node
+                .and_then(|node| animation_player.animation(node))
+                .is_some_and(|active| !active.is_finished());
+        let (next, node, restart) = match (triggered, current_node) {
+            (Some((animation, node)), _)
+                if !one_shot_playing || animation.priority() >= rig.state.priority() =>
+            {
+                (animation, node, true)
+            }
+            (_, Some(node)) if one_shot_playing => (rig.state, node, false),
+            _ => {
+                let Some((animation, node)) = animations.resolve(movement) else {
+                    continue;
+                };
+                (animation, node, false)
+            }
+        };
+
+        if restart || Some(node) != current_node {
+            let active = transitions.play(
+                &mut animation_player,
+                node,
+                Duration::from_secs_f32(BLEND_SECS),
+            );
+            if next.is_one_shot() {
+                active.replay();
+            } else {
+                active.repeat();
+            }
+        }
+        rig.state = next;
+        if next == PlayerAnimation::Run {
+            if let Some(active) = animation_player.animation_mut(node) {
+                active.set_speed((horizontal_speed / RUN_CLIP_SPEED).clamp(0.5, 1.5));
+            }
+        }
+    }
+}
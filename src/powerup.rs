@@ -0,0 +1,535 @@
+//! Power-ups that turn up around the arena.
+//!
+//! Every so often a pickup appears at a random spot on the ground. Running into it gives the
+//! player its power-up for a while, with a glow round them to show it and a line in the corner of
+//! their screen saying how long is left. A player has one power-up at a time, and picking up
+//! another swaps it.
+//!
+//! - Shield soaks up the next ball that hits, neither knocking the player back nor putting them out.
+//! - Slow-time slows down every ball the player didn't throw.
+//! - Magnet catches any ball that comes close, without pressing catch.
+//! - Speed boost makes the player run faster.
+//! - Giant ball makes the balls the player throws much bigger.
+//!
+//! Power-ups run with the simulation but aren't part of a rollback session's snapshots, so they're
+//! left out of rollback games. They aren't in the server's snapshots either, so network clients
+//! play without them: the plugin is off on a client, and a client's player only gets a power-up's
+//! effect through the server correcting where it is.
+
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use bevy_rapier3d::prelude::*;
+use rand::prelude::*;
+
+use crate::ball::{Ball, ThrownBy};
+use crate::camera::PlayerCamera;
+use crate::net::NetMode;
+use crate::player::Player;
+use crate::world::{Cubeovator, Ground, SimulationSet};
+
+const SPAWN_INTERVAL_SECS: f32 = 8.0;
+const MAX_PICKUPS: usize = 3;
+// Pickups spawn on ground within this far of the middle of the arena
+const SPAWN_HALF_EXTENT: f32 = 4.5;
+const SPAWN_ATTEMPTS: usize = 10;
+const SPAWN_RAY_HEIGHT: f32 = 20.0;
+const PICKUP_HEIGHT: f32 = 0.6;
+const PICKUP_RADIUS: f32 = 0.4;
+const PICKUP_SPIN_SPEED: f32 = 2.0;
+const AURA_RADIUS: f32 = 1.1;
+
+/// How much faster a player with a speed boost runs.
+pub(crate) const SPEED_BOOST_SCALE: f32 = 1.6;
+/// How close a ball has to come to a player with a magnet to be caught.
+pub(crate) const MAGNET_CATCH_RADIUS: f32 = 2.5;
+// Balls a player with slow-time didn't throw go this fast
+const SLOW_TIME_SCALE: f32 = 0.4;
+const GIANT_BALL_SCALE: f32 = 2.5;
+
+pub struct PowerUpPlugin;
+
+impl Plugin for PowerUpPlugin {
+    fn build(&self, app: &mut App) {
+        // Hits are checked for shields whether power-ups are on or not
+        app.add_event::<ShieldBlocked>();
+        if matches!(
+            app.world().get_resource::<NetMode>(),
+            Some(NetMode::Client(_))
+        ) {
+            return;
+        }
+        app.insert_resource(PickupSpawner {
+            timer: Timer::from_seconds(SPAWN_INTERVAL_SECS, TimerMode::Repeating),
+        })
+        .add_systems(Startup, setup_power_up_assets)
+        .add_systems(
+            Update,
+            (
+                spawn_pickups,
+                collect_pickups,
+                wear_off_power_ups,
+                slow_balls,
+            )
+                .chain()
+                .in_set(SimulationSet),
+        )
+        .add_systems(
+            Update,
+            (
+                grow_giant_balls,
+                show_auras,
+                spin_pickups,
+                add_power_up_huds,
+                update_power_up_huds,
+            )
+                .after(SimulationSet),
+        )
+        // Once everything that checks for a shield has had this frame's hits
+        .add_systems(PostUpdate, break_shields);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PowerUpKind {
+    Shield,
+    SlowTime,
+    Magnet,
+    SpeedBoost,
+    GiantBall,
+}
+
+impl PowerUpKind {
+    pub const ALL: [PowerUpKind; 5] = [
+        PowerUpKind::Shield,
+        PowerUpKind::SlowTime,
+        PowerUpKind::Magnet,
+        PowerUpKind::SpeedBoost,
+        PowerUpKind::GiantBall,
+    ];
+
+    /// How long it lasts. A shield goes early if it soaks up a hit.
+    pub fn duration_secs(self) -> f32 {
+        match self {
+            PowerUpKind::Shield => 15.0,
+            PowerUpKind::SlowTime => 6.0,
+            PowerUpKind::Magnet => 8.0,
+            PowerUpKind::SpeedBoost => 6.0,
+            PowerUpKind::GiantBall => 10.0,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            PowerUpKind::Shield => "Shield",
+            PowerUpKind::SlowTime => "Slow-time",
+            PowerUpKind::Magnet => "Magnet",
+            PowerUpKind::SpeedBoost => "Speed boost",
+            PowerUpKind::GiantBall => "Giant ball",
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            PowerUpKind::Shield => "#4cc9f0",
+            PowerUpKind::SlowTime => "#b5179e",
+            PowerUpKind::Magnet => "#f72585",
+            PowerUpKind::SpeedBoost => "#ffd60a",
+            PowerUpKind::GiantBall => "#80ed99",
+        }
+    }
+}
+
+/// A power-up waiting on the ground to be run into.
+#[derive(Component)]
+pub struct Pickup {
+    pub kind: PowerUpKind,
+}
+
+/// The power-up a player has, until its timer runs out.
+#[derive(Component, Clone)]
+pub struct ActivePowerUp {
+    pub kind: PowerUpKind,
+    pub timer: Timer,
+}
+
+impl ActivePowerUp {
+    pub fn new(kind: PowerUpKind) -> Self {
+        Self {
+            kind,
+            timer: Timer::from_seconds(kind.duration_secs(), TimerMode::Once),
+        }
+    }
+}
+
+/// Sent when a player's shield stops a ball that would have knocked them back or put them out.
+#[derive(Event, Debug)]
+pub struct ShieldBlocked {
+    pub player: Entity,
+}
+
+/// Whether a player has a particular power-up right now.
+pub fn has_power_up(power_up: Option<&ActivePowerUp>, kind: PowerUpKind) -> bool {
+    power_up.is_some_and(|power_up| power_up.kind == kind)
+}
+
+#[derive(Resource)]
+struct PickupSpawner {
+    timer: Timer,
+}
+
+/// Meshes and materials for pickups and the glow round players, per kind.
+#[derive(Resource)]
+struct PowerUpAssets {
+    pickup_mesh: Handle<Mesh>,
+    aura_mesh: Handle<Mesh>,
+    pickup_materials: HashMap<PowerUpKind, Handle<StandardMaterial>>,
+    aura_materials: HashMap<PowerUpKind, Handle<StandardMaterial>>,
+}
+
+/// The glow round a player with a power-up.
+#[derive(Component)]
+struct PowerUpAura {
+    kind: PowerUpKind,
+}
+
+/// A ball slowed down by someone's slow-time.
+#[derive(Component)]
+struct SlowedBall;
+
+/// The line of text saying which power-up a player has.
+#[derive(Component)]
+struct PowerUpHud {
+    player: Entity,
+}
+
+fn setup_power_up_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let pickup_materials = PowerUpKind::ALL
+        .into_iter()
+        .map(|kind| {
+            let color: Color = Srgba::hex(kind.color()).unwrap().into();
+            let material = materials.add(StandardMaterial {
+                base_color: color,
+                emissive: color.to_linear() * 4.0,
+                ..default()
+            });
+            (kind, material)
+        })
+        .collect();
+    let aura_materials = PowerUpKind::ALL
+        .into_iter()
+        .map(|kind| {
+            let color = Srgba::hex(kind.color()).unwrap().with_alpha(0.25);
+            let material = materials.add(StandardMaterial {
+                base_color: color.into(),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            });
+            (kind, material)
+        })
+        .collect();
+    commands.insert_resource(PowerUpAssets {
+        pickup_mesh: meshes.add(Cuboid::from_length(PICKUP_RADIUS * 1.2)),
+        aura_mesh: meshes.add(Sphere::new(AURA_RADIUS).mesh().uv(32, 18)),
+        pickup_materials,
+        aura_materials,
+    });
+}
+
+// Somewhere on the ground that isn't going anywhere, so pickups don't get left hanging in the air
+fn random_ground_point(
+    rng: &mut impl Rng,
+    rapier_context: &RapierContext,
+    ground_q: &Query<(), With<Ground>>,
+    moving_q: &Query<(), With<Cubeovator>>,
+    parent_q: &Query<&Parent>,
+) -> Option<Vec3> {
+    let filter = QueryFilter::new().exclude_dynamic().exclude_sensors();
+    for _ in 0..SPAWN_ATTEMPTS {
+        let origin = Vec3::new(
+            rng.gen_range(-SPAWN_HALF_EXTENT..SPAWN_HALF_EXTENT),
+            SPAWN_RAY_HEIGHT,
+            rng.gen_range(-SPAWN_HALF_EXTENT..SPAWN_HALF_EXTENT),
+        );
+        let Some((entity, toi)) =
+            rapier_context.cast_ray(origin, Vec3::NEG_Y, SPAWN_RAY_HEIGHT * 2.0, true, filter)
+        else {
+            continue;
+        };
+        let on_platform = moving_q.contains(entity)
+            || parent_q
+                .get(entity)
+                .is_ok_and(|parent| moving_q.contains(parent.get()));
+        if ground_q.contains(entity) && !on_platform {
+            return Some(origin + Vec3::NEG_Y * toi + Vec3::Y * PICKUP_HEIGHT);
+        }
+    }
+    None
+}
+
+fn spawn_pickups(
+    mut commands: Commands,
+    time: Res<Time>,
+    assets: Res<PowerUpAssets>,
+    rapier_context: Res<RapierContext>,
+    mut spawner: ResMut<PickupSpawner>,
+    pickup_q: Query<(), With<Pickup>>,
+    ground_q: Query<(), With<Ground>>,
+    moving_q: Query<(), With<Cubeovator>>,
+    parent_q: Query<&Parent>,
+) {
+    spawner.timer.tick(time.delta());
+    if !spawner.timer.just_finished() || pickup_q.iter().count() >= MAX_PICKUPS {
+        return;
+    }
+    let mut rng = thread_rng();
+    let Some(position) =
+        random_ground_point(&mut rng, &rapier_context, &ground_q, &moving_q, &parent_q)
+    else {
+        return;
+    };
+    let kind = *PowerUpKind::ALL.choose(&mut rng).unwrap();
+    commands.spawn((
+        PbrBundle {
+            mesh: assets.pickup_mesh.clone(),
+            material: assets.pickup_materials[&kind].clone(),
+            transform: Transform::from_translation(position),
+            ..default()
+        },
+        Pickup { kind },
+        Collider::ball(PICKUP_RADIUS),
+        Sensor,
+        Name::new(format!("{} pickup", kind.label())),
+    ));
+}
+
+fn collect_pickups(
+    mut commands: Commands,
+    mut collision_events: EventReader<CollisionEvent>,
+    pickup_q: Query<&Pickup>,
+    player_q: Query<(), With<Player>>,
+) {
+    // Two players can touch the same pickup in one frame, only the first gets it
+    let mut taken = HashSet::new();
+    for collision_event in collision_events.read() {
+        let CollisionEvent::Started(entity1, entity2, _flags) = collision_event else {
+            continue;
+        };
+        let (pickup_entity, player) =
+            match (pickup_q.contains(*entity1), player_q.contains(*entity2)) {
+                (true, true) => (*entity1, *entity2),
+                _ if pickup_q.contains(*entity2) && player_q.contains(*entity1) => {
+                    (*entity2, *entity1)
+                }
+                _ => continue,
+            };
+        let Ok(pickup) = pickup_q.get(pickup_entity) else {
+            continue;
+        };
+        if !taken.insert(pickup_entity) {
+            continue;
+        }
+        commands.entity(pickup_entity).despawn_recursive();
+        commands
+            .entity(player)
+            .insert(ActivePowerUp::new(pickup.kind));
+        println!("Picked up {}", pickup.kind.label());
+    }
+}
+
+// A shield goes on the first ball it stops. Knockback and elimination both let a shielded player
+// off and say so, and this takes the shield away after them.
+fn break_shields(
+    mut commands: Commands,
+    mut blocked_events: EventReader<ShieldBlocked>,
+    player_q: Query<Option<&ActivePowerUp>, With<Player>>,
+) {
+    // Knockback and elimination can both block the same ball
+    let blocked: HashSet<Entity> = blocked_events.read().map(|event| event.player).collect();
+    for player in blocked {
+        if has_power_up(player_q.get(player).ok().flatten(), PowerUpKind::Shield) {
+            commands.entity(player).remove::<ActivePowerUp>();
+            println!("Shield soaked up a hit");
+        }
+    }
+}
+
+fn wear_off_power_ups(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut player_q: Query<(Entity, &mut ActivePowerUp)>,
+) {
+    for (player, mut power_up) in player_q.iter_mut() {
+        power_up.timer.tick(time.delta());
+        if power_up.timer.finished() {
+            commands.entity(player).remove::<ActivePowerUp>();
+        }
+    }
+}
+
+// Balls are slowed once when slow-time catches them and sped back up once it's over. Gravity is
+// turned down to match, so they still fly the same arc, just slower.
+fn slow_balls(
+    mut commands: Commands,
+    power_up_q: Query<(Entity, &ActivePowerUp)>,
+    mut ball_q: Query<
+        (
+            Entity,
+            &mut Velocity,
+            Option<&ThrownBy>,
+            Has<SlowedBall>,
+            Has<RigidBodyDisabled>,
+        ),
+        With<Ball>,
+    >,
+) {
+    let slowers: Vec<Entity> = power_up_q
+        .iter()
+        .filter(|(_, power_up)| power_up.kind == PowerUpKind::SlowTime)
+        .map(|(player, _)| player)
+        .collect();
+    for (ball, mut velocity, thrown_by, slowed, parked) in ball_q.iter_mut() {
+        if parked {
+            // Balls come out of the pool at full speed
+            if slowed {
+                commands.entity(ball).remove::<(SlowedBall, GravityScale)>();
+            }
+            continue;
+        }
+        let thrower = thrown_by.map(|thrown_by| thrown_by.player);
+        let slow = slowers.iter().any(|&player| Some(player) != thrower);
+        if slow && !slowed {
+            velocity.linvel *= SLOW_TIME_SCALE;
+            velocity.angvel *= SLOW_TIME_SCALE;
+            commands
+                .entity(ball)
+                .insert((SlowedBall, GravityScale(SLOW_TIME_SCALE * SLOW_TIME_SCALE)));
+        } else if !slow && slowed {
+            velocity.linvel /= SLOW_TIME_SCALE;
+            velocity.angvel /= SLOW_TIME_SCALE;
+            commands.entity(ball).remove::<(SlowedBall, GravityScale)>();
+        }
+    }
+}
+
+// Scaling the transform scales the collider too. Balls get a fresh transform when they're reused.
+fn grow_giant_balls(
+    mut ball_q: Query<(&ThrownBy, &mut Transform), (With<Ball>, Added<ThrownBy>)>,
+    power_up_q: Query<&ActivePowerUp>,
+) {
+    for (thrown_by, mut transform) in ball_q.iter_mut() {
+        if has_power_up(
+            power_up_q.get(thrown_by.player).ok(),
+            PowerUpKind::GiantBall,
+        ) {
+            transform.scale = Vec3::splat(GIANT_BALL_SCALE);
+        }
+    }
+}
+
+// Swapped whenever the player's power-up is, and gone once it is
+fn show_auras(
+    mut commands: Commands,
+    assets: Res<PowerUpAssets>,
+    player_q: Query<(Entity, &ActivePowerUp)>,
+    aura_q: Query<(Entity, &Parent, &PowerUpAura)>,
+) {
+    for (aura, parent, aura_kind) in aura_q.iter() {
+        let current = player_q
+            .get(parent.get())
+            .ok()
+            .map(|(_, power_up)| power_up.kind);
+        if current != Some(aura_kind.kind) {
+            commands.entity(aura).despawn_recursive();
+        }
+    }
+    for (player, power_up) in player_q.iter() {
+        let showing = aura_q
+            .iter()
+            .any(|(_, parent, aura)| parent.get() == player && aura.kind == power_up.kind);
+        if showing {
+            continue;
+        }
+        let aura = commands
+            .spawn((
+                PbrBundle {
+                    mesh: assets.aura_mesh.clone(),
+                    material: assets.aura_materials[&power_up.kind].clone(),
+                    ..default()
+                },
+                PowerUpAura {
+                    kind: power_up.kind,
+                },
+                Name::new("PowerUpAura"),
+            ))
+            .id();
+        commands.entity(player).add_child(aura);
+    }
+}
+
+fn spin_pickups(time: Res<Time>, mut pickup_q: Query<&mut Transform, With<Pickup>>) {
+    for mut transform in pickup_q.iter_mut() {
+        transform.rotate_y(PICKUP_SPIN_SPEED * time.delta_seconds());
+    }
+}
+
+// Each player camera gets its own line of text, in the bottom left of its part of the screen
+fn add_power_up_huds(
+    mut commands: Commands,
+    camera_q: Query<(Entity, &PlayerCamera), (Added<PlayerCamera>, With<Camera>)>,
+) {
+    for (camera, player_camera) in camera_q.iter() {
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        left: Val::Px(12.0),
+                        bottom: Val::Px(12.0),
+                        ..default()
+                    },
+                    ..default()
+                },
+                TargetCamera(camera),
+                Name::new("PowerUpHud"),
+            ))
+            .with_children(|parent| {
+                parent.spawn((
+                    TextBundle::from_section(
+                        "",
+                        TextStyle {
+                            font_size: 24.0,
+                            ..default()
+                        },
+                    ),
+                    PowerUpHud {
+                        player: player_camera.player,
+                    },
+                ));
+            });
+    }
+}
+
+fn update_power_up_huds(
+    mut hud_q: Query<(&PowerUpHud, &mut Text)>,
+    power_up_q: Query<&ActivePowerUp>,
+) {
+    for (hud, mut text) in hud_q.iter_mut() {
+        let section = &mut text.sections[0];
+        match power_up_q.get(hud.player) {
+            Ok(power_up) => {
+                section.value = format!(
+                    "{} {:.0}s",
+                    power_up.kind.label(),
+                    power_up.timer.remaining_secs().ceil()
+                );
+                section.style.color = Srgba::hex(power_up.kind.color()).unwrap().into();
+            }
+            Err(_) => section.value.clear(),
+        }
+    }
+}
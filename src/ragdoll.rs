@@ -0,0 +1,186 @@
+//! Players knocked over by a big hit go limp.
+//!
+//! The body still tumbles as one rapier body, and each arm and leg of `Player.gltf`'s skeleton
+//! becomes a body of its own, hung off it by a ball joint at the shoulder or hip. The limb bones
+//! follow those bodies until the player gets back up, then the limbs are taken away and the bones
+//! go back to where the model has them. The head stays fixed to the body.
+//!
+//! Limbs are part of the player, so a ball that touches one has hit them. Models without the limb
+//! bones, like a fox bot's, just tumble. Rollback sessions don't snapshot limbs, so players there
+//! only tumble too.
+
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+use bevy_rapier3d::prelude::*;
+
+use crate::player::{HitStun, Player};
+use crate::rollback::RollbackSession;
+use crate::world::SimulationSet;
+
+// Each limb's bone, and where the limb ends in the bone's own space, which is the model's before
+// it's scaled down
+const LIMBS: [(&str, Vec3); 4] = [
+    ("Arm.L", Vec3::new(-0.1, -1.35, 0.7)),
+    ("Arm.R", Vec3::new(-0.3, -0.6, -1.0)),
+    ("Leg.L", Vec3::new(0.0, -0.73, 0.46)),
+    ("Leg.R", Vec3::new(0.0, -0.73, -0.44)),
+];
+// Also in the model's space
+const LIMB_RADIUS: f32 = 0.15;
+const LIMB_MASS: f32 = 2.0;
+// Limbs don't collide with each other, or with the bodies they hang off
+const LIMB_GROUP: Group = Group::GROUP_31;
+const LIMP_BODY_GROUP: Group = Group::GROUP_32;
+
+pub struct RagdollPlugin;
+
+impl Plugin for RagdollPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                go_limp.run_if(not(resource_exists::<RollbackSession>)),
+                get_back_up,
+            )
+                .after(SimulationSet),
+        )
+        .add_systems(
+            PostUpdate,
+            pose_limp_bones.after(TransformSystem::TransformPropagate),
+        );
+    }
+}
+
+/// The player's limbs have gone limp.
+#[derive(Component)]
+struct Ragdoll;
+
+/// One limp limb, jointed to its player's body. The limb's bone follows it.
+#[derive(Component)]
+pub struct RagdollLimb {
+    pub player: Entity,
+    bone: Entity,
+    /// Where the model has the bone, to put it back after
+    rest: Transform,
+    scale: Vec3,
+}
+
+fn go_limp(
+    mut commands: Commands,
+    player_q: Query<
+        (
+            Entity,
+            &HitStun,
+            &GlobalTransform,
+            &Velocity,
+            Option<&GravityScale>,
+        ),
+        (With<Player>, Without<Ragdoll>),
+    >,
+    children_q: Query<&Children>,
+    bone_q: Query<(&Name, &Transform, &GlobalTransform)>,
+) {
+    for (player, hit_stun, player_global, velocity, gravity) in player_q.iter() {
+        if !hit_stun.knocked_over() {
+            continue;
+        }
+        let limbs: Vec<_> = children_q
+            .iter_descendants(player)
+            .filter_map(|bone| {
+                let (name, rest, bone_global) = bone_q.get(bone).ok()?;
+                let (_, tip) = LIMBS.iter().find(|(limb, _)| name.as_str() == *limb)?;
+                Some((bone, *rest, *bone_global, *tip))
+            })
+            .collect();
+        // The model may not have loaded yet, or not have limbs at all
+        if limbs.is_empty() {
+            continue;
+        }
+
+        let to_body = player_global.compute_matrix().inverse();
+        for (bone, rest, bone_global, tip) in limbs {
+            let (scale, rotation, translation) = bone_global.to_scale_rotation_translation();
+            // At the shoulder or hip, which is where the bone is
+            let joint = SphericalJointBuilder::new()
+                .local_anchor1(to_body.transform_point3(translation))
+                .local_anchor2(Vec3::ZERO);
+            commands.spawn((
+                RagdollLimb {
+                    player,
+                    bone,
+                    rest,
+                    scale,
+                },
+                RigidBody::Dynamic,
+                Collider::capsule(Vec3::ZERO, tip * scale, LIMB_RADIUS * scale.x),
+                ColliderMassProperties::Mass(LIMB_MASS),
+                CollisionGroups::new(
+                    LIMB_GROUP,
+                    Group::ALL.difference(LIMB_GROUP | LIMP_BODY_GROUP),
+                ),
+                Damping {
+                    linear_damping: 0.5,
+                    angular_damping: 2.0,
+                },
+                gravity.cloned().unwrap_or_default(),
+                Velocity::linear(velocity.linvel),
+                ImpulseJoint::new(player, joint),
+                TransformBundle::from(
+                    Transform::from_translation(translation).with_rotation(rotation),
+                ),
+                Name::new("Ragdoll limb"),
+            ));
+        }
+        commands
+            .entity(player)
+            .insert((Ragdoll, CollisionGroups::new(LIMP_BODY_GROUP, Group::ALL)));
+    }
+}
+
+// Once the player is up, or out, the limbs go and the bones go back to where the model has them
+fn get_back_up(
+    mut commands: Commands,
+    limb_q: Query<(Entity, &RagdollLimb)>,
+    player_q: Query<Option<&HitStun>, With<Player>>,
+    mut bone_q: Query<&mut Transform, Without<RagdollLimb>>,
+) {
+    for (entity, limb) in limb_q.iter() {
+        let limp = match player_q.get(limb.player) {
+            Ok(hit_stun) => hit_stun.is_some_and(|hit_stun| hit_stun.knocked_over()),
+            Err(_) => false,
+        };
+        if limp {
+            continue;
+        }
+        commands.entity(entity).despawn();
+        if let Ok(mut bone) = bone_q.get_mut(limb.bone) {
+            *bone = limb.rest;
+        }
+        if player_q.contains(limb.player) {
+            commands
+                .entity(limb.player)
+                .remove::<(Ragdoll, CollisionGroups)>();
+        }
+    }
+}
+
+// After transforms are worked out, so the skin is drawn with the limbs where physics left them
+fn pose_limp_bones(
+    limb_q: Query<(&RagdollLimb, &Transform)>,
+    mut bone_q: Query<(&mut Transform, &mut GlobalTransform, &Parent), Without<RagdollLimb>>,
+) {
+    for (limb, limb_transform) in limb_q.iter() {
+        let Ok((_, _, parent)) = bone_q.get(limb.bone) else {
+            continue;
+        };
+        let Ok((_, &parent_global, _)) = bone_q.get(parent.get()) else {
+            continue;
+        };
+        let global = limb_transform.with_scale(limb.scale);
+        let (mut bone, mut bone_global, _) = bone_q.get_mut(limb.bone).unwrap();
+        *bone = Transform::from_matrix(
+            parent_global.compute_matrix().inverse() * global.compute_matrix(),
+        );
+        *bone_global = GlobalTransform::from(global);
+    }
+}
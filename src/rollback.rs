@@ -0,0 +1,907 @@
+//! Peer-to-peer rollback netcode for two players.
+//!
+//! Both peers run the whole simulation at a fixed tick and only send each other their inputs.
+//! When the other peer's input hasn't arrived yet we guess it (same as last tick) and carry on.
+//! When it does arrive and the guess was wrong, we restore the saved state from that tick and
+//! re-simulate up to now with the real input.
+//!
+//! Peers also swap a hash of the world every so often and report a desync if they disagree.
+//! rapier is only bit-for-bit deterministic across machines with its `enhanced-determinism`
+//! feature, so expect desyncs between different CPUs without it.
+//!
+//! Snapshots hold the ECS side of the world, not rapier's contact and solver caches, so a tick
+//! played again after a rollback can come out slightly differently from the first time round. The
+//! checksum only covers what a snapshot restores, player, ball and platform transforms and
+//! velocities, so those small differences show up there as a desync rather than going unnoticed.
+//!
+//! Try it on one machine with
+//! `--rollback 127.0.0.1:6000 127.0.0.1:6001` and `--rollback 127.0.0.1:6001 127.0.0.1:6000`,
+//! adding `--latency 80 --loss 0.05` to either to simulate a bad connection.
+
+use crate::ball::{
+    self, activate_ball, park_ball, Ball, BallAssets, BallKind, BallLifetime, BallPool, BallRng,
+    BallSpawnConfig, ThrownBy,
+};
+use crate::camera::PlayerCamera;
+use crate::input::{LocalPlayer, PlayerActions};
+use crate::player::{
+    self, spawn_player, spawn_point, CatchState, DashState, Grounded, HeldBall, HitStun, Player,
+};
+use crate::world::{self, Cubeovator, SimulationSet};
+use bevy::ecs::schedule::ScheduleLabel;
+use bevy::ecs::world::CommandQueue;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_rapier3d::prelude::*;
+use rand::prelude::*;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+const TICK_SECS: f32 = 1.0 / 60.0;
+// Local input is held back this many ticks, which hides small amounts of latency without rolling back
+const INPUT_DELAY: u32 = 2;
+// Stop and wait for the other peer rather than guess further ahead than this
+const MAX_PREDICTION_TICKS: u32 = 8;
+const SAVED_TICKS: u32 = 64;
+// Every packet repeats this many recent inputs so a lost packet doesn't lose an input
+const REDUNDANT_INPUTS: u32 = 16;
+const CHECKSUM_INTERVAL: u32 = 30;
+const MAX_TICKS_PER_FRAME: u32 = 4;
+const DEFAULT_SEED: u64 = 0xd0d6e;
+
+pub struct RollbackPlugin {
+    pub config: Option<RollbackConfig>,
+}
+
+impl Plugin for RollbackPlugin {
+    fn build(&self, app: &mut App) {
+        let Some(config) = self.config.clone() else {
+            return;
+        };
+        let socket = UdpSocket::bind(config.local).expect("Error binding rollback socket");
+        socket.set_nonblocking(true).unwrap();
+        println!("Rollback session {} <-> {}", config.local, config.peer);
+
+        let mut schedule = Schedule::new(RollbackSchedule);
+        schedule.add_systems(
+            (
+                world::simulation_systems(),
+                player::simulation_systems(),
+                ball::simulation_systems(),
+                RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::SyncBackend),
+                RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::StepSimulation),
+                RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::Writeback),
+            )
+                .chain(),
+        );
+
+        app.add_schedule(schedule)
+            .insert_resource(BallRng(StdRng::seed_from_u64(config.seed)))
+            .insert_resource(RollbackSession::new(config, socket))
+            // The session steps the simulation itself
+            .configure_sets(Update, SimulationSet.run_if(|| false))
+            .add_systems(
+                PostStartup,
+                fix_physics_timestep.run_if(resource_exists::<RollbackSession>),
+            )
+            .add_systems(
+                Update,
+                (
+                    setup_rollback_players,
+                    detach_local_camera,
+                    advance_rollback,
+                )
+                    .chain(),
+            );
+    }
+}
+
+#[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RollbackSchedule;
+
+/// From `--rollback <local addr> <peer addr> [--latency <ms>] [--loss <0-1>] [--seed <n>]`.
+#[derive(Clone, Debug)]
+pub struct RollbackConfig {
+    pub local: SocketAddr,
+    pub peer: SocketAddr,
+    /// Added to every packet we send, to try out bad connections on loopback
+    pub latency: Duration,
+    /// Chance of dropping each packet we send
+    pub loss: f32,
+    pub seed: u64,
+}
+
+impl RollbackConfig {
+    pub fn from_args() -> Option<Self> {
+        let args: Vec<String> = std::env::args().collect();
+        let value_after = |flag: &str, offset: usize| {
+            args.iter()
+                .position(|arg| arg == flag)
+                .and_then(|i| args.get(i + offset))
+        };
+        Some(Self {
+            local: value_after("--rollback", 1)?.parse().ok()?,
+            peer: value_after("--rollback", 2)?.parse().ok()?,
+            latency: Duration::from_millis(
+                value_after("--latency", 1)
+                    .and_then(|ms| ms.parse().ok())
+                    .unwrap_or(0),
+            ),
+            loss: value_after("--loss", 1)
+                .and_then(|loss| loss.parse().ok())
+                .unwrap_or(0.0),
+            seed: value_after("--seed", 1)
+                .and_then(|seed| seed.parse().ok())
+                .unwrap_or(DEFAULT_SEED),
+        })
+    }
+}
+
+/// Which of the two peers drives this player. Both peers agree on this.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RollbackPlayer {
+    pub handle: usize,
+}
+
+/// Steers a rollback player from the camera yaw in their inputs, so both peers steer them the same way.
+#[derive(Component)]
+struct RollbackCameraProxy;
+
+/// One tick of one player's input, quantized so both peers see exactly the same numbers.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+struct TickInput {
+    movement: [i8; 2],
+    jump: bool,
+    catch: bool,
+    throw: bool,
+    dash: bool,
+    camera_yaw: u16,
+}
+
+impl TickInput {
+    fn from_actions(actions: &PlayerActions, camera_rotation: Quat) -> Self {
+        let (yaw, _, _) = camera_rotation.to_euler(EulerRot::YXZ);
+        let quantize = |axis: f32| (axis.clamp(-1.0, 1.0) * 127.0).round() as i8;
+        Self {
+            movement: [quantize(actions.movement.x), quantize(actions.movement.y)],
+            jump: actions.jump,
+            catch: actions.catch,
+            throw: actions.throw,
+            dash: actions.dash,
+            camera_yaw: (yaw.rem_euclid(std::f32::consts::TAU) / std::f32::consts::TAU
+                * u16::MAX as f32) as u16,
+        }
+    }
+
+    fn movement(self) -> Vec2 {
+        Vec2::new(self.movement[0] as f32, self.movement[1] as f32) / 127.0
+    }
+
+    fn camera_rotation(self) -> Quat {
+        Quat::from_rotation_y(self.camera_yaw as f32 / u16::MAX as f32 * std::f32::consts::TAU)
+    }
+}
+
+struct PlayerState {
+    entity: Entity,
+    transform: Transform,
+    velocity: Velocity,
+    grounded: Grounded,
+    hit_stun: Option<HitStun>,
+    locked_axes: LockedAxes,
+    held_ball: Option<HeldBall>,
+    catch_state: CatchState,
+    dash_state: DashState,
+}
+
+struct BallState {
+    entity: Entity,
+    kind: BallKind,
+    transform: Transform,
+    velocity: Velocity,
+    lifetime: BallLifetime,
+    thrown_by: Option<ThrownBy>,
+    in_play: bool,
+}
+
+/// Everything that can change during a tick, saved before the tick runs.
+struct WorldSnapshot {
+    players: Vec<PlayerState>,
+    balls: Vec<BallState>,
+    platforms: Vec<(Entity, Transform, Cubeovator)>,
+    pool: BallPool,
+    spawn_config: BallSpawnConfig,
+    rng: BallRng,
+    checksum: u64,
+}
+
+struct DelayedPacket {
+    send_at: Duration,
+    bytes: Vec<u8>,
+}
+
+#[derive(Resource)]
+pub struct RollbackSession {
+    config: RollbackConfig,
+    socket: UdpSocket,
+    local_handle: usize,
+    /// The next tick to simulate
+    tick: u32,
+    accumulator: f32,
+    /// Inputs by tick, for each handle
+    inputs: [HashMap<u32, TickInput>; 2],
+    /// The remote inputs we actually simulated with, to spot wrong guesses
+    predicted: HashMap<u32, TickInput>,
+    /// Every remote input before this tick has arrived
+    remote_confirmed: u32,
+    /// The remote peer has every one of our inputs before this tick
+    remote_acked: u32,
+    snapshots: HashMap<u32, WorldSnapshot>,
+    remote_checksums: HashMap<u32, u64>,
+    outgoing: VecDeque<DelayedPacket>,
+    rng: StdRng,
+}
+
+impl RollbackSession {
+    fn new(config: RollbackConfig, socket: UdpSocket) -> Self {
+        // Both peers sort their addresses the same way, so they agree on who is player 0
+        let local_handle = if config.local < config.peer { 0 } else { 1 };
+        Self {
+            config,
+            socket,
+            local_handle,
+            tick: 0,
+            accumulator: 0.0,
+            inputs: [HashMap::new(), HashMap::new()],
+            predicted: HashMap::new(),
+            remote_confirmed: 0,
+            remote_acked: 0,
+            snapshots: HashMap::new(),
+            remote_checksums: HashMap::new(),
+            outgoing: VecDeque::new(),
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    fn remote_handle(&self) -> usize {
+        1 - self.local_handle
+    }
+
+    /// The remote input for `tick`, or our best guess at it
+    fn remote_input(&self, tick: u32) -> (TickInput, bool) {
+        let remote = &self.inputs[self.remote_handle()];
+        match remote.get(&tick) {
+            Some(&input) => (input, true),
+            None => {
+                let last_known = self.remote_confirmed.checked_sub(1);
+                let guess = last_known
+                    .and_then(|t| remote.get(&t).copied())
+                    .unwrap_or_default();
+                (guess, false)
+            }
+        }
+    }
+
+    fn encode_packet(&self) -> Vec<u8> {
+        let local = &self.inputs[self.local_handle];
+        let start = self
+            .remote_acked
+            .max(self.tick.saturating_sub(REDUNDANT_INPUTS));
+        let end = self.tick + INPUT_DELAY;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&start.to_le_bytes());
+        let inputs: Vec<TickInput> = (start..end).map_while(|t| local.get(&t).copied()).collect();
+        bytes.push(inputs.len() as u8);
+        for input in inputs {
+            bytes.push(input.movement[0] as u8);
+            bytes.push(input.movement[1] as u8);
+            bytes.push(
+                input.jump as u8
+                    | (input.catch as u8) << 1
+                    | (input.throw as u8) << 2
+                    | (input.dash as u8) << 3,
+            );
+            bytes.extend_from_slice(&input.camera_yaw.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.remote_confirmed.to_le_bytes());
+        // The newest checksum we know is final
+        let checksum_tick = self.final_tick() / CHECKSUM_INTERVAL * CHECKSUM_INTERVAL;
+        let checksum = self
+            .snapshots
+            .get(&checksum_tick)
+            .map_or(0, |snapshot| snapshot.checksum);
+        bytes.extend_from_slice(&checksum_tick.to_le_bytes());
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    /// Returns the earliest tick whose remote input turned out different from what we simulated.
+    fn decode_packet(&mut self, bytes: &[u8]) -> Option<Option<u32>> {
+        let mut bytes = bytes.iter().copied();
+        let mut take = |n: usize| -> Option<Vec<u8>> {
+            let taken: Vec<u8> = bytes.by_ref().take(n).collect();
+            (taken.len() == n).then_some(taken)
+        };
+        let u32_from = |b: Vec<u8>| u32::from_le_bytes(b.try_into().unwrap());
+        let start = u32_from(take(4)?);
+        let count = take(1)?[0] as u32;
+        let end = start.checked_add(count)?;
+        let mut inputs = Vec::with_capacity(count as usize);
+        for t in start..end {
+            let b = take(5)?;
+            let input = TickInput {
+                movement: [b[0] as i8, b[1] as i8],
+                jump: b[2] & 1 != 0,
+                catch: b[2] & 2 != 0,
+                throw: b[2] & 4 != 0,
+                dash: b[2] & 8 != 0,
+                camera_yaw: u16::from_le_bytes([b[3], b[4]]),
+            };
+            inputs.push((t, input));
+        }
+        let acked = u32_from(take(4)?);
+        let checksum_tick = u32_from(take(4)?);
+        let checksum = u64::from_le_bytes(take(8)?.try_into().unwrap());
+
+        // Only a whole packet is let through, a short one would lose the inputs it brought
+        let mut mispredicted = None;
+        let remote_handle = self.remote_handle();
+        for (t, input) in inputs {
+            if self.inputs[remote_handle].insert(t, input).is_none() {
+                if let Some(&guess) = self.predicted.get(&t) {
+                    if guess != input {
+                        mispredicted = Some(mispredicted.map_or(t, |m: u32| m.min(t)));
+                    }
+                }
+            }
+        }
+        while self.inputs[remote_handle].contains_key(&self.remote_confirmed) {
+            self.remote_confirmed += 1;
+        }
+        self.remote_acked = self.remote_acked.max(acked);
+        if checksum_tick > 0 {
+            self.remote_checksums.insert(checksum_tick, checksum);
+        }
+        Some(mispredicted)
+    }
+
+    /// Ticks before this will never be re-simulated, both peers' inputs for them are known
+    fn final_tick(&self) -> u32 {
+        self.remote_confirmed.min(self.tick)
+    }
+
+    fn send(&mut self, now: Duration) {
+        if self.rng.gen::<f32>() < self.config.loss {
+            return;
+        }
+        let bytes = self.encode_packet();
+        self.outgoing.push_back(DelayedPacket {
+            send_at: now + self.config.latency,
+            bytes,
+        });
+        while self
+            .outgoing
+            .front()
+            .is_some_and(|packet| packet.send_at <= now)
+        {
+            let packet = self.outgoing.pop_front().unwrap();
+            if let Err(e) = self.socket.send_to(&packet.bytes, self.config.peer) {
+                println!("Error sending to peer: {}", e);
+            }
+        }
+    }
+}
+
+// Both peers step rapier by exactly one tick, rather than by however long their last frame took
+fn fix_physics_timestep(mut rapier_config: ResMut<RapierConfiguration>) {
+    rapier_config.timestep_mode = TimestepMode::Fixed {
+        dt: TICK_SECS,
+        substeps: 1,
+    };
+}
+
+// Both peers need both players, numbered the same way
+fn setup_rollback_players(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    session: Res<RollbackSession>,
+    mut local_q: Query<(Entity, &mut Transform), Added<LocalPlayer>>,
+) {
+    for (local_player, mut transform) in local_q.iter_mut() {
+        let local_handle = session.local_handle;
+        let remote_handle = session.remote_handle();
+        transform.translation = spawn_point(local_handle, 2);
+        commands.entity(local_player).insert(RollbackPlayer {
+            handle: local_handle,
+        });
+
+        let remote_player = spawn_player(&mut commands, &assets, spawn_point(remote_handle, 2));
+        commands.entity(remote_player).insert((
+            RollbackPlayer {
+                handle: remote_handle,
+            },
+            Name::new("Remote player"),
+        ));
+
+        for player in [local_player, remote_player] {
+            commands.spawn((
+                TransformBundle::default(),
+                RollbackCameraProxy,
+                PlayerCamera { player },
+            ));
+        }
+    }
+}
+
+// The real camera still follows our player, but steering comes from the camera yaw in our inputs
+fn detach_local_camera(
+    mut commands: Commands,
+    camera_q: Query<Entity, (Added<PlayerCamera>, With<Camera>)>,
+) {
+    for camera in camera_q.iter() {
+        commands.entity(camera).remove::<PlayerCamera>();
+    }
+}
+
+fn hash_state(hasher: &mut impl Hasher, transform: &Transform, velocity: &Velocity) {
+    // Quantize so values that print the same hash the same
+    let quantize = |v: Vec3| (v * 1000.0).round().as_ivec3().to_array();
+    quantize(transform.translation).hash(hasher);
+    quantize(velocity.linvel).hash(hasher);
+}
+
+fn save_snapshot(world: &mut World) -> WorldSnapshot {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+
+    let mut player_q = world.query_filtered::<(
+        Entity,
+        &RollbackPlayer,
+        &Transform,
+        &Velocity,
+        &Grounded,
+        Option<&HitStun>,
+        &LockedAxes,
+        Option<&HeldBall>,
+        &CatchState,
+        &DashState,
+    ), With<Player>>();
+    let mut players: Vec<_> = player_q.iter(world).collect();
+    players.sort_by_key(|(_, rollback_player, ..)| rollback_player.handle);
+    let players: Vec<PlayerState> = players
+        .into_iter()
+        .map(
+            |(
+                entity,
+                rollback_player,
+                transform,
+                velocity,
+                grounded,
+                hit_stun,
+                locked_axes,
+                held_ball,
+                catch_state,
+                dash_state,
+            )| {
+                rollback_player.handle.hash(&mut hasher);
+                hash_state(&mut hasher, transform, velocity);
+                grounded.count.hash(&mut hasher);
+                held_ball.is_some().hash(&mut hasher);
+                PlayerState {
+                    entity,
+                    transform: *transform,
+                    velocity: *velocity,
+                    grounded: grounded.clone(),
+                    hit_stun: hit_stun.cloned(),
+                    locked_axes: *locked_axes,
+                    held_ball: held_ball.cloned(),
+                    catch_state: catch_state.clone(),
+                    dash_state: dash_state.clone(),
+                }
+            },
+        )
+        .collect();
+
+    let mut ball_q = world.query_filtered::<(
+        Entity,
+        &BallKind,
+        &Transform,
+        &Velocity,
+        &BallLifetime,
+        Option<&ThrownBy>,
+        Has<RigidBodyDisabled>,
+    ), With<Ball>>();
+    let balls: Vec<BallState> = ball_q
+        .iter(world)
+        .map(
+            |(entity, kind, transform, velocity, lifetime, thrown_by, disabled)| BallState {
+                entity,
+                kind: *kind,
+                transform: *transform,
+                velocity: *velocity,
+                lifetime: lifetime.clone(),
+                thrown_by: thrown_by.copied(),
+                in_play: !disabled,
+            },
+        )
+        .collect();
+    // Entity ids differ between peers, so the balls in play are hashed in an order both agree on
+    let mut in_play: Vec<&BallState> = balls.iter().filter(|ball| ball.in_play).collect();
+    in_play.sort_by_key(|ball| {
+        (ball.transform.translation * 1000.0)
+            .round()
+            .as_ivec3()
+            .to_array()
+    });
+    for ball in in_play {
+        hash_state(&mut hasher, &ball.transform, &ball.velocity);
+    }
+
+    let mut platform_q = world.query::<(Entity, &Transform, &Cubeovator)>();
+    let platforms: Vec<(Entity, Transform, Cubeovator)> = platform_q
+        .iter(world)
+        .map(|(entity, transform, cube)| (entity, *transform, *cube))
+        .collect();
+    for (_, transform, _) in &platforms {
+        hash_state(&mut hasher, transform, &Velocity::zero());
+    }
+
+    let spawn_config = world.resource::<BallSpawnConfig>().clone();
+    spawn_config.timer.elapsed().hash(&mut hasher);
+
+    WorldSnapshot {
+        players,
+        balls,
+        platforms,
+        pool: world.resource::<BallPool>().clone(),
+        spawn_config,
+        rng: world.resource::<BallRng>().clone(),
+        checksum: hasher.finish(),
+    }
+}
+
+fn load_snapshot(world: &mut World, snapshot: &WorldSnapshot) {
+    for player in &snapshot.players {
+        let Some(mut entity) = world.get_entity_mut(player.entity) else {
+            continue;
+        };
+        entity.insert((
+            player.transform,
+            player.velocity,
+            player.grounded.clone(),
+            player.locked_axes,
+            player.catch_state.clone(),
+            player.dash_state.clone(),
+        ));
+        match &player.hit_stun {
+            Some(hit_stun) => {
+                entity.insert(hit_stun.clone());
+            }
+            None => {
+                entity.remove::<HitStun>();
+            }
+        }
+        match &player.held_ball {
+            Some(held_ball) => {
+                entity.insert(held_ball.clone());
+            }
+            None => {
+                entity.remove::<HeldBall>();
+            }
+        }
+    }
+
+    for (entity, transform, cube) in &snapshot.platforms {
+        if let Some(mut platform) = world.get_entity_mut(*entity) {
+            platform.insert((*transform, *cube));
+        }
+    }
+
+    let mut pool = snapshot.pool.clone();
+    world.resource_scope(|world, ball_assets: Mut<BallAssets>| {
+        let mut ball_q = world.query_filtered::<Entity, With<Ball>>();
+        let current: Vec<Entity> = ball_q.iter(world).collect();
+        let mut commands_queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut commands_queue, world);
+        for ball in &snapshot.balls {
+            if ball.in_play {
+                activate_ball(
+                    &mut commands,
+                    &ball_assets,
+                    ball.entity,
+                    ball.kind,
+                    ball.transform,
+                    ball.velocity,
+                    ball.lifetime.clone(),
+                );
+            } else {
+                park_ball(&mut commands, ball.entity);
+            }
+            match ball.thrown_by {
+                Some(thrown_by) => {
+                    commands.entity(ball.entity).insert(thrown_by);
+                }
+                None => {
+                    commands.entity(ball.entity).remove::<ThrownBy>();
+                }
+            }
+        }
+        // Held balls are parked, but still shown in their holder's hands
+        for held_ball in snapshot.players.iter().filter_map(|p| p.held_ball.as_ref()) {
+            commands
+                .entity(held_ball.ball)
+                .insert(Visibility::Inherited);
+        }
+        // Balls the pool grew into after this snapshot go back into it
+        for entity in current {
+            if !snapshot.balls.iter().any(|ball| ball.entity == entity) {
+                park_ball(&mut commands, entity);
+                pool.free.push(entity);
+            }
+        }
+        commands_queue.apply(world);
+    });
+
+    world.insert_resource(pool);
+    world.insert_resource(snapshot.spawn_config.clone());
+    world.insert_resource(snapshot.rng.clone());
+}
+
+fn apply_tick_inputs(world: &mut World, inputs: [TickInput; 2]) {
+    let mut player_q = world.query::<(Entity, &RollbackPlayer, &mut PlayerActions)>();
+    let mut players = Vec::new();
+    for (entity, rollback_player, mut actions) in player_q.iter_mut(world) {
+        let input = inputs[rollback_player.handle];
+        *actions = PlayerActions {
+            movement: input.movement(),
+            look: Vec2::ZERO,
+            jump: input.jump,
+            catch: input.catch,
+            throw: input.throw,
+            dash: input.dash,
+            ..default()
+        };
+        players.push((entity, input.camera_rotation()));
+    }
+    let mut proxy_q =
+        world.query_filtered::<(&PlayerCamera, &mut Transform), With<RollbackCameraProxy>>();
+    for (player_camera, mut transform) in proxy_q.iter_mut(world) {
+        if let Some((_, rotation)) = players
+            .iter()
+            .find(|(player, _)| *player == player_camera.player)
+        {
+            transform.rotation = *rotation;
+        }
+    }
+}
+
+// Runs one tick of the simulation with a fixed delta
+fn simulate_tick(world: &mut World, tick: u32) {
+    let mut tick_time = Time::<()>::default();
+    tick_time.advance_to(Duration::from_secs_f32(tick as f32 * TICK_SECS));
+    tick_time.advance_to(Duration::from_secs_f32((tick + 1) as f32 * TICK_SECS));
+    let frame_time = std::mem::replace(&mut *world.resource_mut::<Time>(), tick_time);
+    world.run_schedule(RollbackSchedule);
+    *world.resource_mut::<Time>() = frame_time;
+}
+
+fn advance_rollback(world: &mut World) {
+    let mut camera_q = world.query_filtered::<&Transform, With<Camera3d>>();
+    let camera_rotation = camera_q
+        .iter(world)
+        .next()
+        .map_or(Quat::IDENTITY, |transform| transform.rotation);
+    let mut player_q =
+        world.query_filtered::<(&PlayerActions, &RollbackPlayer), With<LocalPlayer>>();
+    let local_input = player_q
+        .iter(world)
+        .next()
+        .map(|(actions, _)| TickInput::from_actions(actions, camera_rotation));
+    let Some(local_input) = local_input else {
+        // Players haven't been set up yet
+        return;
+    };
+    let now = world.resource::<Time<Real>>().elapsed();
+    let frame_delta = world.resource::<Time>().delta_seconds();
+
+    world.resource_scope(|world, mut session: Mut<RollbackSession>| {
+        // Hear from the peer
+        let mut rollback_to: Option<u32> = None;
+        let mut buf = [0; 1500];
+        loop {
+            let len = match session.socket.recv_from(&mut buf) {
+                Ok((len, addr)) if addr == session.config.peer => len,
+                Ok(_) => continue,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    println!("Error receiving from peer: {}", e);
+                    break;
+                }
+            };
+            if let Some(Some(tick)) = session.decode_packet(&buf[..len]) {
+                rollback_to = Some(rollback_to.map_or(tick, |t| t.min(tick)));
+            }
+        }
+
+        // Our guess was wrong, go back and play it again with what really happened
+        if let Some(from) = rollback_to.filter(|&from| from < session.tick) {
+            if let Some(snapshot) = session.snapshots.get(&from) {
+                load_snapshot(world, snapshot);
+                for tick in from..session.tick {
+                    run_tick(world, &mut session, tick);
+                }
+            }
+        }
+
+        // Catch up with real time, unless we'd be guessing too far ahead of the peer
+        session.accumulator += frame_delta;
+        let mut ticks_this_frame = 0;
+        while session.accumulator >= TICK_SECS && ticks_this_frame < MAX_TICKS_PER_FRAME {
+            if session.tick >= session.remote_confirmed + MAX_PREDICTION_TICKS {
+                session.accumulator = 0.0;
+                break;
+            }
+            session.accumulator -= TICK_SECS;
+            ticks_this_frame += 1;
+
+            let input_tick = session.tick + INPUT_DELAY;
+            let local_handle = session.local_handle;
+            session.inputs[local_handle].insert(input_tick, local_input);
+            let tick = session.tick;
+            run_tick(world, &mut session, tick);
+            session.tick += 1;
+        }
+
+        // Forget what's too old to roll back to
+        let oldest = session.final_tick().saturating_sub(SAVED_TICKS);
+        session.snapshots.retain(|&tick, _| tick >= oldest);
+        session.predicted.retain(|&tick, _| tick >= oldest);
+        for inputs in session.inputs.iter_mut() {
+            inputs.retain(|&tick, _| tick >= oldest);
+        }
+
+        check_for_desync(&mut session);
+        session.send(now);
+    });
+}
+
+fn run_tick(world: &mut World, session: &mut RollbackSession, tick: u32) {
+    let snapshot = save_snapshot(world);
+    session.snapshots.insert(tick, snapshot);
+
+    let local_input = session.inputs[session.local_handle]
+        .get(&tick)
+        .copied()
+        .unwrap_or_default();
+    let (remote_input, confirmed) = session.remote_input(tick);
+    if confirmed {
+        session.predicted.remove(&tick);
+    } else {
+        session.predicted.insert(tick, remote_input);
+    }
+    let mut inputs = [TickInput::default(); 2];
+    inputs[session.local_handle] = local_input;
+    inputs[session.remote_handle()] = remote_input;
+
+    apply_tick_inputs(world, inputs);
+    simulate_tick(world, tick);
+}
+
+fn check_for_desync(session: &mut RollbackSession) {
+    let final_tick = session.final_tick();
+    let mut checked = Vec::new();
+    for (&tick, &remote_checksum) in session.remote_checksums.iter() {
+        if tick >= final_tick {
+            continue;
+        }
+        checked.push(tick);
+        if let Some(snapshot) = session.snapshots.get(&tick) {
+            if snapshot.checksum != remote_checksum {
+                println!(
+                    "Desync at tick {}: local {:016x}, remote {:016x}",
+                    tick, snapshot.checksum, remote_checksum
+                );
+            }
+        }
+    }
+    for tick in checked {
+        session.remote_checksums.remove(&tick);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(local: &str, peer: &str) -> RollbackSession {
+        let config = RollbackConfig {
+            local: local.parse().unwrap(),
+            peer: peer.parse().unwrap(),
+            latency: Duration::ZERO,
+            loss: 0.0,
+            seed: DEFAULT_SEED,
+        };
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        RollbackSession::new(config, socket)
+    }
+
+    // Player 0 sending to player 1, with inputs for the first twelve ticks
+    fn peers() -> (RollbackSession, RollbackSession) {
+        let mut sender = session("127.0.0.1:6000", "127.0.0.1:6001");
+        let receiver = session("127.0.0.1:6001", "127.0.0.1:6000");
+        for tick in 0..12 {
+            sender.inputs[0].insert(
+                tick,
+                TickInput {
+                    movement: [tick as i8, -(tick as i8)],
+                    jump: tick % 2 == 0,
+                    catch: tick % 3 == 0,
+                    throw: tick == 7,
+                    dash: tick == 5,
+                    camera_yaw: tick as u16 * 5000,
+                },
+            );
+        }
+        sender.tick = 10;
+        (sender, receiver)
+    }
+
+    #[test]
+    fn inputs_round_trip() {
+        let (sender, mut receiver) = peers();
+        assert_eq!(receiver.decode_packet(&sender.encode_packet()), Some(None));
+        assert_eq!(receiver.remote_confirmed, 12);
+        for tick in 0..12 {
+            assert_eq!(receiver.inputs[0].get(&tick), sender.inputs[0].get(&tick));
+        }
+    }
+
+    #[test]
+    fn wrong_guesses_are_reported() {
+        let (sender, mut receiver) = peers();
+        receiver.predicted.insert(2, sender.inputs[0][&2]);
+        receiver.predicted.insert(3, TickInput::default());
+        receiver.predicted.insert(8, TickInput::default());
+        assert_eq!(
+            receiver.decode_packet(&sender.encode_packet()),
+            Some(Some(3))
+        );
+    }
+
+    #[test]
+    fn truncated_packets_are_dropped() {
+        let (sender, _) = peers();
+        let bytes = sender.encode_packet();
+        for len in 0..bytes.len() {
+            let (_, mut receiver) = peers();
+            assert!(
+                receiver.decode_packet(&bytes[..len]).is_none(),
+                "{len} bytes"
+            );
+            assert!(receiver.inputs[0].is_empty(), "{len} bytes");
+            assert_eq!(receiver.remote_confirmed, 0, "{len} bytes");
+            assert_eq!(receiver.remote_acked, 0, "{len} bytes");
+            assert!(receiver.remote_checksums.is_empty(), "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn wrong_guesses_survive_a_truncated_packet() {
+        let (sender, mut receiver) = peers();
+        receiver.predicted.insert(3, TickInput::default());
+        let bytes = sender.encode_packet();
+        assert!(receiver.decode_packet(&bytes[..bytes.len() - 1]).is_none());
+        assert_eq!(receiver.decode_packet(&bytes), Some(Some(3)));
+    }
+
+    #[test]
+    fn packets_running_past_the_last_tick_are_dropped() {
+        let (_, mut receiver) = peers();
+        let mut bytes = (u32::MAX - 1).to_le_bytes().to_vec();
+        bytes.push(5);
+        bytes.extend([0; 5 * 5 + 16]);
+        assert!(receiver.decode_packet(&bytes).is_none());
+    }
+}
@@ -0,0 +1,460 @@
+//! Saving a match in progress and picking it back up.
+//!
+//! F5 saves to `saves/quicksave.ron` and F9 loads it again. A save is a `SaveGame`, written and
+//! read through Bevy reflection. Players and platforms are matched up by the order they were
+//! spawned in, so a save only loads properly into a game started with the same arguments.
+//!
+//! Every save records the version it was written with. Saves from newer versions are refused.
+//! Older saves load as long as fields added since have `#[reflect(default)]`, or the save types
+//! keep `#[reflect(Default)]`, which fills in anything missing.
+
+use bevy::ecs::world::CommandQueue;
+use bevy::reflect::serde::{TypedReflectDeserializer, TypedReflectSerializer};
+use bevy::reflect::TypeRegistry;
+use bevy::{input::common_conditions::input_just_pressed, prelude::*};
+use bevy_rapier3d::prelude::*;
+use serde::de::DeserializeSeed;
+use std::error::Error;
+use std::path::Path;
+
+use crate::ball::{
+    activate_ball, park_ball, release_ball, take_ball, Ball, BallAssets, BallKind, BallLifetime,
+    BallPool, BallSpawnConfig, ThrownBy,
+};
+use crate::dodgeball::{Eliminated, Match, MatchState};
+use crate::player::{HeldBall, HitStun, Player, Team};
+use crate::rollback::RollbackSession;
+use crate::world::{Cubeovator, SimulationSet};
+
+/// Bump this whenever `SaveGame` changes shape
+const SAVE_VERSION: u32 = 3;
+const QUICKSAVE_PATH: &str = "saves/quicksave.ron";
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<SaveGame>().add_systems(
+            Update,
+            (
+                save_game.run_if(input_just_pressed(KeyCode::F5)),
+                // Loading would desync a rollback session
+                load_game
+                    .run_if(input_just_pressed(KeyCode::F9))
+                    .run_if(not(resource_exists::<RollbackSession>)),
+            )
+                .after(SimulationSet),
+        );
+    }
+}
+
+/// Everything needed to pick a match back up.
+#[derive(Reflect, Default, Debug)]
+#[reflect(Default)]
+pub struct SaveGame {
+    pub version: u32,
+    /// In the order they were spawned
+    pub players: Vec<PlayerSave>,
+    /// Balls in play. Held balls are saved with whoever is holding them.
+    pub balls: Vec<BallSave>,
+    /// The ball launcher's timer, so the next ball comes out when it would have
+    pub launcher: Timer,
+    /// In the order they were spawned
+    pub platforms: Vec<Cubeovator>,
+    /// Where each of `platforms` is. Platforms move on from wherever they are, so `phase` alone
+    /// doesn't put them back on their path.
+    #[reflect(default)]
+    pub platform_transforms: Vec<Transform>,
+    /// Score and round, if a dodgeball match is being played
+    pub dodgeball: Option<MatchSave>,
+}
+
+#[derive(Reflect, Default, Debug)]
+#[reflect(Default)]
+pub struct PlayerSave {
+    pub team: Option<Team>,
+    pub transform: Transform,
+    pub velocity: Velocity,
+    /// Players don't have health. Being hit stuns them, and in a match puts them out.
+    pub hit_stun: Option<HitStun>,
+    pub eliminated: bool,
+    pub held_ball: Option<BallKind>,
+}
+
+#[derive(Reflect, Default, Debug)]
+#[reflect(Default)]
+pub struct BallSave {
+    pub kind: BallKind,
+    pub transform: Transform,
+    pub velocity: Velocity,
+    pub lifetime: BallLifetime,
+    /// Index into `SaveGame::players`
+    pub thrown_by: Option<usize>,
+}
+
+#[derive(Reflect, Default, Debug)]
+#[reflect(Default)]
+pub struct MatchSave {
+    pub state: MatchState,
+    pub round: u32,
+    pub wins: [u32; 2],
+    /// Indices into `SaveGame::players`, longest out first
+    pub benched: Vec<usize>,
+    /// Seconds since the match started
+    #[reflect(default)]
+    pub secs: f32,
+}
+
+// Players in the order they were spawned, which is the same from run to run
+fn players_in_order(world: &mut World) -> Vec<Entity> {
+    let mut player_q = world.query_filtered::<Entity, With<Player>>();
+    let mut players: Vec<Entity> = player_q.iter(world).collect();
+    players.sort();
+    players
+}
+
+fn platforms_in_order(world: &mut World) -> Vec<Entity> {
+    let mut platform_q = world.query_filtered::<Entity, With<Cubeovator>>();
+    let mut platforms: Vec<Entity> = platform_q.iter(world).collect();
+    platforms.sort();
+    platforms
+}
+
+fn capture(world: &mut World) -> SaveGame {
+    let player_order = players_in_order(world);
+    let index_of = |entity: Entity| player_order.iter().position(|&player| player == entity);
+
+    let mut player_q = world.query::<(
+        &Transform,
+        &Velocity,
+        Option<&Team>,
+        Option<&HitStun>,
+        Has<Eliminated>,
+        Option<&HeldBall>,
+    )>();
+    let players = player_order
+        .iter()
+        .filter_map(|&player| player_q.get(world, player).ok())
+        .map(
+            |(transform, velocity, team, hit_stun, eliminated, held_ball)| PlayerSave {
+                team: team.copied(),
+                transform: *transform,
+                velocity: *velocity,
+                hit_stun: hit_stun.cloned(),
+                eliminated,
+                held_ball: held_ball.map(|held_ball| held_ball.kind),
+            },
+        )
+        .collect();
+
+    let mut ball_q = world.query_filtered::<(
+        &BallKind,
+        &Transform,
+        &Velocity,
+        &BallLifetime,
+        Option<&ThrownBy>,
+    ), (With<Ball>, Without<RigidBodyDisabled>)>();
+    let balls = ball_q
+        .iter(world)
+        .map(
+            |(kind, transform, velocity, lifetime, thrown_by)| BallSave {
+                kind: *kind,
+                transform: *transform,
+                velocity: *velocity,
+                lifetime: lifetime.clone(),
+                thrown_by: thrown_by.and_then(|thrown_by| index_of(thrown_by.player)),
+            },
+        )
+        .collect();
+
+    let mut platform_q = world.query::<(&Cubeovator, &Transform)>();
+    let (platforms, platform_transforms) = platforms_in_order(world)
+        .into_iter()
+        .filter_map(|platform| platform_q.get(world, platform).ok())
+        .map(|(cube, transform)| (*cube, *transform))
+        .unzip();
+
+    let dodgeball = world.get_resource::<Match>().map(|game| MatchSave {
+        state: game.state.clone(),
+        round: game.round,
+        wins: game.wins,
+        benched: game
+            .benched
+            .iter()
+            .filter_map(|&player| index_of(player))
+            .collect(),
+        secs: game.secs,
+    });
+
+    SaveGame {
+        version: SAVE_VERSION,
+        players,
+        balls,
+        launcher: world.resource::<BallSpawnConfig>().timer.clone(),
+        platforms,
+        platform_transforms,
+        dodgeball,
+    }
+}
+
+fn restore(world: &mut World, save: &SaveGame) {
+    let player_order = players_in_order(world);
+    if player_order.len() != save.players.len() {
+        println!(
+            "Save has {} players but this game has {}, restoring as many as match up",
+            save.players.len(),
+            player_order.len()
+        );
+    }
+    let platform_order = platforms_in_order(world);
+
+    // Everything in play or in hand goes back to the pool, then the saved balls come out of it
+    let mut ball_q = world.query_filtered::<Entity, (With<Ball>, Without<RigidBodyDisabled>)>();
+    let mut returning: Vec<Entity> = ball_q.iter(world).collect();
+    let mut held_q = world.query::<&HeldBall>();
+    returning.extend(held_q.iter(world).map(|held_ball| held_ball.ball));
+
+    world.resource_scope(|world, ball_assets: Mut<BallAssets>| {
+        world.resource_scope(|world, mut pool: Mut<BallPool>| {
+            let mut commands_queue = CommandQueue::default();
+            let mut commands = Commands::new(&mut commands_queue, world);
+            for ball in returning {
+                release_ball(&mut commands, &mut pool, ball);
+            }
+
+            for (&player, saved) in player_order.iter().zip(&save.players) {
+                let mut entity = commands.entity(player);
+                entity.insert((saved.transform, saved.velocity));
+                match &saved.hit_stun {
+                    Some(hit_stun) if hit_stun.knocked_over() => {
+                        entity.insert((hit_stun.clone(), LockedAxes::empty()));
+                    }
+                    Some(hit_stun) => {
+                        entity.insert((hit_stun.clone(), LockedAxes::ROTATION_LOCKED));
+                    }
+                    None => {
+                        entity
+                            .remove::<HitStun>()
+                            .insert(LockedAxes::ROTATION_LOCKED);
+                    }
+                }
+                if saved.eliminated {
+                    entity.insert((Eliminated, RigidBodyDisabled, ColliderDisabled));
+                } else {
+                    entity.remove::<(Eliminated, RigidBodyDisabled, ColliderDisabled)>();
+                }
+                match saved.held_ball {
+                    Some(kind) => {
+                        let ball = take_ball(&mut commands, &mut pool, &ball_assets);
+                        park_ball(&mut commands, ball);
+                        commands.entity(ball).insert((kind, Visibility::Inherited));
+                        commands.entity(player).insert(HeldBall::new(ball, kind));
+                    }
+                    None => {
+                        commands.entity(player).remove::<HeldBall>();
+                    }
+                }
+            }
+
+            for saved in &save.balls {
+                let ball = take_ball(&mut commands, &mut pool, &ball_assets);
+                activate_ball(
+                    &mut commands,
+                    &ball_assets,
+                    ball,
+                    saved.kind,
+                    saved.transform,
+                    saved.velocity,
+                    saved.lifetime.clone(),
+                );
+                if let Some(&player) = saved.thrown_by.and_then(|index| player_order.get(index)) {
+                    commands.entity(ball).insert(ThrownBy { player });
+                }
+            }
+
+            for (i, (&platform, cube)) in platform_order.iter().zip(&save.platforms).enumerate() {
+                commands.entity(platform).insert(*cube);
+                // Version 1 saves only have the phase
+                if let Some(transform) = save.platform_transforms.get(i) {
+                    commands.entity(platform).insert(*transform);
+                }
+            }
+            commands_queue.apply(world);
+        });
+    });
+
+    // Whatever the launcher was winding up to fire goes, it picks again if it's due to
+    let mut launcher = world.resource_mut::<BallSpawnConfig>();
+    launcher.timer = save.launcher.clone();
+    launcher.next_kind = None;
+    if let (Some(saved), Some(mut game)) = (&save.dodgeball, world.get_resource_mut::<Match>()) {
+        game.state = saved.state.clone();
+        game.round = saved.round;
+        game.wins = saved.wins;
+        game.secs = saved.secs;
+        game.benched = saved
+            .benched
+            .iter()
+            .filter_map(|&index| player_order.get(index).copied())
+            .collect();
+    }
+}
+
+fn write_save(save: &SaveGame, registry: &TypeRegistry, path: &Path) -> Result<(), Box<dyn Error>> {
+    let serializer = TypedReflectSerializer::new(save, registry);
+    let text = ron::ser::to_string_pretty(&serializer, ron::ser::PrettyConfig::default())?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, text)?;
+    Ok(())
+}
+
+fn read_save(registry: &TypeRegistry, path: &Path) -> Result<SaveGame, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path)?;
+    let registration = registry
+        .get(std::any::TypeId::of::<SaveGame>())
+        .ok_or("SaveGame isn't registered")?;
+    let mut deserializer = ron::Deserializer::from_str(&text)?;
+    let reflected =
+        TypedReflectDeserializer::new(registration, registry).deserialize(&mut deserializer)?;
+    let save = SaveGame::from_reflect(&*reflected).ok_or("Save doesn't match SaveGame")?;
+    if save.version > SAVE_VERSION {
+        return Err(format!(
+            "save is version {}, but this build only reads up to {}",
+            save.version, SAVE_VERSION
+        )
+        .into());
+    }
+    Ok(save)
+}
+
+fn save_game(world: &mut World) {
+    let save = capture(world);
+    let registry = world.resource::<AppTypeRegistry>().read();
+    match write_save(&save, &registry, Path::new(QUICKSAVE_PATH)) {
+        Ok(()) => println!("Saved to {}", QUICKSAVE_PATH),
+        Err(error) => println!("Couldn't save to {}: {}", QUICKSAVE_PATH, error),
+    }
+}
+
+fn load_game(world: &mut World) {
+    let registry = world.resource::<AppTypeRegistry>().clone();
+    let save = match read_save(&registry.read(), Path::new(QUICKSAVE_PATH)) {
+        Ok(save) => save,
+        Err(error) => {
+            println!("Couldn't load {}: {}", QUICKSAVE_PATH, error);
+            return;
+        }
+    };
+    restore(world, &save);
+    println!("Loaded {} (version {})", QUICKSAVE_PATH, save.version);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn registry() -> TypeRegistry {
+        let mut registry = TypeRegistry::new();
+        registry.register::<SaveGame>();
+        registry
+    }
+
+    fn save() -> SaveGame {
+        SaveGame {
+            version: SAVE_VERSION,
+            players: vec![PlayerSave {
+                team: Some(Team::Away),
+                transform: Transform::from_xyz(1.0, 2.0, 3.0),
+                velocity: Velocity::linear(Vec3::X),
+                hit_stun: None,
+                eliminated: false,
+                held_ball: Some(BallKind::Heavy),
+            }],
+            balls: vec![BallSave {
+                kind: BallKind::Curve,
+                transform: Transform::from_xyz(-4.0, 1.0, 0.0),
+                velocity: Velocity::linear(Vec3::new(30.0, 1.0, 0.0)),
+                lifetime: BallLifetime::default(),
+                thrown_by: Some(0),
+            }],
+            launcher: Timer::from_seconds(3.0, TimerMode::Repeating),
+            platforms: vec![Cubeovator { phase: 1.5 }],
+            platform_transforms: vec![Transform::from_xyz(0.0, 2.0, 0.0)],
+            dodgeball: Some(MatchSave {
+                state: MatchState::RoundOver { secs_left: 2.0 },
+                round: 2,
+                wins: [1, 0],
+                benched: vec![0],
+                secs: 12.5,
+            }),
+        }
+    }
+
+    // A file of its own for each test, as they run side by side
+    fn save_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("save-test-{}-{}.ron", std::process::id(), name))
+    }
+
+    // Writes `save`, lets `edit` change the text as it's on disk, and reads it back
+    fn write_and_read(
+        save: &SaveGame,
+        name: &str,
+        edit: impl FnOnce(String) -> String,
+    ) -> Result<SaveGame, Box<dyn Error>> {
+        let registry = registry();
+        let path = save_path(name);
+        write_save(save, &registry, &path).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, edit(text)).unwrap();
+        let read = read_save(&registry, &path);
+        std::fs::remove_file(&path).unwrap();
+        read
+    }
+
+    #[test]
+    fn saves_round_trip() {
+        let save = save();
+        let read = write_and_read(&save, "round-trip", |text| text).unwrap();
+        assert_eq!(format!("{:?}", read), format!("{:?}", save));
+    }
+
+    #[test]
+    fn older_saves_load() {
+        let mut save = save();
+        save.platform_transforms.clear();
+        // Version 1 had no platform transforms or match clock
+        let read = write_and_read(&save, "older", |text| {
+            let version = format!("version: {},", SAVE_VERSION);
+            assert!(text.contains(&version));
+            let lines: Vec<&str> = text
+                .lines()
+                .filter(|line| {
+                    let line = line.trim();
+                    line != "platform_transforms: []," && line != "secs: 12.5,"
+                })
+                .collect();
+            assert_eq!(lines.len(), text.lines().count() - 2);
+            lines.join("\n").replace(&version, "version: 1,")
+        })
+        .unwrap();
+        assert_eq!(read.version, 1);
+        assert!(read.platform_transforms.is_empty());
+        let dodgeball = read.dodgeball.unwrap();
+        assert_eq!(dodgeball.round, 2);
+        assert_eq!(dodgeball.secs, 0.0);
+    }
+
+    #[test]
+    fn newer_saves_are_refused() {
+        let read = write_and_read(&save(), "newer", |text| {
+            text.replace(
+                &format!("version: {},", SAVE_VERSION),
+                &format!("version: {},", SAVE_VERSION + 1),
+            )
+        });
+        assert!(read.is_err());
+    }
+}
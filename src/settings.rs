@@ -0,0 +1,332 @@
+//! Player settings, kept in `settings.toml` next to the game.
+//!
+//! Escape opens the settings window. Changes apply as they're made and are written to the file
+//! when the window is closed. A missing or broken file just means the defaults.
+
+use bevy::prelude::*;
+use bevy::window::{PrimaryWindow, WindowMode};
+use bevy_inspector_egui::bevy_egui::{egui, EguiContexts, EguiPlugin};
+use bevy_third_person_camera::ThirdPersonCamera;
+use serde::{Deserialize, Serialize};
+
+use crate::bot::BotDifficulty;
+use crate::camera::PlayerCamera;
+
+const SETTINGS_PATH: &str = "settings.toml";
+/// What `mouse_sensitivity` is measured against, the sensitivity the camera always used to have
+const DEFAULT_SENSITIVITY: f32 = 2.5;
+const RESOLUTIONS: [[u32; 2]; 4] = [[1280, 720], [1600, 900], [1920, 1080], [2560, 1440]];
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        let mut settings = Settings::load();
+        // A difficulty on the command line wins for this run
+        if let Some(difficulty) = BotDifficulty::from_args() {
+            settings.difficulty = difficulty;
+        }
+        app.insert_resource(settings)
+            .init_resource::<SettingsMenu>()
+            .add_systems(
+                Update,
+                (apply_settings, apply_camera_settings, apply_light_settings),
+            );
+        // Headless runs have no egui, and nobody to show a menu to
+        if app.is_plugin_added::<EguiPlugin>() {
+            app.add_systems(Update, (toggle_settings_menu, settings_menu).chain());
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowModeSetting {
+    Windowed,
+    Borderless,
+    Fullscreen,
+}
+
+impl WindowModeSetting {
+    fn label(self) -> &'static str {
+        match self {
+            WindowModeSetting::Windowed => "Windowed",
+            WindowModeSetting::Borderless => "Borderless",
+            WindowModeSetting::Fullscreen => "Fullscreen",
+        }
+    }
+}
+
+/// Everything in `settings.toml`. Anything missing from the file keeps its default.
+#[derive(Resource, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct Settings {
+    pub mouse_sensitivity: f32,
+    pub invert_y: bool,
+    /// How hard throws are pulled towards a locked on target, 0 is not at all
+    pub aim_assist: f32,
+    /// Vertical field of view, in degrees
+    pub fov: f32,
+    pub shadows: bool,
+    pub resolution: [u32; 2],
+    pub window_mode: WindowModeSetting,
+    /// 0 is silent, 1 is full volume
+    pub volume: f32,
+    pub difficulty: BotDifficulty,
+    /// How hard the screen shakes, 0 turns it off
+    pub screen_shake: f32,
+    /// Time briefly slows when you're hit
+    pub hit_stop: bool,
+    /// The camera pulls back when several balls are coming at you
+    pub threat_framing: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            mouse_sensitivity: DEFAULT_SENSITIVITY,
+            invert_y: false,
+            aim_assist: 0.5,
+            fov: 45.0,
+            shadows: true,
+            resolution: [1280, 720],
+            window_mode: WindowModeSetting::Windowed,
+            volume: 1.0,
+            difficulty: BotDifficulty::default(),
+            screen_shake: 1.0,
+            hit_stop: true,
+            threat_framing: true,
+        }
+    }
+}
+
+impl Settings {
+    fn load() -> Self {
+        let Ok(text) = std::fs::read_to_string(SETTINGS_PATH) else {
+            return Self::default();
+        };
+        toml::from_str(&text).unwrap_or_else(|error| {
+            println!("Ignoring {}: {}", SETTINGS_PATH, error);
+            Self::default()
+        })
+    }
+
+    fn save(&self) {
+        let result = toml::to_string_pretty(self)
+            .map_err(|error| error.to_string())
+            .and_then(|text| {
+                std::fs::write(SETTINGS_PATH, text).map_err(|error| error.to_string())
+            });
+        match result {
+            Ok(()) => println!("Saved settings to {}", SETTINGS_PATH),
+            Err(error) => println!("Couldn't save settings to {}: {}", SETTINGS_PATH, error),
+        }
+    }
+
+    /// Scales raw look input, with y flipped if it's inverted
+    pub fn look_scale(&self) -> Vec2 {
+        let y = if self.invert_y { -1.0 } else { 1.0 };
+        Vec2::new(1.0, y) * self.mouse_sensitivity / DEFAULT_SENSITIVITY
+    }
+}
+
+/// A light that never casts shadows, whatever the shadows setting says.
+#[derive(Component)]
+pub struct Shadowless;
+
+/// Whether the settings window is showing. Local players' keyboard and mouse are ignored while it is.
+#[derive(Resource, Default)]
+pub struct SettingsMenu {
+    pub open: bool,
+}
+
+fn apply_settings(
+    settings: Res<Settings>,
+    mut applied: Local<Option<Settings>>,
+    mut difficulty: ResMut<BotDifficulty>,
+    mut global_volume: ResMut<GlobalVolume>,
+    sink_q: Query<&AudioSink>,
+    mut window_q: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    let previous = applied.replace(settings.clone());
+    *difficulty = settings.difficulty;
+
+    // The global volume only reaches sounds started after it changes. Ones already playing are
+    // scaled by the change, so each keeps its own loudness.
+    let old_volume = global_volume.volume.get();
+    if settings.volume != old_volume {
+        *global_volume = GlobalVolume::new(settings.volume);
+        for sink in sink_q.iter() {
+            if old_volume > 0.0 {
+                sink.set_volume(sink.volume() * settings.volume / old_volume);
+            } else {
+                sink.set_volume(settings.volume);
+            }
+        }
+    }
+
+    // Only when they change, so other settings don't undo the player resizing the window
+    let window_changed = previous.map_or(true, |previous| {
+        previous.resolution != settings.resolution || previous.window_mode != settings.window_mode
+    });
+    if !window_changed {
+        return;
+    }
+    if let Ok(mut window) = window_q.get_single_mut() {
+        let [width, height] = settings.resolution;
+        window.resolution.set(width as f32, height as f32);
+        window.mode = match settings.window_mode {
+            WindowModeSetting::Windowed => WindowMode::Windowed,
+            WindowModeSetting::Borderless => WindowMode::BorderlessFullscreen,
+            WindowModeSetting::Fullscreen => WindowMode::Fullscreen,
+        };
+    }
+}
+
+// Cameras come and go with players, so new ones are caught up too
+fn apply_camera_settings(
+    settings: Res<Settings>,
+    menu: Res<SettingsMenu>,
+    mut camera_q: Query<
+        (
+            Ref<PlayerCamera>,
+            &mut Projection,
+            Option<&mut ThirdPersonCamera>,
+        ),
+        With<Camera>,
+    >,
+) {
+    for (player_camera, mut projection, third_person) in camera_q.iter_mut() {
+        if !settings.is_changed() && !menu.is_changed() && !player_camera.is_added() {
+            continue;
+        }
+        if let Projection::Perspective(perspective) = &mut *projection {
+            perspective.fov = settings.fov.to_radians();
+        }
+        if let Some(mut third_person) = third_person {
+            third_person.sensitivity = settings.look_scale() * DEFAULT_SENSITIVITY;
+            // Let the cursor go so the menu can be used
+            third_person.cursor_lock_active = !menu.open;
+        }
+    }
+}
+
+fn apply_light_settings(
+    settings: Res<Settings>,
+    mut directional_q: Query<&mut DirectionalLight, Without<Shadowless>>,
+    mut point_q: Query<&mut PointLight, Without<Shadowless>>,
+    mut spot_q: Query<&mut SpotLight, Without<Shadowless>>,
+) {
+    for mut light in directional_q.iter_mut() {
+        if settings.is_changed() || light.is_added() {
+            light.shadows_enabled = settings.shadows;
+        }
+    }
+    for mut light in point_q.iter_mut() {
+        if settings.is_changed() || light.is_added() {
+            light.shadows_enabled = settings.shadows;
+        }
+    }
+    for mut light in spot_q.iter_mut() {
+        if settings.is_changed() || light.is_added() {
+            light.shadows_enabled = settings.shadows;
+        }
+    }
+}
+
+fn toggle_settings_menu(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut menu: ResMut<SettingsMenu>,
+    settings: Res<Settings>,
+) {
+    if keys.just_pressed(KeyCode::Escape) {
+        menu.open = !menu.open;
+        if !menu.open {
+            settings.save();
+        }
+    }
+}
+
+fn settings_menu(
+    mut contexts: EguiContexts,
+    mut menu: ResMut<SettingsMenu>,
+    mut settings: ResMut<Settings>,
+) {
+    if !menu.open {
+        return;
+    }
+    let mut edited = settings.clone();
+    let mut open = true;
+    egui::Window::new("Settings")
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.heading("Controls");
+            ui.add(
+                egui::Slider::new(&mut edited.mouse_sensitivity, 0.2..=10.0)
+                    .text("Mouse sensitivity"),
+            );
+            ui.checkbox(&mut edited.invert_y, "Invert Y");
+            ui.add(egui::Slider::new(&mut edited.aim_assist, 0.0..=1.0).text("Aim assist"));
+
+            ui.heading("Graphics");
+            ui.add(egui::Slider::new(&mut edited.fov, 30.0..=110.0).text("Field of view"));
+            ui.checkbox(&mut edited.shadows, "Shadows");
+            egui::ComboBox::from_label("Resolution")
+                .selected_text(format!(
+                    "{} x {}",
+                    edited.resolution[0], edited.resolution[1]
+                ))
+                .show_ui(ui, |ui| {
+                    for resolution in RESOLUTIONS {
+                        let label = format!("{} x {}", resolution[0], resolution[1]);
+                        ui.selectable_value(&mut edited.resolution, resolution, label);
+                    }
+                });
+            egui::ComboBox::from_label("Window mode")
+                .selected_text(edited.window_mode.label())
+                .show_ui(ui, |ui| {
+                    for mode in [
+                        WindowModeSetting::Windowed,
+                        WindowModeSetting::Borderless,
+                        WindowModeSetting::Fullscreen,
+                    ] {
+                        ui.selectable_value(&mut edited.window_mode, mode, mode.label());
+                    }
+                });
+
+            ui.heading("Sound");
+            ui.add(egui::Slider::new(&mut edited.volume, 0.0..=1.0).text("Volume"));
+
+            ui.heading("Accessibility");
+            ui.add(egui::Slider::new(&mut edited.screen_shake, 0.0..=1.0).text("Screen shake"));
+            ui.checkbox(&mut edited.hit_stop, "Hit-stop");
+            ui.checkbox(&mut edited.threat_framing, "Pull back for incoming balls");
+
+            ui.heading("Game");
+            egui::ComboBox::from_label("Bot difficulty")
+                .selected_text(format!("{:?}", edited.difficulty))
+                .show_ui(ui, |ui| {
+                    for difficulty in [
+                        BotDifficulty::Easy,
+                        BotDifficulty::Normal,
+                        BotDifficulty::Hard,
+                    ] {
+                        let label = format!("{:?}", difficulty);
+                        ui.selectable_value(&mut edited.difficulty, difficulty, label);
+                    }
+                });
+        });
+    // Only touch the resource on a real change, so the apply systems don't run every frame
+    if edited != *settings {
+        *settings = edited;
+    }
+    if !open {
+        menu.open = false;
+        settings.save();
+    }
+}
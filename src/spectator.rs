@@ -0,0 +1,272 @@
+//! A spectator camera for watching the game, replays and launcher setups.
+//!
+//! F6 switches to the spectator camera and back. It has three modes:
+//! - 1 flies freely. WASD to move, Q and E to go down and up, shift to go faster, mouse to look.
+//! - 2 orbits whatever was in the middle of the screen. Mouse to go round it, W and S to get closer
+//!   or further away, left click to pick something else.
+//! - 3 follows a ball in play, the same way. Tab moves on to the next ball.
+//!
+//! The player cameras are switched off rather than moved, and put back how they were when the
+//! spectator camera goes away, so play picks up where it left off. Players on the keyboard and
+//! mouse stand still while spectating. The spectator camera runs on real time, so it still moves
+//! during hit-stop.
+
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::ball::Ball;
+use crate::camera::PlayerCamera;
+use crate::input::key_axis;
+use crate::settings::{Settings, SettingsMenu};
+
+const SPECTATOR_CAMERA_ORDER: isize = 100;
+const LOOK_SENSITIVITY: f32 = 0.005;
+const MAX_PITCH: f32 = 1.5;
+// Metres per second
+const FLY_SPEED: f32 = 10.0;
+const FAST_FLY_SCALE: f32 = 4.0;
+const ZOOM_SPEED: f32 = 10.0;
+const ORBIT_DISTANCE: f32 = 10.0;
+const MIN_ORBIT_DISTANCE: f32 = 1.5;
+const MAX_ORBIT_DISTANCE: f32 = 60.0;
+// How far away something can be picked to orbit
+const PICK_RANGE: f32 = 200.0;
+
+pub struct SpectatorPlugin;
+
+impl Plugin for SpectatorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                toggle_spectator,
+                control_spectator.run_if(resource_exists::<Spectator>),
+            )
+                .chain(),
+        );
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SpectatorMode {
+    FreeFly,
+    Orbit,
+    FollowBall,
+}
+
+/// Marks the spectator camera.
+#[derive(Component)]
+pub struct SpectatorCamera;
+
+/// Only there while spectating.
+#[derive(Resource)]
+pub struct Spectator {
+    pub mode: SpectatorMode,
+    /// What the camera is orbiting or following
+    pub subject: Option<Entity>,
+    camera: Entity,
+    yaw: f32,
+    pitch: f32,
+    distance: f32,
+    // Orbited when there's no subject, and kept up with the subject so the camera stays put if it goes
+    orbit_point: Vec3,
+    // Player cameras as they were, to put back afterwards
+    saved: Vec<(Entity, Transform)>,
+}
+
+fn toggle_spectator(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    settings: Res<Settings>,
+    spectator: Option<Res<Spectator>>,
+    mut camera_q: Query<(Entity, &mut Camera, &mut Transform), With<PlayerCamera>>,
+) {
+    if !keys.just_pressed(KeyCode::F6) {
+        return;
+    }
+    match spectator {
+        Some(spectator) => {
+            commands.entity(spectator.camera).despawn_recursive();
+            for (entity, mut camera, mut transform) in camera_q.iter_mut() {
+                camera.is_active = true;
+                if let Some((_, saved)) = spectator.saved.iter().find(|(saved, _)| *saved == entity)
+                {
+                    *transform = *saved;
+                }
+            }
+            commands.remove_resource::<Spectator>();
+            println!("Spectator camera off");
+        }
+        None => {
+            // Start from where the first player's camera is
+            let start = camera_q.iter().next().map_or(
+                Transform::from_xyz(0.0, 10.0, 20.0).looking_at(Vec3::ZERO, Vec3::Y),
+                |(_, _, transform)| *transform,
+            );
+            let saved = camera_q
+                .iter()
+                .map(|(entity, _, transform)| (entity, *transform))
+                .collect();
+            for (_, mut camera, _) in camera_q.iter_mut() {
+                camera.is_active = false;
+            }
+            let camera = commands
+                .spawn((
+                    Camera3dBundle {
+                        camera: Camera {
+                            order: SPECTATOR_CAMERA_ORDER,
+                            ..default()
+                        },
+                        projection: PerspectiveProjection {
+                            fov: settings.fov.to_radians(),
+                            ..default()
+                        }
+                        .into(),
+                        transform: start,
+                        ..default()
+                    },
+                    SpectatorCamera,
+                    Name::new("Spectator camera"),
+                ))
+                .id();
+            let (yaw, pitch, _) = start.rotation.to_euler(EulerRot::YXZ);
+            commands.insert_resource(Spectator {
+                mode: SpectatorMode::FreeFly,
+                subject: None,
+                camera,
+                yaw,
+                pitch,
+                distance: ORBIT_DISTANCE,
+                orbit_point: start.translation + start.forward() * ORBIT_DISTANCE,
+                saved,
+            });
+            println!("Spectator camera on: 1 free-fly, 2 orbit, 3 follow a ball, F6 to go back");
+        }
+    }
+}
+
+// Orbits whatever's in the middle of the screen, or the empty space there if there's nothing
+fn pick_subject(spectator: &mut Spectator, transform: &Transform, rapier_context: &RapierContext) {
+    let origin = transform.translation;
+    let forward = *transform.forward();
+    let hit = rapier_context.cast_ray(origin, forward, PICK_RANGE, true, QueryFilter::new());
+    let distance = hit.map_or(ORBIT_DISTANCE, |(_, toi)| toi);
+    spectator.subject = hit.map(|(entity, _)| entity);
+    spectator.orbit_point = origin + forward * distance;
+    spectator.distance = distance.clamp(MIN_ORBIT_DISTANCE, MAX_ORBIT_DISTANCE);
+}
+
+fn next_ball(
+    current: Option<Entity>,
+    ball_q: &Query<Entity, (With<Ball>, Without<RigidBodyDisabled>)>,
+) -> Option<Entity> {
+    let mut balls: Vec<Entity> = ball_q.iter().collect();
+    balls.sort();
+    balls
+        .iter()
+        .find(|&&ball| current.map_or(true, |current| ball > current))
+        .or(balls.first())
+        .copied()
+}
+
+fn control_spectator(
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    real_time: Res<Time<Real>>,
+    settings: Res<Settings>,
+    menu: Res<SettingsMenu>,
+    rapier_context: Res<RapierContext>,
+    mut spectator: ResMut<Spectator>,
+    mut camera_q: Query<&mut Transform, With<SpectatorCamera>>,
+    subject_q: Query<&GlobalTransform>,
+    ball_q: Query<Entity, (With<Ball>, Without<RigidBodyDisabled>)>,
+    name_q: Query<&Name>,
+) {
+    let mouse_delta: Vec2 = mouse_motion.read().map(|motion| motion.delta).sum();
+    if menu.open {
+        return;
+    }
+    let Ok(mut transform) = camera_q.get_mut(spectator.camera) else {
+        return;
+    };
+
+    let previous = (spectator.mode, spectator.subject);
+    if keys.just_pressed(KeyCode::Digit1) {
+        spectator.mode = SpectatorMode::FreeFly;
+        spectator.subject = None;
+    }
+    let picking =
+        spectator.mode == SpectatorMode::Orbit && mouse_buttons.just_pressed(MouseButton::Left);
+    if keys.just_pressed(KeyCode::Digit2) || picking {
+        spectator.mode = SpectatorMode::Orbit;
+        pick_subject(&mut spectator, &transform, &rapier_context);
+    }
+    let following = spectator.mode == SpectatorMode::FollowBall;
+    // Balls go back to the pool once they're done, so move on to another one
+    let lost_ball = following
+        && spectator
+            .subject
+            .map_or(true, |ball| !ball_q.contains(ball));
+    if keys.just_pressed(KeyCode::Digit3)
+        || (following && keys.just_pressed(KeyCode::Tab))
+        || lost_ball
+    {
+        spectator.mode = SpectatorMode::FollowBall;
+        spectator.subject = next_ball(spectator.subject, &ball_q);
+        if !following {
+            spectator.distance = ORBIT_DISTANCE;
+        }
+    }
+    if (spectator.mode, spectator.subject) != previous {
+        let subject = spectator.subject.map(|subject| {
+            name_q
+                .get(subject)
+                .map_or(format!("{:?}", subject), |name| name.to_string())
+        });
+        match subject {
+            Some(subject) => println!("Spectating {:?}: {}", spectator.mode, subject),
+            None => println!("Spectating {:?}", spectator.mode),
+        }
+    }
+
+    let look = mouse_delta * LOOK_SENSITIVITY * settings.look_scale();
+    spectator.yaw -= look.x;
+    spectator.pitch = (spectator.pitch - look.y).clamp(-MAX_PITCH, MAX_PITCH);
+    let rotation = Quat::from_euler(EulerRot::YXZ, spectator.yaw, spectator.pitch, 0.0);
+    let delta_time = real_time.delta_seconds();
+
+    match spectator.mode {
+        SpectatorMode::FreeFly => {
+            let mut speed = FLY_SPEED;
+            if keys.pressed(KeyCode::ShiftLeft) {
+                speed *= FAST_FLY_SCALE;
+            }
+            let along = rotation
+                * Vec3::new(
+                    key_axis(&keys, KeyCode::KeyA, KeyCode::KeyD),
+                    0.0,
+                    -key_axis(&keys, KeyCode::KeyS, KeyCode::KeyW),
+                );
+            let up = Vec3::Y * key_axis(&keys, KeyCode::KeyQ, KeyCode::KeyE);
+            transform.translation += (along + up).normalize_or_zero() * speed * delta_time;
+            transform.rotation = rotation;
+        }
+        SpectatorMode::Orbit | SpectatorMode::FollowBall => {
+            let zoom = key_axis(&keys, KeyCode::KeyS, KeyCode::KeyW) * ZOOM_SPEED * delta_time;
+            spectator.distance =
+                (spectator.distance - zoom).clamp(MIN_ORBIT_DISTANCE, MAX_ORBIT_DISTANCE);
+            if let Some(subject) = spectator
+                .subject
+                .and_then(|subject| subject_q.get(subject).ok())
+            {
+                spectator.orbit_point = subject.translation();
+            }
+            let focus = spectator.orbit_point;
+            *transform =
+                Transform::from_translation(focus + rotation * Vec3::Z * spectator.distance)
+                    .looking_at(focus, Vec3::Y);
+        }
+    }
+}
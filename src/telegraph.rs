@@ -0,0 +1,241 @@
+//! Launchers warn before they fire.
+//!
+//! For its lead time before firing, a launcher's orb glows in the colour of the ball it's about to
+//! fire, pulsing faster as the shot gets closer, with a line drawn along the path the ball will
+//! take, and a light on the orb shows up the launcher even in a night arena. Audio plays a rising
+//! whine as the wind-up starts. The lead time is `launcher_lead_secs` in the arena's file, and a
+//! hazard launcher can set its own.
+//!
+//! The timed launcher is driven by `BallSpawnConfig`, and hazard launchers wind up themselves once
+//! triggered. Network clients don't launch balls, so they don't see the warnings either.
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use std::f32::consts::TAU;
+
+use crate::ball::{BallKind, BallSpawnConfig, LAUNCH_POSITION, LAUNCH_VELOCITY};
+use crate::settings::Shadowless;
+use crate::trajectory::{effective_restitution, predict_path};
+use crate::world::SimulationSet;
+
+// How far ahead the aim line follows the ball
+const AIM_LINE_SECS: f32 = 1.0;
+// Emissive strength at the moment of firing
+const MAX_GLOW: f32 = 30.0;
+// The orb's light at the moment of firing, and how far it reaches
+const MAX_LIGHT_INTENSITY: f32 = 400_000.0;
+const LIGHT_RANGE: f32 = 12.0;
+// Pulses per second as the wind-up starts, and as it ends
+const SLOW_PULSE_HZ: f32 = 2.0;
+const FAST_PULSE_HZ: f32 = 10.0;
+
+pub struct TelegraphPlugin;
+
+impl Plugin for TelegraphPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, spawn_timed_launcher).add_systems(
+            Update,
+            (follow_ball_spawner, add_glows, update_glows, draw_aim_lines)
+                .chain()
+                .after(SimulationSet),
+        );
+    }
+}
+
+/// Something that fires balls, and warns before it does.
+#[derive(Component, Clone, Debug)]
+pub struct Telegraph {
+    /// Seconds left before it fires, while it's winding up
+    pub remaining_secs: Option<f32>,
+    pub lead_secs: f32,
+    /// What it fires next
+    pub kind: BallKind,
+    /// Where balls leave it, in world space
+    pub muzzle: Vec3,
+    /// How fast balls leave it, speed scale included
+    pub velocity: Vec3,
+}
+
+impl Telegraph {
+    /// How far through the wind-up it is, from 0 as it starts to 1 as it fires.
+    pub fn progress(&self) -> Option<f32> {
+        self.remaining_secs.map(|remaining| {
+            if self.lead_secs <= 0.0 {
+                1.0
+            } else {
+                (1.0 - remaining / self.lead_secs).clamp(0.0, 1.0)
+            }
+        })
+    }
+}
+
+/// Marks the launcher `BallSpawnConfig` fires from.
+#[derive(Component)]
+struct TimedLauncher;
+
+/// The glowing orb on a launcher.
+#[derive(Component)]
+struct TelegraphGlow {
+    launcher: Entity,
+    lit: bool,
+}
+
+/// The light on a launcher's orb.
+#[derive(Component)]
+struct TelegraphLight;
+
+fn spawn_timed_launcher(mut commands: Commands) {
+    commands.spawn((
+        TransformBundle::from_transform(Transform::from_translation(LAUNCH_POSITION)),
+        TimedLauncher,
+        Telegraph {
+            remaining_secs: None,
+            lead_secs: 0.0,
+            kind: BallKind::default(),
+            muzzle: LAUNCH_POSITION,
+            velocity: LAUNCH_VELOCITY,
+        },
+        Name::new("TimedLauncher"),
+    ));
+}
+
+fn follow_ball_spawner(
+    config: Res<BallSpawnConfig>,
+    mut launcher_q: Query<&mut Telegraph, With<TimedLauncher>>,
+) {
+    for mut telegraph in launcher_q.iter_mut() {
+        telegraph.lead_secs = config.lead_secs;
+        telegraph.remaining_secs = config
+            .next_kind
+            .filter(|_| !config.timer.paused())
+            .map(|_| config.timer.remaining_secs());
+        if let Some(kind) = config.next_kind {
+            telegraph.kind = kind;
+            telegraph.velocity = LAUNCH_VELOCITY * kind.speed_scale();
+        }
+    }
+}
+
+fn add_glows(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    launcher_q: Query<(Entity, &Telegraph), Added<Telegraph>>,
+) {
+    for (launcher, telegraph) in launcher_q.iter() {
+        // Each orb has a material of its own, since they glow separately
+        commands
+            .spawn((
+                PbrBundle {
+                    mesh: meshes.add(Sphere::new(0.5).mesh().ico(2).unwrap()),
+                    material: materials.add(StandardMaterial {
+                        base_color: Srgba::hex(telegraph.kind.color()).unwrap().into(),
+                        ..default()
+                    }),
+                    transform: Transform::from_translation(telegraph.muzzle)
+                        .with_scale(Vec3::splat(telegraph.kind.radius())),
+                    ..default()
+                },
+                TelegraphGlow {
+                    launcher,
+                    lit: true,
+                },
+                Name::new("TelegraphGlow"),
+            ))
+            .with_children(|parent| {
+                parent.spawn((
+                    PointLightBundle {
+                        point_light: PointLight {
+                            intensity: 0.0,
+                            range: LIGHT_RANGE,
+                            shadows_enabled: false,
+                            ..default()
+                        },
+                        ..default()
+                    },
+                    TelegraphLight,
+                    // A shadow pass per launcher would cost every frame, lit or not
+                    Shadowless,
+                ));
+            });
+    }
+}
+
+fn update_glows(
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    launcher_q: Query<&Telegraph>,
+    mut glow_q: Query<(
+        &mut TelegraphGlow,
+        &mut Transform,
+        &Handle<StandardMaterial>,
+        &Children,
+    )>,
+    mut light_q: Query<&mut PointLight, With<TelegraphLight>>,
+) {
+    for (mut glow, mut transform, material, children) in glow_q.iter_mut() {
+        let Ok(telegraph) = launcher_q.get(glow.launcher) else {
+            continue;
+        };
+        let progress = telegraph.progress();
+        // Only touch the material while it's changing, so idle orbs aren't uploaded every frame
+        if progress.is_none() && !glow.lit {
+            continue;
+        }
+        glow.lit = progress.is_some();
+        let Some(material) = materials.get_mut(material) else {
+            continue;
+        };
+        let color = Color::from(Srgba::hex(telegraph.kind.color()).unwrap());
+        let brightness = match progress {
+            Some(progress) => {
+                // Integrating the rising pulse rate keeps the pulses smooth as they speed up
+                let elapsed = progress * telegraph.lead_secs;
+                let phase = TAU
+                    * (SLOW_PULSE_HZ * elapsed
+                        + (FAST_PULSE_HZ - SLOW_PULSE_HZ) * elapsed * progress / 2.0);
+                let pulse = 0.6 + 0.4 * phase.cos();
+                progress * pulse
+            }
+            None => 0.0,
+        };
+        material.base_color = color;
+        material.emissive = color.to_linear() * MAX_GLOW * brightness;
+        let mut lights = light_q.iter_many_mut(children);
+        while let Some(mut light) = lights.fetch_next() {
+            light.color = color;
+            light.intensity = MAX_LIGHT_INTENSITY * brightness;
+        }
+        transform.scale = Vec3::splat(telegraph.kind.radius());
+    }
+}
+
+fn draw_aim_lines(
+    mut gizmos: Gizmos,
+    rapier_context: Res<RapierContext>,
+    rapier_config: Res<RapierConfiguration>,
+    launcher_q: Query<&Telegraph>,
+) {
+    for telegraph in launcher_q.iter() {
+        let Some(progress) = telegraph.progress() else {
+            continue;
+        };
+        let kind = telegraph.kind;
+        let path = predict_path(
+            &rapier_context,
+            rapier_config.gravity,
+            telegraph.muzzle,
+            telegraph.velocity,
+            kind.radius(),
+            effective_restitution(&kind.restitution()),
+            AIM_LINE_SECS,
+        );
+        let color = Srgba::hex(kind.color())
+            .unwrap()
+            .with_alpha(0.3 + 0.7 * progress);
+        gizmos.linestrip(path.points, color);
+        if let Some((point, normal)) = path.landing {
+            let normal = Dir3::new(normal).unwrap_or(Dir3::Y);
+            gizmos.circle(point + *normal * 0.02, normal, kind.radius(), color);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::ball::{Ball, BallKind};
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+const PREDICTION_STEP_SECS: f32 = 1.0 / 60.0;
+// Bounces lose too much speed to be worth drawing after a few
+const MAX_PREDICTED_BOUNCES: usize = 4;
+
+pub struct TrajectoryPlugin;
+
+impl Plugin for TrajectoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TrajectoryPreview>().add_systems(
+            Update,
+            (
+                toggle_trajectory_preview,
+                draw_ball_trajectories.run_if(|preview: Res<TrajectoryPreview>| preview.enabled),
+            ),
+        );
+    }
+}
+
+/// Draws where each live ball is going to go. Toggled with P.
+/// Useful as an accessibility assist and for tuning launchers.
+#[derive(Resource)]
+pub struct TrajectoryPreview {
+    pub enabled: bool,
+    /// How far ahead to simulate
+    pub horizon_secs: f32,
+}
+
+impl Default for TrajectoryPreview {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            horizon_secs: 1.5,
+        }
+    }
+}
+
+pub struct PredictedPath {
+    pub points: Vec<Vec3>,
+    /// Where the ball first comes down on something it could roll on, and that surface's normal
+    pub landing: Option<(Vec3, Vec3)>,
+}
+
+// The arena's static colliders have the default zero restitution, so this is what a bounce keeps
+pub(crate) fn effective_restitution(restitution: &Restitution) -> f32 {
+    match restitution.combine_rule {
+        CoefficientCombineRule::Average => restitution.coefficient / 2.0,
+        CoefficientCombineRule::Max => restitution.coefficient,
+        CoefficientCombineRule::Min | CoefficientCombineRule::Multiply => 0.0,
+    }
+}
+
+/// Steps a ball forward under gravity, bouncing it off fixed and kinematic colliders.
+/// Moving platforms are treated as if they stay where they are now.
+pub fn predict_path(
+    rapier_context: &RapierContext,
+    gravity: Vec3,
+    start: Vec3,
+    mut velocity: Vec3,
+    radius: f32,
+    restitution: f32,
+    horizon_secs: f32,
+) -> PredictedPath {
+    let filter = QueryFilter::new().exclude_dynamic().exclude_sensors();
+    let mut position = start;
+    let mut points = vec![position];
+    let mut landing = None;
+    let mut bounces = 0;
+    let mut elapsed = 0.0;
+
+    while elapsed < horizon_secs && bounces <= MAX_PREDICTED_BOUNCES {
+        velocity += gravity * PREDICTION_STEP_SECS;
+        let step = velocity * PREDICTION_STEP_SECS;
+        let distance = step.length();
+        elapsed += PREDICTION_STEP_SECS;
+        if distance <= f32::EPSILON {
+            continue;
+        }
+        let direction = step / distance;
+
+        // Reach ahead by the radius so the surface of the ball, not its centre, makes contact
+        match rapier_context.cast_ray_and_get_normal(
+            position,
+            direction,
+            distance + radius,
+            true,
+            filter,
+        ) {
+            Some((_entity, hit)) => {
+                position = hit.point + hit.normal * radius;
+                velocity -= (1.0 + restitution) * velocity.dot(hit.normal) * hit.normal;
+                bounces += 1;
+                if landing.is_none() && hit.normal.y > 0.7 {
+                    landing = Some((hit.point, hit.normal));
+                }
+            }
+            None => position += step,
+        }
+        points.push(position);
+    }
+
+    PredictedPath { points, landing }
+}
+
+fn toggle_trajectory_preview(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut preview: ResMut<TrajectoryPreview>,
+) {
+    if keys.just_pressed(KeyCode::KeyP) {
+        preview.enabled = !preview.enabled;
+    }
+}
+
+fn draw_ball_trajectories(
+    mut gizmos: Gizmos,
+    preview: Res<TrajectoryPreview>,
+    rapier_context: Res<RapierContext>,
+    rapier_config: Res<RapierConfiguration>,
+    ball_q: Query<
+        (&Transform, &Velocity, &BallKind, &Restitution),
+        (With<Ball>, Without<RigidBodyDisabled>),
+    >,
+) {
+    for (transform, velocity, kind, restitution) in ball_q.iter() {
+        let path = predict_path(
+            &rapier_context,
+            rapier_config.gravity,
+            transform.translation,
+            velocity.linvel,
+            kind.radius(),
+            effective_restitution(restitution),
+            preview.horizon_secs,
+        );
+        gizmos.linestrip(path.points, Color::WHITE);
+        if let Some((point, normal)) = path.landing {
+            let normal = Dir3::new(normal).unwrap_or(Dir3::Y);
+            gizmos.circle(point + *normal * 0.02, normal, kind.radius(), Color::WHITE);
+            gizmos.circle(
+                point + *normal * 0.02,
+                normal,
+                kind.radius() * 0.4,
+                Color::WHITE,
+            );
+        }
+    }
+}
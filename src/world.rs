@@ -1,62 +1,30 @@
+use bevy::ecs::schedule::SystemConfigs;
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 use std::f64::consts::TAU;
-use std::time::Duration;
+
+use crate::nav::NavObstacle;
 
 pub struct WorldPlugin;
 
 #[derive(Component)]
 pub struct Ground;
 
-impl Plugin for WorldPlugin {
-    fn build(&self, app: &mut App) {
-        app.add_systems(
-            Startup,
-            (
-                spawn_light,
-                spawn_directional_light,
-                spawn_floor,
-                spawn_objects,
-                setup_floor,
-                // spawn_ball,
-                setup_ball_spawning,
-            ),
-        )
-        .add_systems(Update, (move_cubes, spawn_ball, despawn_ball));
-    }
-}
+/// Systems that move the game on. They run every frame in `Update`, unless a rollback session
+/// is stepping them itself at a fixed tick.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SimulationSet;
 
-fn spawn_light(mut commands: Commands) {
-    let light = (
-        PointLightBundle {
-            point_light: PointLight {
-                intensity: 0.0,
-                ..default()
-            },
-            transform: Transform::from_xyz(0.0, 5.0, 0.0),
-            ..default()
-        },
-        Name::new("PointLight"),
-    );
-    commands.spawn(light);
+/// World systems that move the game on. A rollback session steps these itself.
+pub(crate) fn simulation_systems() -> SystemConfigs {
+    move_cubes.into_configs()
 }
 
-fn spawn_directional_light(mut commands: Commands) {
-    let light = (
-        DirectionalLightBundle {
-            transform: Transform::from_xyz(50.0, 50.0, 50.0).looking_at(Vec3::ZERO, Vec3::Y),
-            directional_light: DirectionalLight {
-                illuminance: 1_000.,
-                shadows_enabled: true,
-                color: Srgba::rgba_u8(137, 123, 43, 255).into(),
-
-                ..default()
-            },
-            ..default()
-        },
-        Name::new("DirectionalLight"),
-    );
-    commands.spawn(light);
+impl Plugin for WorldPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, (spawn_floor, spawn_objects, setup_floor))
+            .add_systems(Update, simulation_systems().in_set(SimulationSet));
+    }
 }
 
 fn spawn_floor(
@@ -91,10 +59,13 @@ fn spawn_floor(
     commands.spawn(floor);
 }
 
-#[derive(Component)]
+#[derive(Component, Reflect, Clone, Copy, Default, Debug)]
 
 // we need to add the oscillator here so that player movement can query it
-pub struct Cubeovator;
+pub struct Cubeovator {
+    /// How far through its bob up and down, in radians
+    pub phase: f32,
+}
 
 #[derive(Bundle)]
 struct GroundBundle(Collider, Ground, Transform);
@@ -109,7 +80,14 @@ fn spawn_objects(
                            xyz: (f32, f32, f32),
                            name: String|
      -> (
-        (PbrBundle, Name, Collider, RigidBody, Cubeovator),
+        (
+            PbrBundle,
+            Name,
+            Collider,
+            RigidBody,
+            Cubeovator,
+            NavObstacle,
+        ),
         GroundBundle,
     ) {
         let cube = (
@@ -127,7 +105,10 @@ fn spawn_objects(
             Name::new(name),
             Collider::cuboid(hdim_xyz.0 / 2.0, hdim_xyz.1 / 2.0, hdim_xyz.2 / 2.0),
             RigidBody::KinematicPositionBased,
-            Cubeovator,
+            Cubeovator::default(),
+            NavObstacle {
+                half_extents: Vec3::new(hdim_xyz.0, hdim_xyz.1, hdim_xyz.2) / 2.0,
+            },
             // Ground,
         );
         let cube_ground = GroundBundle(
@@ -156,74 +137,11 @@ fn setup_floor(mut commands: Commands) {
         .insert(TransformBundle::from(Transform::from_xyz(0.0, -2.0, 0.0)));
 }
 
-fn spawn_ball(mut commands: Commands, time: Res<Time>, mut config: ResMut<BallSpawnConfig>) {
-    config.timer.tick(time.delta());
-    if config.timer.finished() {
-        commands
-            .spawn(RigidBody::Dynamic)
-            .insert(Ball)
-            .insert(BallLifetime {
-                timer: Timer::new(Duration::from_secs(5), TimerMode::Repeating),
-            })
-            .insert(ActiveCollisionTypes::default() | ActiveCollisionTypes::KINEMATIC_STATIC)
-            .insert(Collider::ball(0.5))
-            .insert(AdditionalMassProperties::Mass(1000.2))
-            .insert(ColliderMassProperties::Density(1000.0))
-            .insert(Restitution::coefficient(0.9))
-            .insert(TransformBundle::from(Transform::from_xyz(-4.0, 1.0, 0.0)))
-            .insert(Friction {
-                coefficient: 3.00,
-                combine_rule: CoefficientCombineRule::Min,
-            })
-            .insert(Velocity {
-                linvel: Vec3::new(30.0, 1.0, 0.0),
-                angvel: Vec3::new(0.2, 0.4, 0.8),
-            });
-    }
-}
-
-fn despawn_ball(
-    mut commands: Commands,
-    mut q: Query<(Entity, &mut BallLifetime)>,
-    time: Res<Time>,
-) {
-    for (entity, mut fuse_timer) in q.iter_mut() {
-        // timers gotta be ticked, to work
-        fuse_timer.timer.tick(time.delta());
-
-        // if it finished, despawn the ball
-        if fuse_timer.timer.finished() {
-            commands.entity(entity).despawn();
-        }
-    }
-}
-
-fn move_cubes(time: Res<Time>, mut cube_q: Query<&mut Transform, With<Cubeovator>>) {
-    cube_q.iter_mut().for_each(|mut transform| {
-        let oscillator = (time.elapsed_seconds() % (TAU as f32)).sin();
+fn move_cubes(time: Res<Time>, mut cube_q: Query<(&mut Transform, &mut Cubeovator)>) {
+    cube_q.iter_mut().for_each(|(mut transform, mut cube)| {
+        cube.phase = (cube.phase + time.delta_seconds()) % (TAU as f32);
+        let oscillator = cube.phase.sin();
 
         transform.translation.y += oscillator / 6.0;
     });
 }
-
-#[derive(Resource)]
-struct BallSpawnConfig {
-    /// How often to spawn a new ball? (repeating timer)
-    timer: Timer,
-}
-
-fn setup_ball_spawning(mut commands: Commands) {
-    commands.insert_resource(BallSpawnConfig {
-        // create the repeating timer
-        timer: Timer::new(Duration::from_secs(3), TimerMode::Repeating),
-    })
-}
-
-#[derive(Component)]
-struct BallLifetime {
-    /// track when the ball should despawn (non-repeating timer)
-    timer: Timer,
-}
-
-#[derive(Component)]
-pub struct Ball;